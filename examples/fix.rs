@@ -19,18 +19,20 @@ fn main() -> Result<(), Whatever> {
     let earth_fix_dat = File::open(earth_fix_dat)
         .whatever_context("Could not open earth_fix.dat!")?;
     let fixes = xputils::navdata::fix::parse_file(earth_fix_dat)
-        .whatever_context("Could not parse earth_nav.dat!")?;
+        .whatever_context("Could not parse earth_fix.dat!")?;
 
     println!("\n\nMetadata: {:#?}\n\n", fixes.header());
+    // Rows are parsed as they are read, so only as much of the file as needed is read.
     for fix in fixes
-        .entries()
-        .iter()
         .filter(|fix| {
-            !matches!(fix.func, FixFunction::Unspecified)
-                && !matches!(fix.typ, FixType::Unspecified)
+            fix.as_ref().map_or(true, |fix| {
+                !matches!(fix.func, FixFunction::Unspecified)
+                    && !matches!(fix.typ, FixType::Unspecified)
+            })
         })
         .take(20)
     {
+        let fix = fix.whatever_context("Could not parse a fix!")?;
         println!("\nFix: {fix:#?}");
     }
 
@@ -18,12 +18,12 @@ fn main() -> Result<(), Whatever> {
     println!("File path: {}", earth_nav_dat.display());
     let earth_nav_dat = File::open(earth_nav_dat)
         .whatever_context("Could not open earth_nav.dat!")?;
-    let navaids = xputils::navdata::nav::parse_file(earth_nav_dat)
+    let (header, navaids) = xputils::navdata::nav::parse_file(earth_nav_dat)
+        .and_then(|rows| rows.collect_all())
         .whatever_context("Could not parse earth_nav.dat!")?;
 
-    println!("\n\nMetadata: {:#?}\n\n", navaids.header());
+    println!("\n\nMetadata: {header:#?}\n\n");
     for vor in navaids
-        .entries()
         .iter()
         .filter(|navaid| matches!(navaid.type_data, TypeSpecificData::Vor { .. }))
         .take(5)
@@ -32,7 +32,6 @@ fn main() -> Result<(), Whatever> {
     }
 
     for ndb in navaids
-        .entries()
         .iter()
         .filter(|navaid| matches!(navaid.type_data, TypeSpecificData::Ndb { .. }))
         .take(5)
@@ -41,7 +40,6 @@ fn main() -> Result<(), Whatever> {
     }
 
     for navaid in navaids
-        .entries()
         .iter()
         .filter(|navaid| {
             matches!(navaid.type_data, TypeSpecificData::ThresholdPoint { .. })
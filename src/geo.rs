@@ -0,0 +1,384 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Geodesy on the WGS84 ellipsoid, and on a spherical earth where that is good enough.
+//!
+//! Latitudes, longitudes, and bearings are in degrees, with bearings relative to true
+//! north. Distances are in nautical miles.
+
+pub mod wmm;
+
+use std::f64::consts::PI;
+
+/// Semi-major axis of the WGS84 ellipsoid, in metres.
+pub const WGS84_A: f64 = 6_378_137f64;
+/// Flattening of the WGS84 ellipsoid.
+pub const WGS84_F: f64 = 1f64 / 298.257_223_563;
+/// Semi-minor axis of the WGS84 ellipsoid, in metres.
+pub const WGS84_B: f64 = WGS84_A * (1f64 - WGS84_F);
+/// Mean radius of the earth, in nautical miles.
+pub const EARTH_RADIUS_NM: f64 = 3440.065;
+/// Metres in a nautical mile.
+pub const METRES_PER_NM: f64 = 1852f64;
+
+/// Iterations after which Vincenty's formulae are taken not to converge.
+const MAX_ITERATIONS: usize = 200;
+const CONVERGENCE: f64 = 1e-12;
+
+/// Great-circle distance between two positions on a spherical earth, in nautical
+/// miles.
+#[must_use]
+pub fn great_circle_distance_nm(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2f64).sin().powi(2)
+        + lat1.cos() * lat2.cos() * (dlon / 2f64).sin().powi(2);
+    2f64 * EARTH_RADIUS_NM * a.sqrt().min(1f64).asin()
+}
+
+/// Normalize a bearing into `[0, 360)`.
+fn normalize_bearing(bearing: f64) -> f64 {
+    let bearing = bearing.rem_euclid(360f64);
+    // rem_euclid can round up to exactly 360 for tiny negative inputs.
+    if bearing >= 360f64 {
+        0f64
+    } else {
+        bearing
+    }
+}
+
+/// Normalize a longitude into `[-180, 180)`.
+fn normalize_lon(lon: f64) -> f64 {
+    (lon + 180f64).rem_euclid(360f64) - 180f64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A position on the earth.
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The solution of the inverse geodesic problem between two points.
+pub struct Inverse {
+    pub distance_nm: f64,
+    /// The bearing at the first point, towards the second.
+    pub initial_bearing: f64,
+    /// The bearing at the second point, continuing away from the first.
+    pub final_bearing: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The solution of the direct geodesic problem from a point.
+pub struct Direct {
+    pub point: GeoPoint,
+    /// The bearing at the destination, continuing along the geodesic.
+    pub final_bearing: f64,
+}
+
+impl GeoPoint {
+    #[must_use]
+    pub const fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    #[must_use]
+    /// The distance and bearings along the WGS84 geodesic from this point to `other`,
+    /// by Vincenty's formulae.
+    ///
+    /// Vincenty's formulae do not converge for some nearly antipodal points. For those,
+    /// the spherical solution is given instead, which is within about 0.5% of the
+    /// ellipsoidal one.
+    pub fn inverse(self, other: GeoPoint) -> Inverse {
+        vincenty_inverse(self, other).unwrap_or_else(|| Inverse {
+            distance_nm: self.great_circle_distance_nm(other),
+            initial_bearing: self.great_circle_bearing(other),
+            final_bearing: normalize_bearing(
+                other.great_circle_bearing(self) + 180f64,
+            ),
+        })
+    }
+
+    #[must_use]
+    /// The distance along the WGS84 geodesic from this point to `other`.
+    pub fn distance_nm(self, other: GeoPoint) -> f64 {
+        self.inverse(other).distance_nm
+    }
+
+    #[must_use]
+    /// The point `distance_nm` from this one along the WGS84 geodesic leaving it on
+    /// `bearing`, by Vincenty's formulae.
+    pub fn direct(self, bearing: f64, distance_nm: f64) -> Direct {
+        let metres = distance_nm * METRES_PER_NM;
+        let alpha1 = bearing.to_radians();
+        let (sin_alpha1, cos_alpha1) = alpha1.sin_cos();
+        let tan_u1 = (1f64 - WGS84_F) * self.lat.to_radians().tan();
+        let cos_u1 = 1f64 / (1f64 + tan_u1 * tan_u1).sqrt();
+        let sin_u1 = tan_u1 * cos_u1;
+        let sigma1 = tan_u1.atan2(cos_alpha1);
+        let sin_alpha = cos_u1 * sin_alpha1;
+        let cos_sq_alpha = 1f64 - sin_alpha * sin_alpha;
+        let (big_a, big_b) = vincenty_a_b(cos_sq_alpha);
+
+        let mut sigma = metres / (WGS84_B * big_a);
+        let (mut sin_sigma, mut cos_sigma, mut cos_2sigma_m);
+        let mut iterations = 0;
+        loop {
+            cos_2sigma_m = (2f64 * sigma1 + sigma).cos();
+            (sin_sigma, cos_sigma) = sigma.sin_cos();
+            let delta_sigma =
+                vincenty_delta_sigma(big_b, sin_sigma, cos_sigma, cos_2sigma_m);
+            let next_sigma = metres / (WGS84_B * big_a) + delta_sigma;
+            let converged = (next_sigma - sigma).abs() < CONVERGENCE;
+            sigma = next_sigma;
+            iterations += 1;
+            if converged || iterations >= MAX_ITERATIONS {
+                break;
+            }
+        }
+        (sin_sigma, cos_sigma) = sigma.sin_cos();
+        cos_2sigma_m = (2f64 * sigma1 + sigma).cos();
+
+        let x = sin_u1 * sin_sigma - cos_u1 * cos_sigma * cos_alpha1;
+        let lat2 = (sin_u1 * cos_sigma + cos_u1 * sin_sigma * cos_alpha1)
+            .atan2((1f64 - WGS84_F) * sin_alpha.hypot(x));
+        let lambda = (sin_sigma * sin_alpha1)
+            .atan2(cos_u1 * cos_sigma - sin_u1 * sin_sigma * cos_alpha1);
+        let c = WGS84_F / 16f64
+            * cos_sq_alpha
+            * (4f64 + WGS84_F * (4f64 - 3f64 * cos_sq_alpha));
+        let big_l = lambda
+            - (1f64 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m
+                            + c * cos_sigma
+                                * (-1f64 + 2f64 * cos_2sigma_m * cos_2sigma_m)));
+        Direct {
+            point: GeoPoint {
+                lat: lat2.to_degrees(),
+                lon: normalize_lon(self.lon + big_l.to_degrees()),
+            },
+            final_bearing: normalize_bearing(sin_alpha.atan2(-x).to_degrees()),
+        }
+    }
+
+    #[must_use]
+    /// The great-circle distance from this point to `other`, on a spherical earth.
+    pub fn great_circle_distance_nm(self, other: GeoPoint) -> f64 {
+        great_circle_distance_nm(self.lat, self.lon, other.lat, other.lon)
+    }
+
+    #[must_use]
+    /// The initial bearing of the great circle from this point to `other`, on a
+    /// spherical earth.
+    pub fn great_circle_bearing(self, other: GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlon = (other.lon - self.lon).to_radians();
+        let y = dlon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+        normalize_bearing(y.atan2(x).to_degrees())
+    }
+
+    #[must_use]
+    /// The point `fraction` of the way along the great circle from this point to
+    /// `other`, on a spherical earth. `fraction` may be outside of `[0, 1]`, to
+    /// extend the great circle.
+    pub fn intermediate(self, other: GeoPoint, fraction: f64) -> GeoPoint {
+        let delta = self.great_circle_distance_nm(other) / EARTH_RADIUS_NM;
+        if delta == 0f64 {
+            return self;
+        }
+        let (lat1, lon1) = (self.lat.to_radians(), self.lon.to_radians());
+        let (lat2, lon2) = (other.lat.to_radians(), other.lon.to_radians());
+        let from_weight = ((1f64 - fraction) * delta).sin() / delta.sin();
+        let to_weight = (fraction * delta).sin() / delta.sin();
+        let x = from_weight * lat1.cos() * lon1.cos()
+            + to_weight * lat2.cos() * lon2.cos();
+        let y = from_weight * lat1.cos() * lon1.sin()
+            + to_weight * lat2.cos() * lon2.sin();
+        let z = from_weight * lat1.sin() + to_weight * lat2.sin();
+        GeoPoint {
+            lat: z.atan2(x.hypot(y)).to_degrees(),
+            lon: normalize_lon(y.atan2(x).to_degrees()),
+        }
+    }
+
+    #[must_use]
+    /// The distance of this point from the great circle from `start` to `end`, on a
+    /// spherical earth. Positive if the point is to the right of the track, and
+    /// negative if it is to the left.
+    pub fn cross_track_nm(self, start: GeoPoint, end: GeoPoint) -> f64 {
+        let delta13 = start.great_circle_distance_nm(self) / EARTH_RADIUS_NM;
+        let theta13 = start.great_circle_bearing(self).to_radians();
+        let theta12 = start.great_circle_bearing(end).to_radians();
+        (delta13.sin() * (theta13 - theta12).sin()).asin() * EARTH_RADIUS_NM
+    }
+
+    #[must_use]
+    /// The distance from `start` along the great circle from `start` to `end`, to the
+    /// point on it closest to this point, on a spherical earth. Negative if that point
+    /// is behind `start`.
+    pub fn along_track_nm(self, start: GeoPoint, end: GeoPoint) -> f64 {
+        let delta13 = start.great_circle_distance_nm(self) / EARTH_RADIUS_NM;
+        let theta13 = start.great_circle_bearing(self).to_radians();
+        let theta12 = start.great_circle_bearing(end).to_radians();
+        let delta_xt = (delta13.sin() * (theta13 - theta12).sin()).asin();
+        let along = (delta13.cos() / delta_xt.cos()).clamp(-1f64, 1f64).acos();
+        along.copysign((theta12 - theta13).cos()) * EARTH_RADIUS_NM
+    }
+}
+
+/// Vincenty's `A` and `B` coefficients.
+fn vincenty_a_b(cos_sq_alpha: f64) -> (f64, f64) {
+    let u_sq =
+        cos_sq_alpha * (WGS84_A * WGS84_A - WGS84_B * WGS84_B) / (WGS84_B * WGS84_B);
+    let big_a = 1f64
+        + u_sq / 16384f64
+            * (4096f64 + u_sq * (-768f64 + u_sq * (320f64 - 175f64 * u_sq)));
+    let big_b =
+        u_sq / 1024f64 * (256f64 + u_sq * (-128f64 + u_sq * (74f64 - 47f64 * u_sq)));
+    (big_a, big_b)
+}
+
+fn vincenty_delta_sigma(
+    big_b: f64,
+    sin_sigma: f64,
+    cos_sigma: f64,
+    cos_2sigma_m: f64,
+) -> f64 {
+    let cos_sq_2sigma_m = cos_2sigma_m * cos_2sigma_m;
+    big_b
+        * sin_sigma
+        * (cos_2sigma_m
+            + big_b / 4f64
+                * (cos_sigma * (-1f64 + 2f64 * cos_sq_2sigma_m)
+                    - big_b / 6f64
+                        * cos_2sigma_m
+                        * (-3f64 + 4f64 * sin_sigma * sin_sigma)
+                        * (-3f64 + 4f64 * cos_sq_2sigma_m)))
+}
+
+/// Vincenty's inverse formula, or [`None`] if it doesn't converge.
+fn vincenty_inverse(p1: GeoPoint, p2: GeoPoint) -> Option<Inverse> {
+    let big_l = (p2.lon - p1.lon).to_radians();
+    let u1 = ((1f64 - WGS84_F) * p1.lat.to_radians().tan()).atan();
+    let u2 = ((1f64 - WGS84_F) * p2.lat.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = big_l;
+    for _ in 0..MAX_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = (cos_u2 * sin_lambda)
+            .hypot(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+        if sin_sigma == 0f64 {
+            // The points are the same.
+            return Some(Inverse {
+                distance_nm: 0f64,
+                initial_bearing: 0f64,
+                final_bearing: 0f64,
+            });
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1f64 - sin_alpha * sin_alpha;
+        // Both points are on the equator if cos²α is 0.
+        let cos_2sigma_m = if cos_sq_alpha == 0f64 {
+            0f64
+        } else {
+            cos_sigma - 2f64 * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = WGS84_F / 16f64
+            * cos_sq_alpha
+            * (4f64 + WGS84_F * (4f64 - 3f64 * cos_sq_alpha));
+        let prev_lambda = lambda;
+        lambda = big_l
+            + (1f64 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m
+                            + c * cos_sigma
+                                * (-1f64 + 2f64 * cos_2sigma_m * cos_2sigma_m)));
+        if lambda.abs() > PI {
+            // Nearly antipodal, and diverging.
+            return None;
+        }
+        if (lambda - prev_lambda).abs() < CONVERGENCE {
+            let (big_a, big_b) = vincenty_a_b(cos_sq_alpha);
+            let delta_sigma =
+                vincenty_delta_sigma(big_b, sin_sigma, cos_sigma, cos_2sigma_m);
+            let distance_m = WGS84_B * big_a * (sigma - delta_sigma);
+            let (sin_lambda, cos_lambda) = lambda.sin_cos();
+            let alpha1 = (cos_u2 * sin_lambda)
+                .atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+            let alpha2 = (cos_u1 * sin_lambda)
+                .atan2(-sin_u1 * cos_u2 + cos_u1 * sin_u2 * cos_lambda);
+            return Some(Inverse {
+                distance_nm: distance_m / METRES_PER_NM,
+                initial_bearing: normalize_bearing(alpha1.to_degrees()),
+                final_bearing: normalize_bearing(alpha2.to_degrees()),
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geo::{vincenty_inverse, GeoPoint, METRES_PER_NM};
+
+    fn dms(deg: f64, min: f64, sec: f64) -> f64 {
+        deg.signum() * (deg.abs() + min / 60.0 + sec / 3600.0)
+    }
+
+    #[test]
+    fn vincenty_flinders_peak_to_buninyong() {
+        // Vincenty's own worked example.
+        let flinders_peak =
+            GeoPoint::new(dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440));
+        let buninyong =
+            GeoPoint::new(dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390));
+        let inverse = flinders_peak.inverse(buninyong);
+        assert!((inverse.distance_nm * METRES_PER_NM - 54_972.271).abs() < 0.001);
+        assert!((inverse.initial_bearing - dms(306.0, 52.0, 5.37)).abs() < 1e-5);
+        assert!((inverse.final_bearing - dms(307.0, 10.0, 25.07)).abs() < 1e-5);
+
+        let direct =
+            flinders_peak.direct(inverse.initial_bearing, inverse.distance_nm);
+        assert!((direct.point.lat - buninyong.lat).abs() < 1e-8);
+        assert!((direct.point.lon - buninyong.lon).abs() < 1e-8);
+        assert!((direct.final_bearing - inverse.final_bearing).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nearly_antipodal_falls_back() {
+        let (p1, p2) = (GeoPoint::new(0.0, 0.0), GeoPoint::new(0.5, 179.7));
+        assert!(vincenty_inverse(p1, p2).is_none());
+        let inverse = p1.inverse(p2);
+        assert!((inverse.distance_nm - 10_788.0).abs() < 60.0);
+    }
+
+    #[test]
+    fn great_circle_helpers() {
+        let start = GeoPoint::new(0.0, 0.0);
+        let end = GeoPoint::new(0.0, 10.0);
+        let mid = start.intermediate(end, 0.5);
+        assert!(mid.lat.abs() < 1e-9 && (mid.lon - 5.0).abs() < 1e-9);
+
+        let north = GeoPoint::new(1.0, 5.0);
+        assert!((north.cross_track_nm(start, end) + 60.0).abs() < 0.1);
+        assert!((north.along_track_nm(start, end) - 300.2).abs() < 0.1);
+        let behind = GeoPoint::new(-1.0, -1.0);
+        assert!(behind.cross_track_nm(start, end) > 0.0);
+        assert!(behind.along_track_nm(start, end) < 0.0);
+    }
+}
@@ -0,0 +1,485 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! The World Magnetic Model, for the magnetic declination (variation) anywhere on the
+//! earth.
+//!
+//! The coefficients of WMM2025 are built in, and are valid from 2025.0 to 2030.0.
+//! Newer models can be loaded from the `.COF` files NOAA publishes them as.
+//!
+//! Declination is positive when magnetic north is east of true north.
+
+use std::{fs, io::Error as IoError, path::Path, sync::OnceLock};
+
+use snafu::{prelude::*, Backtrace};
+
+use crate::geo::{normalize_bearing, GeoPoint, WGS84_A, WGS84_F};
+
+/// WMM2025, as published by NOAA in `WMM.COF`.
+const WMM2025_COF: &str = "    2025.0            WMM-2025     11/13/2024
+  1  0  -29351.8       0.0       12.0        0.0
+  1  1   -1410.8    4545.4        9.7      -21.5
+  2  0   -2556.6       0.0      -11.6        0.0
+  2  1    2951.1   -3133.6       -5.2      -27.7
+  2  2    1649.3    -815.1       -8.0      -12.1
+  3  0    1361.0       0.0       -1.3        0.0
+  3  1   -2404.1     -56.6       -4.2        4.0
+  3  2    1243.8     237.5        0.4       -0.3
+  3  3     453.6    -549.5      -15.6       -4.1
+  4  0     895.0       0.0       -1.6        0.0
+  4  1     799.5     278.6       -2.4       -1.1
+  4  2      55.7    -133.9       -6.0        4.1
+  4  3    -281.1     212.0        5.6        1.6
+  4  4      12.1    -375.6       -7.0       -4.4
+  5  0    -233.2       0.0        0.6        0.0
+  5  1     368.9      45.4        1.4       -0.5
+  5  2     187.2     220.2        0.0        2.2
+  5  3    -138.7    -122.9        0.6        0.4
+  5  4    -142.0      43.0        2.2        1.7
+  5  5      20.9     106.1        0.9        1.9
+  6  0      64.4       0.0       -0.2        0.0
+  6  1      63.8     -18.4       -0.4        0.3
+  6  2      76.9      16.8        0.9       -1.6
+  6  3    -115.7      48.8        1.2       -0.4
+  6  4     -40.9     -59.8       -0.9        0.9
+  6  5      14.9      10.9        0.3        0.7
+  6  6     -60.7      72.7        0.9        0.9
+  7  0      79.5       0.0       -0.0        0.0
+  7  1     -77.0     -48.9       -0.1        0.6
+  7  2      -8.8     -14.4       -0.1        0.5
+  7  3      59.3      -1.0        0.5       -0.8
+  7  4      15.8      23.4       -0.1        0.0
+  7  5       2.5      -7.4       -0.8       -1.0
+  7  6     -11.1     -25.1       -0.8        0.6
+  7  7      14.2      -2.3        0.8       -0.2
+  8  0      23.2       0.0       -0.1        0.0
+  8  1      10.8       7.1        0.2       -0.2
+  8  2     -17.5     -12.6        0.0        0.5
+  8  3       2.0      11.4        0.5       -0.4
+  8  4     -21.7      -9.7       -0.1        0.4
+  8  5      16.9      12.7        0.3       -0.5
+  8  6      15.0       0.7        0.2       -0.6
+  8  7     -16.8      -5.2       -0.0        0.3
+  8  8       0.9       3.9        0.2        0.2
+  9  0       4.6       0.0       -0.0        0.0
+  9  1       7.8     -24.8       -0.1       -0.3
+  9  2       3.0      12.2        0.1        0.3
+  9  3      -0.2       8.3        0.3       -0.3
+  9  4      -2.5      -3.3       -0.3        0.3
+  9  5     -13.1      -5.2        0.0        0.2
+  9  6       2.4       7.2        0.3       -0.1
+  9  7       8.6      -0.6       -0.1       -0.2
+  9  8      -8.7       0.8        0.1        0.4
+  9  9     -12.9      10.0       -0.1        0.1
+ 10  0      -1.3       0.0        0.1        0.0
+ 10  1      -6.4       3.3        0.0        0.0
+ 10  2       0.2       0.0        0.1       -0.0
+ 10  3       2.0       2.4        0.1       -0.2
+ 10  4      -1.0       5.3       -0.0        0.1
+ 10  5      -0.6      -9.1       -0.3       -0.1
+ 10  6      -0.9       0.4        0.0        0.1
+ 10  7       1.5      -4.2       -0.1        0.0
+ 10  8       0.9      -3.8       -0.1       -0.1
+ 10  9      -2.7       0.9       -0.0        0.2
+ 10 10      -3.9      -9.1       -0.0       -0.0
+ 11  0       2.9       0.0        0.0        0.0
+ 11  1      -1.5       0.0       -0.0       -0.0
+ 11  2      -2.5       2.9        0.0        0.1
+ 11  3       2.4      -0.6        0.0       -0.0
+ 11  4      -0.6       0.2        0.0        0.1
+ 11  5      -0.1       0.5       -0.1       -0.0
+ 11  6      -0.6      -0.3        0.0       -0.0
+ 11  7      -0.1      -1.2       -0.0        0.1
+ 11  8       1.1      -1.7       -0.1       -0.0
+ 11  9      -1.0      -2.9       -0.1        0.0
+ 11 10      -0.2      -1.8       -0.1        0.0
+ 11 11       2.6      -2.3       -0.1        0.0
+ 12  0      -2.0       0.0        0.0        0.0
+ 12  1      -0.2      -1.3        0.0       -0.0
+ 12  2       0.3       0.7       -0.0        0.0
+ 12  3       1.2       1.0       -0.0       -0.1
+ 12  4      -1.3      -1.4       -0.0        0.1
+ 12  5       0.6      -0.0       -0.0       -0.0
+ 12  6       0.6       0.6        0.1       -0.0
+ 12  7       0.5      -0.1       -0.0       -0.0
+ 12  8      -0.1       0.8        0.0        0.0
+ 12  9      -0.4       0.1        0.0       -0.0
+ 12 10      -0.2      -1.0       -0.1       -0.0
+ 12 11      -1.3       0.1       -0.0        0.0
+ 12 12      -0.7       0.2       -0.1       -0.1
+999999999999999999999999999999999999999999999999
+999999999999999999999999999999999999999999999999
+";
+
+/// The reference radius of the model, in kilometres.
+const REFERENCE_RADIUS_KM: f64 = 6371.2;
+
+#[derive(Snafu, Debug)]
+pub enum WmmError {
+    #[snafu(display("An I/O error has occurred!"))]
+    #[snafu(context(false))]
+    Io {
+        source: IoError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("The header of the coefficient file could not be parsed."))]
+    InvalidHeader { backtrace: Backtrace },
+
+    #[snafu(display(
+        "Line {line_number} of the coefficient file is invalid: {line}"
+    ))]
+    InvalidLine {
+        line_number: usize,
+        line: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("The coefficient file has no coefficients."))]
+    NoCoefficients { backtrace: Backtrace },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The magnetic field at a point, as given by a [`MagneticModel`].
+pub struct MagneticField {
+    /// The northward component, in nanoteslas.
+    pub north_nt: f64,
+    /// The eastward component, in nanoteslas.
+    pub east_nt: f64,
+    /// The downward component, in nanoteslas.
+    pub down_nt: f64,
+    /// The declination, in degrees. Positive when magnetic north is east of true north.
+    pub declination: f64,
+    /// The inclination (dip), in degrees. Positive when the field points down.
+    pub inclination: f64,
+}
+
+impl MagneticField {
+    #[must_use]
+    /// The horizontal intensity, in nanoteslas.
+    pub fn horizontal_nt(&self) -> f64 {
+        self.north_nt.hypot(self.east_nt)
+    }
+
+    #[must_use]
+    /// The total intensity, in nanoteslas.
+    pub fn total_nt(&self) -> f64 {
+        self.horizontal_nt().hypot(self.down_nt)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A spherical harmonic model of the earth's main magnetic field, like the World
+/// Magnetic Model.
+pub struct MagneticModel {
+    name: String,
+    epoch: f64,
+    max_degree: usize,
+    /// Schmidt semi-normalized Gauss coefficients, and their yearly rates of change,
+    /// in nanoteslas, indexed by [`coefficient_index`].
+    g: Vec<f64>,
+    h: Vec<f64>,
+    g_dot: Vec<f64>,
+    h_dot: Vec<f64>,
+}
+
+/// The index of the coefficients of degree `n` and order `m`.
+fn coefficient_index(n: usize, m: usize) -> usize {
+    n * (n + 1) / 2 + m
+}
+
+impl MagneticModel {
+    #[must_use]
+    /// The built-in World Magnetic Model, WMM2025.
+    /// # Panics
+    /// Never; the built-in coefficients are checked by the tests.
+    pub fn wmm() -> &'static MagneticModel {
+        static WMM: OnceLock<MagneticModel> = OnceLock::new();
+        WMM.get_or_init(|| Self::from_cof(WMM2025_COF).unwrap())
+    }
+
+    /// Load a model from a `.COF` file, like the `WMM.COF` published by NOAA.
+    /// # Errors
+    /// Returns an [`Err`] if the file cannot be read or is malformed.
+    pub fn load_cof(path: &Path) -> Result<Self, WmmError> {
+        Self::from_cof(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a model from the contents of a `.COF` file.
+    /// # Errors
+    /// Returns an [`Err`] if the contents are malformed.
+    pub fn from_cof(cof: &str) -> Result<Self, WmmError> {
+        let mut lines = cof.lines().enumerate();
+        let (_, header) = lines.next().context(InvalidHeaderSnafu)?;
+        let mut header = header.split_whitespace();
+        let epoch = header
+            .next()
+            .and_then(|epoch| epoch.parse::<f64>().ok())
+            .context(InvalidHeaderSnafu)?;
+        let name = header.next().context(InvalidHeaderSnafu)?.to_owned();
+
+        let mut rows = Vec::new();
+        for (idx, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // The file is terminated by lines of 9s.
+            if line.starts_with("9999") {
+                break;
+            }
+            let row = parse_cof_row(line).context(InvalidLineSnafu {
+                line_number: idx + 1,
+                line,
+            })?;
+            rows.push(row);
+        }
+        let max_degree = rows
+            .iter()
+            .map(|(n, ..)| *n)
+            .max()
+            .context(NoCoefficientsSnafu)?;
+
+        let len = coefficient_index(max_degree, max_degree) + 1;
+        let mut model = Self {
+            name,
+            epoch,
+            max_degree,
+            g: vec![0f64; len],
+            h: vec![0f64; len],
+            g_dot: vec![0f64; len],
+            h_dot: vec![0f64; len],
+        };
+        for (n, m, [g, h, g_dot, h_dot]) in rows {
+            let idx = coefficient_index(n, m);
+            model.g[idx] = g;
+            model.h[idx] = h;
+            model.g_dot[idx] = g_dot;
+            model.h_dot[idx] = h_dot;
+        }
+        Ok(model)
+    }
+
+    #[must_use]
+    /// The name of the model, like `WMM-2025`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    /// The epoch of the model, as a decimal year. The model is meant for use in the
+    /// five years from its epoch.
+    pub fn epoch(&self) -> f64 {
+        self.epoch
+    }
+
+    #[must_use]
+    /// The magnetic field at `point`, `alt_ft` feet above the WGS84 ellipsoid, on the
+    /// date `year`, given as a decimal year, like from [`decimal_year`].
+    #[allow(clippy::similar_names, clippy::many_single_char_names)]
+    pub fn field(&self, point: GeoPoint, alt_ft: f64, year: f64) -> MagneticField {
+        let dt = year - self.epoch;
+        let alt_km = alt_ft * 0.3048 / 1000f64;
+        let lat = point.lat.to_radians();
+        let lon = point.lon.to_radians();
+
+        // Geodetic to geocentric spherical coordinates.
+        let a_km = WGS84_A / 1000f64;
+        let e_sq = WGS84_F * (2f64 - WGS84_F);
+        let (sin_lat, cos_lat) = lat.sin_cos();
+        let rc = a_km / (1f64 - e_sq * sin_lat * sin_lat).sqrt();
+        let p = (rc + alt_km) * cos_lat;
+        let z = (rc * (1f64 - e_sq) + alt_km) * sin_lat;
+        let r = p.hypot(z);
+        let lat_gc = (z / r).asin();
+
+        // Associated Legendre functions of cos(colatitude), and their derivatives by
+        // colatitude, Schmidt semi-normalized.
+        let cos_theta = lat_gc.sin();
+        // At the poles, the east component is undefined; stay just off of them.
+        let sin_theta = lat_gc.cos().max(1e-10);
+        let len = self.g.len();
+        let mut p_nm = vec![0f64; len];
+        let mut dp_nm = vec![0f64; len];
+        p_nm[0] = 1f64;
+        for n in 1..=self.max_degree {
+            for m in 0..=n {
+                let idx = coefficient_index(n, m);
+                if m == n {
+                    let prev = coefficient_index(n - 1, n - 1);
+                    p_nm[idx] = sin_theta * p_nm[prev];
+                    dp_nm[idx] = sin_theta * dp_nm[prev] + cos_theta * p_nm[prev];
+                } else {
+                    let prev = coefficient_index(n - 1, m);
+                    #[allow(clippy::cast_precision_loss)]
+                    let k = if n == 1 {
+                        0f64
+                    } else {
+                        ((n - 1) * (n - 1) - m * m) as f64
+                            / ((2 * n - 1) * (2 * n - 3)) as f64
+                    };
+                    let (p_prev2, dp_prev2) = if n == 1 {
+                        (0f64, 0f64)
+                    } else {
+                        let prev2 = coefficient_index(n - 2, m);
+                        (p_nm[prev2], dp_nm[prev2])
+                    };
+                    p_nm[idx] = cos_theta * p_nm[prev] - k * p_prev2;
+                    dp_nm[idx] = cos_theta * dp_nm[prev]
+                        - sin_theta * p_nm[prev]
+                        - k * dp_prev2;
+                }
+            }
+        }
+        // Gauss to Schmidt semi-normalization.
+        let mut schmidt = 1f64;
+        for n in 1..=self.max_degree {
+            #[allow(clippy::cast_precision_loss)]
+            let n_f = n as f64;
+            schmidt *= (2f64 * n_f - 1f64) / n_f;
+            let mut factor = schmidt;
+            for m in 0..=n {
+                #[allow(clippy::cast_precision_loss)]
+                let m_f = m as f64;
+                if m > 0 {
+                    let delta = if m == 1 { 2f64 } else { 1f64 };
+                    factor *= ((n_f - m_f + 1f64) * delta / (n_f + m_f)).sqrt();
+                }
+                let idx = coefficient_index(n, m);
+                p_nm[idx] *= factor;
+                dp_nm[idx] *= factor;
+            }
+        }
+
+        let (mut north, mut east, mut down) = (0f64, 0f64, 0f64);
+        let ratio = REFERENCE_RADIUS_KM / r;
+        let mut ratio_pow = ratio * ratio;
+        for n in 1..=self.max_degree {
+            ratio_pow *= ratio;
+            #[allow(clippy::cast_precision_loss)]
+            let n_f = n as f64;
+            for m in 0..=n {
+                let idx = coefficient_index(n, m);
+                let g = self.g[idx] + dt * self.g_dot[idx];
+                let h = self.h[idx] + dt * self.h_dot[idx];
+                #[allow(clippy::cast_precision_loss)]
+                let m_f = m as f64;
+                let (sin_ml, cos_ml) = (m_f * lon).sin_cos();
+                north += ratio_pow * (g * cos_ml + h * sin_ml) * dp_nm[idx];
+                east += ratio_pow * m_f * (g * sin_ml - h * cos_ml) * p_nm[idx];
+                down -=
+                    ratio_pow * (n_f + 1f64) * (g * cos_ml + h * sin_ml) * p_nm[idx];
+            }
+        }
+        east /= sin_theta;
+
+        // Geocentric to geodetic components.
+        let psi = lat_gc - lat;
+        let (sin_psi, cos_psi) = psi.sin_cos();
+        let north_nt = north * cos_psi - down * sin_psi;
+        let down_nt = north * sin_psi + down * cos_psi;
+        MagneticField {
+            north_nt,
+            east_nt: east,
+            down_nt,
+            declination: east.atan2(north_nt).to_degrees(),
+            inclination: down_nt.atan2(north_nt.hypot(east)).to_degrees(),
+        }
+    }
+
+    #[must_use]
+    /// The declination at `point` and `alt_ft`, on the date `year`, like in
+    /// [`field`](MagneticModel::field).
+    pub fn declination(&self, point: GeoPoint, alt_ft: f64, year: f64) -> f64 {
+        self.field(point, alt_ft, year).declination
+    }
+}
+
+fn parse_cof_row(line: &str) -> Option<(usize, usize, [f64; 4])> {
+    let mut fields = line.split_whitespace();
+    let n = fields.next()?.parse().ok()?;
+    let m = fields.next()?.parse().ok()?;
+    if n == 0 || m > n {
+        return None;
+    }
+    let mut values = [0f64; 4];
+    for value in &mut values {
+        *value = fields.next()?.parse().ok()?;
+    }
+    Some((n, m, values))
+}
+
+#[must_use]
+/// The decimal year of a date, as used by [`MagneticModel`]. `month` and `day` start
+/// at 1.
+pub fn decimal_year(year: i32, month: u32, day: u32) -> f64 {
+    const DAYS_BEFORE_MONTH: [u32; 12] =
+        [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let is_leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month_idx = month.clamp(1, 12) as usize - 1;
+    let mut day_of_year = DAYS_BEFORE_MONTH[month_idx] + day.max(1) - 1;
+    if is_leap && month_idx >= 2 {
+        day_of_year += 1;
+    }
+    let days_in_year = if is_leap { 366f64 } else { 365f64 };
+    f64::from(year) + f64::from(day_of_year) / days_in_year
+}
+
+#[must_use]
+/// Convert a magnetic course to a true one, given the magnetic variation.
+pub fn magnetic_to_true(course_mag: f64, variation: f64) -> f64 {
+    normalize_bearing(course_mag + variation)
+}
+
+#[must_use]
+/// Convert a true course to a magnetic one, given the magnetic variation.
+pub fn true_to_magnetic(course_true: f64, variation: f64) -> f64 {
+    normalize_bearing(course_true - variation)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geo::{
+        wmm::{decimal_year, magnetic_to_true, true_to_magnetic, MagneticModel},
+        GeoPoint,
+    };
+
+    #[test]
+    fn declination_at_known_places() {
+        let wmm = MagneticModel::wmm();
+        assert_eq!(wmm.name(), "WMM-2025");
+        let year = decimal_year(2025, 7, 1);
+        // Declinations as charted around mid-2025, rounded to the nearest degree.
+        for (lat, lon, expected) in [
+            (37.62, -122.38, 13.0),
+            (40.64, -73.78, -13.0),
+            (51.47, -0.45, 1.0),
+            (-33.95, 151.18, 13.0),
+        ] {
+            let declination = wmm.declination(GeoPoint::new(lat, lon), 0.0, year);
+            assert!(
+                (declination - expected).abs() < 1.0,
+                "{declination} at {lat}, {lon}"
+            );
+        }
+        let field = wmm.field(GeoPoint::new(37.62, -122.38), 0.0, year);
+        assert!((field.inclination - 61.0).abs() < 1.5);
+        assert!((field.total_nt() - 48_000.0).abs() < 1500.0);
+    }
+
+    #[test]
+    fn course_conversions() {
+        assert!((magnetic_to_true(350.0, 13.0) - 3.0).abs() < 1e-9);
+        assert!((true_to_magnetic(3.0, 13.0) - 350.0).abs() < 1e-9);
+        assert!((decimal_year(2024, 3, 1) - (2024.0 + 60.0 / 366.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reject_bad_cof() {
+        assert!(MagneticModel::from_cof("").is_err());
+        assert!(
+            MagneticModel::from_cof("    2025.0   WMM-2025\n  1  0  x\n").is_err()
+        );
+        assert!(MagneticModel::from_cof("    2025.0   WMM-2025\n9999\n").is_err());
+    }
+}
@@ -7,8 +7,12 @@
 #[cfg(feature = "dsf")]
 pub mod dsf;
 
+pub mod geo;
+
 #[cfg(feature = "navdata")]
 pub mod navdata;
 
+pub mod radio;
+
 #[cfg(test)]
 mod tests {}
@@ -3,22 +3,40 @@
 // SPDX-License-Identifier: Parity-7.0.0
 
 pub mod airways;
+pub mod apt;
+pub mod builder;
+#[cfg(feature = "navdata_cache")]
+mod cache;
 pub mod cifp;
 pub mod fix;
+pub mod fms;
 pub mod hold;
+mod ident_index;
+pub mod layers;
+pub mod mora;
+pub mod msa;
 pub mod nav;
+pub mod reception;
+pub mod route;
+pub mod source;
+mod spatial;
 
 use either::Either::{self, Left, Right};
+use itertools::Itertools;
 use petgraph::{
+    algo::astar,
     graph::{DiGraph, NodeIndex},
-    visit::{DfsPostOrder, EdgeFiltered, Walker},
+    visit::{DfsPostOrder, EdgeFiltered, EdgeRef, Walker},
 };
 use snafu::{prelude::*, Backtrace};
 use std::{
+    collections::HashMap,
     fmt::Display,
     fs::File,
-    io::{BufRead, BufReader, Error as IoError, Lines, Read},
-    path::Path,
+    io::{BufRead, BufReader, Error as IoError, Lines, Read, Write},
+    iter::FusedIterator,
+    ops::Range,
+    path::{Path, PathBuf},
     rc::Rc,
     str::FromStr,
 };
@@ -33,17 +51,34 @@ use winnow::{
     Located,
 };
 
-use crate::navdata::{
-    airways::AwyEdge,
-    fix::Fix,
-    hold::Edge as HoldEdge,
-    nav::{Navaid, TypeSpecificData},
+use crate::{
+    geo::{great_circle_distance_nm, wmm::MagneticModel, GeoPoint},
+    navdata::{
+        airways::{AirwayLeg, AirwayLevel, AwyEdge},
+        apt::Airport,
+        cifp::{Procedure, ProcedureKind},
+        fix::Fix,
+        hold::Edge as HoldEdge,
+        ident_index::IdentIndex,
+        layers::{DataFolders, Layer, LayeredEntries, Provenances},
+        mora::MoraGrid,
+        msa::Msa,
+        nav::{Navaid, TypeSpecificData},
+        spatial::SpatialIndex,
+    },
 };
 
 pub struct NavGraph {
     fix_header: Header,
     navaids_header: Header,
     graph: DiGraph<NavEntry, NavEdge>,
+    procedures: HashMap<heapless::String<4>, Vec<Procedure>>,
+    ident_index: IdentIndex,
+    spatial_index: SpatialIndex,
+    airports: HashMap<heapless::String<4>, Airport>,
+    mora: MoraGrid,
+    msas: HashMap<NodeIndex, Vec<Msa>>,
+    provenance: Provenances,
 }
 
 impl NavGraph {
@@ -51,72 +86,142 @@ impl NavGraph {
     /// # Errors
     /// Returns an [`Err`] if there is an I/O error, or if the data is malformed.
     pub fn build_data_from_folder(folder: &Path) -> Result<Self, ParseError> {
-        let fix_file = BufReader::new(File::open(folder.join("earth_fix.dat"))?);
-        let mut fixes = fix::parse_file_buffered(fix_file)?;
-        let user_fixes = folder.join("user_fix.dat");
-        if user_fixes.exists() {
-            let user_fixes = BufReader::new(File::open(user_fixes)?);
-            let user_fixes = fix::parse_file_buffered(user_fixes)?;
-            for user_fix in user_fixes.entries {
-                // Essentially, check if there is a fix in the same area, with the same ident.
-                let matching_main_fix_pos = fixes.entries.iter().position(|fix| {
-                    fix.ident == user_fix.ident
-                        && fix.icao_region == user_fix.icao_region
-                        && fix.terminal_region == user_fix.terminal_region
-                });
-                if let Some(pos) = matching_main_fix_pos {
-                    fixes.entries[pos] = user_fix;
-                } else {
-                    fixes.entries.push(user_fix);
+        Self::build_data_from_folders(&DataFolders::custom_data(folder))
+    }
+
+    /// Parses all navdata from the X-Plane `Custom Data` folder, like
+    /// [`build_data_from_folder`](NavGraph::build_data_from_folder), but skips rows
+    /// that are malformed or reference waypoints that don't exist, and CIFP files that
+    /// aren't named after an airport. Each of those is returned as a [`Diagnostic`].
+    /// # Errors
+    /// Returns an [`Err`] if there is an I/O error, or if a file is malformed beyond
+    /// its rows, like a bad header or a missing terminating line.
+    pub fn build_data_from_folder_lenient(
+        folder: &Path,
+    ) -> Result<(Self, Vec<Diagnostic>), ParseError> {
+        Self::build_data_from_folders_lenient(&DataFolders::custom_data(folder))
+    }
+
+    /// Parses all navdata from the layered data folders, as described in
+    /// [`layers`]. The [`Provenance`](layers::Provenance) of each entry is kept, and can be
+    /// queried with [`provenance`](NavGraph::provenance).
+    /// # Errors
+    /// Returns an [`Err`] if there is an I/O error, if a required file is missing from
+    /// the folder the earth files are taken from, or if the data is malformed.
+    pub fn build_data_from_folders(
+        folders: &DataFolders,
+    ) -> Result<Self, ParseError> {
+        let mut sink = DiagnosticSink::strict();
+        Self::build(folders, &mut sink)
+    }
+
+    /// Like [`build_data_from_folders`](NavGraph::build_data_from_folders), but
+    /// lenient, like
+    /// [`build_data_from_folder_lenient`](NavGraph::build_data_from_folder_lenient).
+    /// # Errors
+    /// Returns an [`Err`] if there is an I/O error, if a required file is missing from
+    /// the folder the earth files are taken from, or if a file is malformed beyond its
+    /// rows.
+    pub fn build_data_from_folders_lenient(
+        folders: &DataFolders,
+    ) -> Result<(Self, Vec<Diagnostic>), ParseError> {
+        let mut sink = DiagnosticSink::lenient();
+        let nav_graph = Self::build(folders, &mut sink)?;
+        Ok((nav_graph, sink.diagnostics))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn build(
+        folders: &DataFolders,
+        sink: &mut DiagnosticSink,
+    ) -> Result<Self, ParseError> {
+        let mut layered = LayeredEntries::default();
+
+        let fix_file = folders.require("earth_fix.dat")?;
+        let fix_path = fix_file.path;
+        let (fix_header, fixes) = fix::parse_file_buffered(fix_file.reader)
+            .and_then(|rows| rows.collect_with(sink.errors()))
+            .map_err(|e| e.in_file(&fix_path))?;
+        sink.file_done(&fix_path);
+        layered.extend(
+            fixes.into_iter().map(NavEntry::Fix),
+            fix_file.layer,
+            &fix_path,
+        );
+        if let Some(user_fix_file) = folders.user_file("user_fix.dat")? {
+            let user_fixes = user_fix_file.path;
+            let user_fix_rows = fix::parse_file_buffered(user_fix_file.reader)
+                .map_err(|e| e.in_file(&user_fixes))?;
+            for user_fix in user_fix_rows {
+                let user_fix = recover(user_fix, sink.errors())
+                    .map_err(|e| e.in_file(&user_fixes))?;
+                if let Some(user_fix) = user_fix {
+                    layered.overlay(
+                        NavEntry::Fix(user_fix),
+                        Layer::User,
+                        &user_fixes,
+                    );
                 }
             }
+            sink.file_done(&user_fixes);
         }
-        let nav_file = BufReader::new(File::open(folder.join("earth_nav.dat"))?);
-        let mut navaids = nav::parse_file_buffered(nav_file)?;
-        let established_cycle = fixes.header.cycle;
+
+        let nav_file = folders.require("earth_nav.dat")?;
+        let nav_path = nav_file.path;
+        let (navaids_header, navaids) = nav::parse_file_buffered(nav_file.reader)
+            .and_then(|rows| rows.collect_with(sink.errors()))
+            .map_err(|e| e.in_file(&nav_path))?;
+        sink.file_done(&nav_path);
+        let established_cycle = fix_header.cycle;
         ensure!(
-            navaids.header.cycle == established_cycle,
+            navaids_header.cycle == established_cycle,
             CycleMismatchSnafu {
                 established_cycle,
-                new_cycle: navaids.header.cycle
+                new_cycle: navaids_header.cycle
             }
         );
-        let user_nav = folder.join("user_nav.dat");
-        if user_nav.exists() {
-            let user_nav = BufReader::new(File::open(user_nav)?);
-            let user_nav = nav::parse_file_buffered(user_nav)?;
-            for user_navaid in user_nav.entries {
-                // Essentially, check if there is a matching navaid of the same type, in the same place, with the same ident.
-                let matching_main_navaid_pos =
-                    navaids.entries.iter().position(|navaid| {
-                        navaid.ident == user_navaid.ident
-                            && navaid.icao_region == user_navaid.icao_region
-                            && std::mem::discriminant(&navaid.type_data)
-                                == std::mem::discriminant(&user_navaid.type_data)
-                    });
-                if let Some(pos) = matching_main_navaid_pos {
-                    navaids.entries[pos] = user_navaid;
-                } else {
-                    navaids.entries.push(user_navaid);
+        layered.extend(
+            navaids.into_iter().map(NavEntry::Navaid),
+            nav_file.layer,
+            &nav_path,
+        );
+        if let Some(user_nav_file) = folders.user_file("user_nav.dat")? {
+            let user_nav = user_nav_file.path;
+            let user_nav_rows = nav::parse_file_buffered(user_nav_file.reader)
+                .map_err(|e| e.in_file(&user_nav))?;
+            for user_navaid in user_nav_rows {
+                let user_navaid = recover(user_navaid, sink.errors())
+                    .map_err(|e| e.in_file(&user_nav))?;
+                if let Some(user_navaid) = user_navaid {
+                    layered.overlay(
+                        NavEntry::Navaid(user_navaid),
+                        Layer::User,
+                        &user_nav,
+                    );
                 }
             }
+            sink.file_done(&user_nav);
         }
-        let fix_header = fixes.header;
-        let navaids_header = navaids.header;
-        let mut nav_graph = DiGraph::<NavEntry, NavEdge>::with_capacity(
-            fixes.entries.len() + navaids.entries.len(),
-            0,
-        );
-        for fix in fixes.entries {
-            nav_graph.add_node(NavEntry::Fix(fix));
-        }
-        for navaid in navaids.entries {
-            nav_graph.add_node(NavEntry::Navaid(navaid));
+
+        let mut nav_graph =
+            DiGraph::<NavEntry, NavEdge>::with_capacity(layered.entries.len(), 0);
+        for entry in layered.entries.drain(..) {
+            nav_graph.add_node(entry);
         }
+        let provenance = layered.into_provenance();
+
+        let ident_index = IdentIndex::build(&nav_graph);
 
-        let airway_file = BufReader::new(File::open(folder.join("earth_awy.dat"))?);
-        let airway_header =
-            airways::parse_file_buffered(airway_file, &mut nav_graph)?;
+        let airway_file = folders.require("earth_awy.dat")?;
+        let airway_path = airway_file.path;
+        let airway_header = airways::parse_file_buffered(
+            airway_file.reader,
+            &mut nav_graph,
+            &ident_index,
+            sink.errors(),
+        )
+        .map_err(|e| e.in_file(&airway_path))?;
+        sink.file_done(&airway_path);
         ensure!(
             airway_header.cycle == established_cycle,
             CycleMismatchSnafu {
@@ -125,8 +230,16 @@ impl NavGraph {
             }
         );
 
-        let hold_file = BufReader::new(File::open(folder.join("earth_hold.dat"))?);
-        let hold_header = hold::parse_file_buffered(hold_file, &mut nav_graph)?;
+        let hold_file = folders.require("earth_hold.dat")?;
+        let hold_path = hold_file.path;
+        let hold_header = hold::parse_file_buffered(
+            hold_file.reader,
+            &mut nav_graph,
+            &ident_index,
+            sink.errors(),
+        )
+        .map_err(|e| e.in_file(&hold_path))?;
+        sink.file_done(&hold_path);
         ensure!(
             hold_header.cycle == established_cycle,
             CycleMismatchSnafu {
@@ -134,7 +247,201 @@ impl NavGraph {
                 new_cycle: hold_header.cycle
             }
         );
-        todo!()
+
+        // MORAs and MSAs are not in older navdata.
+        let mut mora = MoraGrid::default();
+        if let Some(mora_file) = folders.find("earth_mora.dat")? {
+            let mora_path = mora_file.path;
+            let moras = mora::parse_file_buffered(mora_file.reader, sink.errors())
+                .map_err(|e| e.in_file(&mora_path))?;
+            sink.file_done(&mora_path);
+            ensure!(
+                moras.header.cycle == established_cycle,
+                CycleMismatchSnafu {
+                    established_cycle,
+                    new_cycle: moras.header.cycle
+                }
+            );
+            mora = moras.grid;
+        }
+        let mut msas = HashMap::new();
+        if let Some(msa_file) = folders.find("earth_msa.dat")? {
+            let msa_path = msa_file.path;
+            let (msa_header, parsed_msas) = msa::parse_file_buffered(
+                msa_file.reader,
+                &nav_graph,
+                &ident_index,
+                sink.errors(),
+            )
+            .map_err(|e| e.in_file(&msa_path))?;
+            sink.file_done(&msa_path);
+            ensure!(
+                msa_header.cycle == established_cycle,
+                CycleMismatchSnafu {
+                    established_cycle,
+                    new_cycle: msa_header.cycle
+                }
+            );
+            msas = parsed_msas;
+        }
+
+        let mut procedures = HashMap::new();
+        if let Some(cifp_folder) = folders.find_folder("CIFP")? {
+            for name in cifp_folder.names {
+                let Some(stem) = name.strip_suffix(".dat") else {
+                    continue;
+                };
+                let cifp_name = format!("CIFP/{name}");
+                let path = cifp_folder.source.file_path(&cifp_name);
+                // CIFP files are named after the airport they belong to.
+                let airport_icao = heapless::String::<4>::try_from(stem)
+                    .ok()
+                    .context(InvalidCifpFileNameSnafu {
+                        name: path.display().to_string(),
+                    });
+                if let Some(airport_icao) = recover(airport_icao, sink.errors())? {
+                    // The file may have been removed since the folder was listed.
+                    if let Some(cifp_file) = cifp_folder.source.open(&cifp_name)? {
+                        let airport_procedures =
+                            cifp::parse_file_buffered(cifp_file, sink.errors())
+                                .map_err(|e| e.in_file(&path))?;
+                        procedures.insert(airport_icao, airport_procedures);
+                    }
+                }
+                sink.file_done(&path);
+            }
+        }
+
+        let spatial_index = SpatialIndex::build(&nav_graph);
+
+        Ok(Self {
+            fix_header,
+            navaids_header,
+            graph: nav_graph,
+            procedures,
+            ident_index,
+            spatial_index,
+            airports: HashMap::new(),
+            mora,
+            msas,
+            provenance,
+        })
+    }
+
+    /// Parse an `apt.dat` file and add its airports with
+    /// [`add_airports`](NavGraph::add_airports).
+    /// # Errors
+    /// Returns an [`Err`] if there is an I/O error, or if the data is malformed.
+    pub fn load_airports(&mut self, apt_file: &Path) -> Result<(), ParseError> {
+        let airports =
+            apt::parse_file_buffered(BufReader::new(File::open(apt_file)?))
+                .map_err(|e| e.in_file(apt_file))?;
+        self.add_airports(airports);
+        Ok(())
+    }
+
+    /// Add airports, linking their runway ends to the localizers, glideslopes, and
+    /// threshold points for them. Airports replace any already added with the same ICAO
+    /// code, so scenery should be added from lowest to highest priority.
+    pub fn add_airports(&mut self, airports: impl IntoIterator<Item = Airport>) {
+        let mut rwy_navaids: HashMap<(&str, &str), Vec<NodeIndex>> = HashMap::new();
+        for idx in self.graph.node_indices() {
+            let NavEntry::Navaid(Navaid { type_data, .. }) = &self.graph[idx] else {
+                continue;
+            };
+            if let TypeSpecificData::Localizer {
+                airport_icao, rwy, ..
+            }
+            | TypeSpecificData::Glideslope {
+                airport_icao, rwy, ..
+            }
+            | TypeSpecificData::ThresholdPoint {
+                airport_icao, rwy, ..
+            } = type_data
+            {
+                rwy_navaids
+                    .entry((airport_icao.as_str(), rwy.as_str()))
+                    .or_default()
+                    .push(idx);
+            }
+        }
+        for mut airport in airports {
+            // Airports longer than an ICAO code can't be referred to by navdata.
+            let Ok(icao) = heapless::String::<4>::try_from(airport.icao()) else {
+                continue;
+            };
+            for end in airport.runways.iter_mut().flat_map(|rwy| &mut rwy.ends) {
+                end.navaids = rwy_navaids
+                    .get(&(icao.as_str(), end.ident.as_str()))
+                    .cloned()
+                    .unwrap_or_default();
+            }
+            self.airports.insert(icao, airport);
+        }
+    }
+
+    #[must_use]
+    /// Get the airport with the ICAO code `icao`, or with the ident `icao` if it has
+    /// no ICAO code. Returns [`None`] if no such airport has been added.
+    pub fn airport(&self, icao: &str) -> Option<&Airport> {
+        let icao = heapless::String::<4>::try_from(icao).ok()?;
+        self.airports.get(&icao)
+    }
+
+    /// Get all airports that have been added.
+    pub fn airports(&self) -> impl Iterator<Item = &Airport> {
+        self.airports.values()
+    }
+
+    #[must_use]
+    /// Get the grid MORA in feet at the given position. Returns [`None`] if it is
+    /// unknown, or if no MORAs were loaded.
+    pub fn mora_ft(&self, lat: f64, lon: f64) -> Option<u32> {
+        self.mora.mora_ft(lat, lon)
+    }
+
+    #[must_use]
+    /// Get the MSAs centered on the node `idx`.
+    pub fn msas(&self, idx: NodeIndex) -> &[Msa] {
+        self.msas.get(&idx).map_or(&[], Vec::as_slice)
+    }
+
+    #[must_use]
+    /// Get the header of the fix data this graph was built from.
+    pub fn fix_header(&self) -> &Header {
+        &self.fix_header
+    }
+
+    #[must_use]
+    /// Get the header of the navaid data this graph was built from.
+    pub fn navaids_header(&self) -> &Header {
+        &self.navaids_header
+    }
+
+    /// Get the ICAO codes of all airports that CIFP data was loaded for.
+    pub fn cifp_airports(&self) -> impl Iterator<Item = &str> {
+        self.procedures.keys().map(heapless::String::as_str)
+    }
+
+    #[must_use]
+    /// Get all SIDs, STARs, and approaches for the airport `airport_icao`.
+    /// Returns [`None`] if no CIFP data was loaded for that airport.
+    pub fn procedures(&self, airport_icao: &str) -> Option<&[Procedure]> {
+        let airport_icao = heapless::String::<4>::try_from(airport_icao).ok()?;
+        self.procedures.get(&airport_icao).map(Vec::as_slice)
+    }
+
+    #[must_use]
+    /// Find a procedure of the given kind by its ident, at the airport `airport_icao`.
+    pub fn find_procedure(
+        &self,
+        airport_icao: &str,
+        kind: ProcedureKind,
+        ident: &str,
+    ) -> Option<&Procedure> {
+        self.procedures(airport_icao)?
+            .iter()
+            .find(|p| p.kind == kind && p.ident == ident)
     }
 
     #[must_use]
@@ -152,12 +459,65 @@ impl NavGraph {
     /// Find all entries matching the given `ident` in the navigation database.
     /// Returns tuples of the indices of the nodes and references to the entries.
     pub fn find_nav_entry(&self, ident: &str) -> Vec<(NodeIndex, &NavEntry)> {
-        self.graph
-            .node_indices()
-            .filter(|idx| match &self.graph[*idx] {
-                NavEntry::Fix(fix) => fix.ident == ident,
-                NavEntry::Navaid(navaid) => navaid.ident == ident,
-            })
+        self.ident_index
+            .get(ident)
+            .iter()
+            .map(|idx| (*idx, &self.graph[*idx]))
+            .collect()
+    }
+
+    #[must_use]
+    /// Find the `count` entries nearest to the given position, optionally only of the
+    /// given kinds. Returns tuples of the indices of the nodes, references to the
+    /// entries, and their great-circle distances in nautical miles, nearest first.
+    pub fn nearest(
+        &self,
+        lat: f64,
+        lon: f64,
+        count: usize,
+        kinds: Option<&[NavEntryKind]>,
+    ) -> Vec<(NodeIndex, &NavEntry, f64)> {
+        self.spatial_index
+            .nearest(&self.graph, lat, lon, count, kinds)
+            .into_iter()
+            .map(|(idx, dist)| (idx, &self.graph[idx], dist))
+            .collect()
+    }
+
+    #[must_use]
+    /// Find all entries within `radius_nm` nautical miles of the given position,
+    /// optionally only of the given kinds. Returns tuples of the indices of the nodes,
+    /// references to the entries, and their great-circle distances in nautical miles,
+    /// nearest first.
+    pub fn within_radius(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_nm: f64,
+        kinds: Option<&[NavEntryKind]>,
+    ) -> Vec<(NodeIndex, &NavEntry, f64)> {
+        self.spatial_index
+            .within_radius(&self.graph, lat, lon, radius_nm, kinds)
+            .into_iter()
+            .map(|(idx, dist)| (idx, &self.graph[idx], dist))
+            .collect()
+    }
+
+    #[must_use]
+    /// Find all entries inside the given bounding box, optionally only of the given
+    /// kinds. If `west` is greater than `east`, the box is taken to cross the
+    /// antimeridian.
+    pub fn within_bounds(
+        &self,
+        south: f64,
+        west: f64,
+        north: f64,
+        east: f64,
+        kinds: Option<&[NavEntryKind]>,
+    ) -> Vec<(NodeIndex, &NavEntry)> {
+        self.spatial_index
+            .within_bounds(&self.graph, south, west, north, east, kinds)
+            .into_iter()
             .map(|idx| (idx, &self.graph[idx]))
             .collect()
     }
@@ -220,6 +580,72 @@ impl NavGraph {
             Ok(res)
         }
     }
+
+    /// Find the shortest route along airways from `start` to `end`, by great-circle
+    /// distance. Only airway segments whose flight level limits cover `cruise_fl`,
+    /// and which are of the given `level`, are used.
+    ///
+    /// Returns the legs of the route in order. If `start` and `end` are the same,
+    /// no legs are returned.
+    ///
+    /// # Errors
+    /// An error will be returned if one of the following occurs:
+    /// - A bad node index is given.
+    /// - There is no route between the nodes using the allowed airways.
+    pub fn airway_route(
+        &self,
+        start: NodeIndex,
+        end: NodeIndex,
+        cruise_fl: u16,
+        level: AirwayLevel,
+    ) -> Result<Vec<AirwayLeg>, AirwayTraverseError> {
+        for idx in [start, end] {
+            if self.graph.node_weight(idx).is_none() {
+                return BadNodeSnafu { idx }.fail()?;
+            }
+        }
+        let usable = |edge: &NavEdge| matches!(edge, NavEdge::Airway(awy) if awy.usable_at(cruise_fl, level));
+        let ef = EdgeFiltered::from_fn(&self.graph, |er| usable(er.weight()));
+        let distance = |a: NodeIndex, b: NodeIndex| {
+            let (a, b) = (&self.graph[a], &self.graph[b]);
+            great_circle_distance_nm(a.lat(), a.lon(), b.lat(), b.lon())
+        };
+
+        let (_, path) = astar(
+            &ef,
+            start,
+            |idx| idx == end,
+            |er| distance(er.source(), er.target()),
+            |idx| distance(idx, end),
+        )
+        .context(NoPathSnafu { idx: Left(end) })?;
+
+        let mut legs: Vec<AirwayLeg> =
+            Vec::with_capacity(path.len().saturating_sub(1));
+        for (from, to) in path.iter().copied().tuple_windows() {
+            // Stay on the same airway where possible.
+            let previous = legs.last().map(|leg| &leg.airway);
+            let airway = self
+                .graph
+                .edges_connecting(from, to)
+                .filter_map(|er| match er.weight() {
+                    NavEdge::Airway(awy) if awy.usable_at(cruise_fl, level) => {
+                        Some(&awy.name)
+                    },
+                    _ => None,
+                })
+                .max_by_key(|name| Some(*name) == previous)
+                .cloned()
+                .unwrap_or_default();
+            legs.push(AirwayLeg {
+                from,
+                to,
+                airway,
+                distance_nm: distance(from, to),
+            });
+        }
+        Ok(legs)
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -257,6 +683,93 @@ pub enum AirwayTraverseError {
     },
 }
 
+#[derive(Debug, Snafu)]
+pub enum RouteError {
+    #[snafu(display("The route is empty."))]
+    EmptyRoute { backtrace: Backtrace },
+
+    #[snafu(display(
+        "Token {index} `{token}` is not a known waypoint, or an airway from the previous waypoint."
+    ))]
+    UnknownWaypoint {
+        token: String,
+        /// The index of the token in the route string, counting from 0.
+        index: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Token {index} `{token}` is not a valid latitude/longitude."))]
+    BadLatLon {
+        token: String,
+        /// The index of the token in the route string, counting from 0.
+        index: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Token {index} `{token}` is not a known {kind:?} or transition at {airport}."
+    ))]
+    UnknownProcedure {
+        token: String,
+        /// The index of the token in the route string, counting from 0.
+        index: usize,
+        kind: ProcedureKind,
+        airport: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The airway `{token}` (token {index}) is not followed by a waypoint."
+    ))]
+    AirwayWithoutExit {
+        token: String,
+        /// The index of the token in the route string, counting from 0.
+        index: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Token {index} `{token}` is not on the airway {airway}."))]
+    BadAirwayExit {
+        token: String,
+        /// The index of the token in the route string, counting from 0.
+        index: usize,
+        airway: String,
+        #[snafu(source(from(AirwayTraverseError, Box::new)))]
+        source: Box<AirwayTraverseError>,
+    },
+}
+
+#[cfg(feature = "navdata_cache")]
+#[derive(Debug, Snafu)]
+pub enum CacheError {
+    #[snafu(display("An I/O error has occurred!"))]
+    #[snafu(context(false))]
+    Io {
+        source: IoError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("The header of a source file could not be parsed."))]
+    #[snafu(context(false))]
+    SourceHeader { source: ParseError },
+
+    #[snafu(display("The cache could not be encoded or decoded."))]
+    #[snafu(context(false))]
+    Encoding {
+        source: bincode::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("The file is not a navdata cache."))]
+    BadMagic { backtrace: Backtrace },
+
+    #[snafu(display("The cache format version {version} is not supported."))]
+    UnsupportedFormat { version: u32, backtrace: Backtrace },
+
+    #[snafu(display("The cache is out of date with the source files."))]
+    Stale { backtrace: Backtrace },
+}
+
 #[derive(Debug, Snafu)]
 pub enum GraphError {
     #[snafu(display("A bad node index has been given: {idx:?}"))]
@@ -268,18 +781,121 @@ pub enum GraphError {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum NavEntry {
     Fix(Fix),
     Navaid(Navaid),
 }
 
+impl NavEntry {
+    #[must_use]
+    pub fn lat(&self) -> f64 {
+        match self {
+            NavEntry::Fix(Fix { lat, .. })
+            | NavEntry::Navaid(Navaid { lat, .. }) => *lat,
+        }
+    }
+
+    #[must_use]
+    pub fn lon(&self) -> f64 {
+        match self {
+            NavEntry::Fix(Fix { lon, .. })
+            | NavEntry::Navaid(Navaid { lon, .. }) => *lon,
+        }
+    }
+
+    #[must_use]
+    /// The position of the entry, for use with the [`geo`](crate::geo) functions.
+    pub fn position(&self) -> GeoPoint {
+        GeoPoint::new(self.lat(), self.lon())
+    }
+
+    #[must_use]
+    /// The magnetic variation to use for courses referenced to this entry, in
+    /// degrees, positive east, on the date `year` (see
+    /// [`decimal_year`](crate::geo::wmm::decimal_year)).
+    ///
+    /// VOR radials are referenced to the variation the VOR is slaved to, which can be
+    /// years out of date. Other entries use the variation from `model` at their
+    /// position.
+    pub fn magnetic_variation(&self, model: &MagneticModel, year: f64) -> f64 {
+        match self {
+            NavEntry::Navaid(Navaid {
+                type_data:
+                    TypeSpecificData::Vor {
+                        slaved_variation, ..
+                    },
+                ..
+            }) => f64::from(*slaved_variation),
+            NavEntry::Navaid(Navaid { elevation, .. }) => {
+                model.declination(self.position(), f64::from(*elevation), year)
+            },
+            NavEntry::Fix(_) => model.declination(self.position(), 0f64, year),
+        }
+    }
+
+    #[must_use]
+    pub fn ident(&self) -> &str {
+        match self {
+            NavEntry::Fix(Fix { ident, .. }) => ident,
+            NavEntry::Navaid(Navaid { ident, .. }) => ident,
+        }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> NavEntryKind {
+        match self {
+            NavEntry::Fix(_) => NavEntryKind::Fix,
+            NavEntry::Navaid(Navaid { type_data, .. }) => match type_data {
+                TypeSpecificData::Ndb { .. } => NavEntryKind::Ndb,
+                TypeSpecificData::Vor { .. } => NavEntryKind::Vor,
+                TypeSpecificData::Localizer { .. } => NavEntryKind::Localizer,
+                TypeSpecificData::Glideslope { .. } => NavEntryKind::Glideslope,
+                TypeSpecificData::MarkerBeacon { .. } => NavEntryKind::MarkerBeacon,
+                TypeSpecificData::Dme { .. } => NavEntryKind::Dme,
+                TypeSpecificData::Fpap { .. } => NavEntryKind::Fpap,
+                TypeSpecificData::ThresholdPoint { .. } => {
+                    NavEntryKind::ThresholdPoint
+                },
+                TypeSpecificData::Gls { .. } => NavEntryKind::Gls,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// The kind of a [`NavEntry`], for filtering queries.
+pub enum NavEntryKind {
+    Fix,
+    Ndb,
+    Vor,
+    Localizer,
+    Glideslope,
+    MarkerBeacon,
+    Dme,
+    Fpap,
+    ThresholdPoint,
+    Gls,
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum NavEdge {
     Airway(AwyEdge),
     Hold(HoldEdge),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum DataVersion {
     XP1100,
     XP1101,
@@ -288,7 +904,11 @@ pub enum DataVersion {
     XP1200,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct Header {
     pub version: DataVersion,
     pub cycle: u16,
@@ -315,12 +935,6 @@ pub enum ParseError {
     #[snafu(display("The byte order marker was an unexpected value: {bom}"))]
     BadBOM { bom: String, backtrace: Backtrace },
 
-    #[snafu(display("The last line of this file was unexpected:\n{last_line}"))]
-    BadLastLine {
-        last_line: String,
-        backtrace: Backtrace,
-    },
-
     #[snafu(display("A line was expected, but the file had no more."))]
     MissingLine,
 
@@ -347,6 +961,64 @@ pub enum ParseError {
     #[snafu(display("An invalid airway direction was encountered: `{dir}`"))]
     InvalidAwyDir { dir: char, backtrace: Backtrace },
 
+    #[snafu(display("The CIFP file `{name}` is not named after an airport."))]
+    InvalidCifpFileName { name: String, backtrace: Backtrace },
+
+    #[snafu(display(
+        "The procedure {proc_ident} has a leg with unknown route type `{route_typ}`."
+    ))]
+    UnknownRouteType {
+        route_typ: char,
+        proc_ident: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The {path_term} leg with sequence number {sequence} of the procedure \
+         {proc_ident} is missing its {field}."
+    ))]
+    MissingLegField {
+        /// The path and terminator of the offending leg.
+        path_term: String,
+        /// The field the leg is missing.
+        field: &'static str,
+        proc_ident: String,
+        sequence: u16,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The procedure {proc_ident} has a leg with unknown path and terminator \
+         `{path_term}`."
+    ))]
+    UnknownPathTerminator {
+        path_term: String,
+        proc_ident: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The leg with sequence number {sequence} of the procedure {proc_ident} has \
+         an unknown constraint descriptor `{desc}`."
+    ))]
+    UnknownConstraintDescriptor {
+        desc: char,
+        proc_ident: String,
+        sequence: u16,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The leg with sequence number {sequence} of the procedure {proc_ident} has \
+         a malformed altitude `{alt}`."
+    ))]
+    InvalidAltitude {
+        alt: String,
+        proc_ident: String,
+        sequence: u16,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("An invalid hold direction was encountered: `{dir}`"))]
     InvalidHoldDir { dir: char, backtrace: Backtrace },
 
@@ -356,36 +1028,499 @@ pub enum ParseError {
         dme: f32,
         backtrace: Backtrace,
     },
+
+    /// Another error, found in a particular line of a file.
+    #[snafu(display("{error}\n{location}"))]
+    AtLine {
+        location: Box<SourceLocation>,
+        error: Box<ParseError>,
+    },
+}
+
+#[derive(Debug, Snafu)]
+pub enum WriteError {
+    #[snafu(display("An I/O error has occurred!"))]
+    #[snafu(context(false))]
+    Io {
+        source: IoError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The data version {version:?} is not supported by the writer for this format."
+    ))]
+    UnwritableVersion {
+        version: DataVersion,
+        backtrace: Backtrace,
+    },
+}
+
+#[derive(Debug)]
+/// A problem in the navdata that was skipped over by a lenient load, like a malformed
+/// row, or a row referencing a waypoint that does not exist.
+pub struct Diagnostic {
+    /// The file the problem was found in.
+    pub file: PathBuf,
+    pub error: ParseError,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Where in a data file an error was found.
+pub struct SourceLocation {
+    /// The file the error was found in, if it was read from a file.
+    pub file: Option<PathBuf>,
+    /// The line the error was found in, starting at 1.
+    pub line: usize,
+    /// The byte range of the offending part of the line.
+    pub span: Range<usize>,
+    /// The whole text of the line.
+    pub text: String,
+}
+
+impl SourceLocation {
+    /// Point at the whole of a line, less surrounding whitespace.
+    fn whole_line(line: usize, text: &str) -> Self {
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len().max(start);
+        Self {
+            file: None,
+            line,
+            span: start..end,
+            text: text.to_owned(),
+        }
+    }
+
+    /// Point at the token of a line starting at the byte `offset`, or at the character
+    /// there if it is whitespace.
+    fn at_offset(line: usize, text: &str, offset: usize) -> Self {
+        let offset = offset.min(text.len());
+        let rest = &text[offset..];
+        let token_len = rest
+            .find(char::is_whitespace)
+            .unwrap_or(rest.len())
+            .max(rest.chars().next().map_or(0, char::len_utf8));
+        Self {
+            file: None,
+            line,
+            span: offset..offset + token_len,
+            text: text.to_owned(),
+        }
+    }
+
+    #[must_use]
+    /// The column the offending part of the line starts at, in characters, starting at
+    /// 1.
+    pub fn column(&self) -> usize {
+        self.text[..self.span.start].chars().count() + 1
+    }
+}
+
+impl Display for SourceLocation {
+    /// Renders the line, with the offending part underlined, like so:
+    /// ```text
+    ///   --> earth_nav.dat:12:3
+    ///    |
+    /// 12 | 3 not a navaid
+    ///    |   ^^^
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let column = self.column();
+        let gutter = " ".repeat(self.line.to_string().len());
+        if let Some(file) = &self.file {
+            writeln!(f, "{gutter}--> {}:{}:{column}", file.display(), self.line)?;
+        } else {
+            writeln!(f, "{gutter}--> line {}, column {column}", self.line)?;
+        }
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line, self.text)?;
+        let underline = self.text[self.span.clone()].chars().count().max(1);
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(column - 1),
+            "^".repeat(underline)
+        )
+    }
+}
+
+impl ParseError {
+    #[must_use]
+    /// Where in its file this error was found, if known.
+    pub fn location(&self) -> Option<&SourceLocation> {
+        match self {
+            Self::AtLine { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    /// This error, without the location it was found at.
+    pub fn without_location(&self) -> &Self {
+        match self {
+            Self::AtLine { error, .. } => error,
+            _ => self,
+        }
+    }
+
+    /// Give this error `location`, unless it already has one.
+    fn at(self, location: SourceLocation) -> Self {
+        match self {
+            Self::AtLine { .. } => self,
+            error => Self::AtLine {
+                location: Box::new(location),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Give this error the location of the whole line `text`, at `line`, unless it
+    /// already has a location. I/O errors and missing lines are not about any line,
+    /// and are left alone.
+    fn at_line(self, line: usize, text: &str) -> Self {
+        match self {
+            Self::Io { .. } | Self::MissingLine => self,
+            error => error.at(SourceLocation::whole_line(line, text)),
+        }
+    }
+
+    /// Record that this error was found in the file at `path`, if it has a location.
+    fn in_file(mut self, path: &Path) -> Self {
+        if let Self::AtLine { location, .. } = &mut self {
+            location.file.get_or_insert_with(|| path.to_owned());
+        }
+        self
+    }
+
+    #[must_use]
+    /// Whether this error concerns only a single row or file, which a lenient load can
+    /// skip. Other errors, like I/O errors and bad headers, always fail the load.
+    pub fn is_skippable(&self) -> bool {
+        matches!(
+            self.without_location(),
+            Self::Parse { .. }
+                | Self::ReferencedNonexistentWpt { .. }
+                | Self::InvalidAwyDir { .. }
+                | Self::InvalidCifpFileName { .. }
+                | Self::UnknownRouteType { .. }
+                | Self::MissingLegField { .. }
+                | Self::UnknownPathTerminator { .. }
+                | Self::UnknownConstraintDescriptor { .. }
+                | Self::InvalidAltitude { .. }
+                | Self::InvalidHoldDir { .. }
+                | Self::ConflictingHoldLegLengths { .. }
+        )
+    }
+}
+
+/// Gathers the [`Diagnostic`]s of a load, file by file.
+struct DiagnosticSink {
+    lenient: bool,
+    /// Errors skipped in the file being parsed.
+    errors: Vec<ParseError>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl DiagnosticSink {
+    fn strict() -> Self {
+        Self {
+            lenient: false,
+            errors: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn lenient() -> Self {
+        Self {
+            lenient: true,
+            ..Self::strict()
+        }
+    }
+
+    /// Where a parser should put the errors it skips, or [`None`] if it shouldn't skip
+    /// any.
+    fn errors(&mut self) -> Option<&mut Vec<ParseError>> {
+        self.lenient.then_some(&mut self.errors)
+    }
+
+    /// Attribute the errors skipped since the last call to the file at `path`.
+    fn file_done(&mut self, path: &Path) {
+        self.diagnostics
+            .extend(self.errors.drain(..).map(|error| Diagnostic {
+                file: path.to_owned(),
+                error: error.in_file(path),
+            }));
+    }
+}
+
+/// If `diagnostics` is given, and `result` is a skippable error, record it and carry
+/// on with [`None`]. Otherwise, pass `result` through.
+fn recover<T>(
+    result: Result<T, ParseError>,
+    diagnostics: Option<&mut Vec<ParseError>>,
+) -> Result<Option<T>, ParseError> {
+    match (result, diagnostics) {
+        (Ok(value), _) => Ok(Some(value)),
+        (Err(e), Some(diagnostics)) if e.is_skippable() => {
+            diagnostics.push(e);
+            Ok(None)
+        },
+        (Err(e), _) => Err(e),
+    }
+}
+
+/// A streaming reader of the rows of a data file, following its header. Rows are
+/// parsed as they are read, and iteration ends at the `99` line that terminates the
+/// file. If the file ends without one, the last item is
+/// [`ParseError::MissingLine`].
+pub struct Rows<F: BufRead, T> {
+    header: Header,
+    lines: DataLines<F>,
+    parse_row: fn(usize, &str) -> Result<T, ParseError>,
+}
+
+impl<F: BufRead, T> Rows<F, T> {
+    /// `parse_row` is given each row with its line number.
+    fn new(
+        header: Header,
+        lines: Lines<F>,
+        parse_row: fn(usize, &str) -> Result<T, ParseError>,
+    ) -> Self {
+        Self {
+            header,
+            lines: DataLines::new(lines),
+            parse_row,
+        }
+    }
+
+    #[must_use]
+    /// Get the header of the file.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<F: BufRead, T: Send> Rows<F, T> {
+    /// Read all remaining rows. With the `parallel` feature, rows are parsed across
+    /// threads, but still come out in the order they are in the file.
+    /// # Errors
+    /// Returns the first [`Err`] encountered.
+    pub fn collect_all(self) -> Result<(Header, Vec<T>), ParseError> {
+        self.collect_with(None)
+    }
+
+    /// Read all remaining rows, skipping rows that fail to parse, and adding their
+    /// errors to `diagnostics`.
+    /// # Errors
+    /// Returns an [`Err`] if there is an I/O error, or if the file is not terminated
+    /// properly.
+    pub fn collect_lenient(
+        self,
+        diagnostics: &mut Vec<ParseError>,
+    ) -> Result<(Header, Vec<T>), ParseError> {
+        self.collect_with(Some(diagnostics))
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn collect_with(
+        self,
+        mut diagnostics: Option<&mut Vec<ParseError>>,
+    ) -> Result<(Header, Vec<T>), ParseError> {
+        let header = self.header.clone();
+        let mut entries = Vec::new();
+        for row in self {
+            entries.extend(recover(row, diagnostics.as_deref_mut())?);
+        }
+        Ok((header, entries))
+    }
+
+    /// Rows are read in chunks, and each chunk is parsed in parallel. The parsed rows
+    /// are then handled in order, just as [`Iterator::next`] would give them, so that
+    /// the first error returned is that of the first bad row.
+    #[cfg(feature = "parallel")]
+    fn collect_with(
+        mut self,
+        mut diagnostics: Option<&mut Vec<ParseError>>,
+    ) -> Result<(Header, Vec<T>), ParseError> {
+        use rayon::prelude::*;
+
+        const CHUNK_LINES: usize = 4096;
+
+        let mut entries = Vec::new();
+        let mut chunk = Vec::with_capacity(CHUNK_LINES);
+        loop {
+            chunk.clear();
+            // An error reading lines comes after the rows before it.
+            let mut read_error = None;
+            for line in self.lines.by_ref() {
+                match line {
+                    Ok(line) => chunk.push(line),
+                    Err(e) => {
+                        read_error = Some(e);
+                        break;
+                    },
+                }
+                if chunk.len() == CHUNK_LINES {
+                    break;
+                }
+            }
+            let parse_row = self.parse_row;
+            let rows: Vec<_> = chunk
+                .par_iter()
+                .map(|(line_number, line)| {
+                    parse_row(*line_number, line)
+                        .map_err(|e| e.at_line(*line_number, line))
+                })
+                .collect();
+            for row in rows {
+                entries.extend(recover(row, diagnostics.as_deref_mut())?);
+            }
+            if let Some(e) = read_error {
+                return Err(e);
+            }
+            if chunk.len() < CHUNK_LINES {
+                return Ok((self.header, entries));
+            }
+        }
+    }
+}
+
+impl<F: BufRead, T> Iterator for Rows<F, T> {
+    type Item = Result<T, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line_number, line) = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(
+            (self.parse_row)(line_number, &line)
+                .map_err(|e| e.at_line(line_number, &line)),
+        )
+    }
+}
+
+impl<F: BufRead, T> FusedIterator for Rows<F, T> {}
+
+/// The non-empty lines of a data file following its header, with their line numbers, up
+/// to the `99` line that terminates the file. If the file ends without one, the last
+/// item is [`ParseError::MissingLine`].
+struct DataLines<F: BufRead> {
+    lines: Lines<F>,
+    line_number: usize,
+    finished: bool,
+}
+
+impl<F: BufRead> DataLines<F> {
+    /// `lines` must have had just the two lines of the header read from them.
+    fn new(lines: Lines<F>) -> Self {
+        Self {
+            lines,
+            line_number: 2,
+            finished: false,
+        }
+    }
+}
+
+impl<F: BufRead> Iterator for DataLines<F> {
+    type Item = Result<(usize, String), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => {
+                    self.finished = true;
+                    return Some(Err(e.into()));
+                },
+                None => {
+                    self.finished = true;
+                    return Some(Err(ParseError::MissingLine));
+                },
+            };
+            self.line_number += 1;
+            if line.is_empty() {
+                continue;
+            }
+            if line == "99" {
+                self.finished = true;
+                return None;
+            }
+            return Some(Ok((self.line_number, line)));
+        }
+    }
+}
+
+impl<F: BufRead> FusedIterator for DataLines<F> {}
+
+/// Call `handle_row` with each row following the header of a data file, and its line
+/// number. Errors are given the location of their row, and if `diagnostics` is given,
+/// rows with skippable errors are skipped, and their errors are added to it.
+fn for_each_row<F: BufRead>(
+    lines: Lines<F>,
+    mut diagnostics: Option<&mut Vec<ParseError>>,
+    mut handle_row: impl FnMut(usize, &str) -> Result<(), ParseError>,
+) -> Result<(), ParseError> {
+    for line in DataLines::new(lines) {
+        let (line_number, line) = line?;
+        let handled = handle_row(line_number, &line)
+            .map_err(|e| e.at_line(line_number, &line));
+        recover(handled, diagnostics.as_deref_mut())?;
+    }
+    Ok(())
+}
+
+/// Parse all of `line`, the row at `line_number`, with `parser`. If it fails, the error
+/// points at where in the row parsing stopped.
+fn parse_line<'a, T>(
+    line_number: usize,
+    line: &'a str,
+    stage: &'static str,
+    parser: impl Parser<Located<&'a str>, T, ContextError>,
+) -> Result<T, ParseError> {
+    trace(stage, parser).parse(Located::new(line)).map_err(|e| {
+        let rendered = e.inner().to_string();
+        ParseSnafu {
+            rendered: if rendered.is_empty() {
+                "Unexpected input.".to_owned()
+            } else {
+                rendered
+            },
+            stage,
+        }
+        .build()
+        .at(SourceLocation::at_offset(line_number, line, e.offset()))
+    })
 }
 
 fn parse_header<F: Read + BufRead>(
     verify_type: impl Fn(&str) -> bool,
     lines: &mut Lines<F>,
 ) -> Result<Header, ParseError> {
-    lines.next().ok_or(ParseError::MissingLine).and_then(|l| {
-        let bom = l?;
-        if bom != "A" && bom != "I" {
-            return BadBOMSnafu { bom }.fail();
-        }
-        Ok(())
-    })?;
-    lines
-        .next()
-        .ok_or(ParseError::MissingLine)
-        .and_then(|line| {
-            let line = line?;
-            let ret =
-                parse_header_after_bom(verify_type)
-                    .parse(&line)
-                    .map_err(|e| {
-                        ParseSnafu {
-                            rendered: e.to_string(),
-                            stage: "header",
-                        }
-                        .build()
-                    });
-            ret // Weird lifetime error if I don't do this.
-        })
+    let bom = lines.next().ok_or(ParseError::MissingLine)??;
+    if bom != "A" && bom != "I" {
+        return Err(BadBOMSnafu { bom: bom.clone() }.build().at_line(1, &bom));
+    }
+    let line = lines.next().ok_or(ParseError::MissingLine)??;
+    let ret = parse_header_after_bom(verify_type)
+        .parse(&line)
+        .map_err(|e| {
+            ParseSnafu {
+                rendered: e.inner().to_string(),
+                stage: "header",
+            }
+            .build()
+            .at(SourceLocation::at_offset(2, &line, e.offset()))
+        });
+    ret // Weird lifetime error if I don't do this.
 }
 
 fn parse_header_after_bom<'a>(
@@ -440,6 +1575,29 @@ fn parse_header_after_bom<'a>(
     }
 }
 
+/// Write the byte order marker and header line of a data file, along with the blank line
+/// that follows them.
+fn write_header<W: Write>(
+    header: &Header,
+    md_type: &str,
+    out: &mut W,
+) -> Result<(), IoError> {
+    let version = match header.version {
+        DataVersion::XP1100 => "1100",
+        DataVersion::XP1101 => "1101",
+        DataVersion::XP1140 => "1140",
+        DataVersion::XP1150 => "1150",
+        DataVersion::XP1200 => "1200",
+    };
+    writeln!(out, "I")?;
+    writeln!(
+        out,
+        "{version} Version - data cycle {:04}, build {:08}, metadata {md_type}. {}",
+        header.cycle, header.build, header.copyright
+    )?;
+    writeln!(out)
+}
+
 fn take_hstring_till<const N: usize, F: Fn(char) -> bool + Copy>(
     till: F,
 ) -> impl Fn(&mut Located<&str>) -> PResult<heapless::String<N>> {
@@ -474,45 +1632,359 @@ fn fixed_hstring_till<'a, const N: usize, F: Fn(char) -> bool + Copy>(
 }
 
 fn match_wpt_predicate<'a>(
-    wpt: &'a ParsedNodeRef,
+    wpt: &'a WptRef,
     nav_graph: &'a DiGraph<NavEntry, NavEdge>,
 ) -> impl Fn(&NodeIndex) -> bool + 'a {
     |idx| -> bool {
-        match (wpt.typ, &nav_graph[*idx]) {
-            (ParsedNodeRefType::Fix, NavEntry::Fix(fix)) => {
-                wpt.ident == fix.ident && wpt.icao_region == fix.icao_region
-            },
-            (ParsedNodeRefType::Vhf, NavEntry::Navaid(navaid)) => {
-                wpt.ident == navaid.ident
-                    && wpt.icao_region == navaid.icao_region
-                    && matches!(
-                        navaid.type_data,
-                        TypeSpecificData::Vor { .. }
-                            | TypeSpecificData::Dme {
-                                display_freq: true,
-                                ..
-                            }
-                    )
-            },
-            (ParsedNodeRefType::Ndb, NavEntry::Navaid(navaid)) => {
-                wpt.ident == navaid.ident
-                    && wpt.icao_region == navaid.icao_region
-                    && matches!(navaid.type_data, TypeSpecificData::Ndb { .. })
-            },
-            _ => false,
-        }
+        let entry = &nav_graph[*idx];
+        let icao_region = match entry {
+            NavEntry::Fix(Fix { icao_region, .. })
+            | NavEntry::Navaid(Navaid { icao_region, .. }) => icao_region,
+        };
+        wpt.typ.matches(entry)
+            && wpt.ident == entry.ident()
+            && wpt.icao_region == *icao_region
     }
 }
 
-struct ParsedNodeRef {
-    ident: heapless::String<5>,
-    icao_region: heapless::String<2>,
-    typ: ParsedNodeRefType,
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A reference to a waypoint, as used by the airway and hold files.
+pub struct WptRef {
+    pub ident: heapless::String<5>,
+    pub icao_region: heapless::String<2>,
+    pub typ: WptType,
 }
 
-#[derive(Debug, Copy, Clone)]
-enum ParsedNodeRefType {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// The type of waypoint a [`WptRef`] refers to.
+pub enum WptType {
     Ndb,
+    /// A VOR, or a DME shown on its own.
     Vhf,
     Fix,
 }
+
+impl WptType {
+    /// The type from its code, as used by the airway and hold files, and `.fms` flight
+    /// plans. These follow the row codes of `earth_nav.dat`, where 2 is an NDB and 3 a
+    /// VOR.
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            2 => Some(Self::Ndb),
+            3 => Some(Self::Vhf),
+            11 => Some(Self::Fix),
+            _ => None,
+        }
+    }
+
+    /// The code of this type, as read by [`WptType::from_code`].
+    fn code(self) -> u8 {
+        match self {
+            Self::Ndb => 2,
+            Self::Vhf => 3,
+            Self::Fix => 11,
+        }
+    }
+
+    fn matches(self, entry: &NavEntry) -> bool {
+        match (self, entry) {
+            (Self::Fix, NavEntry::Fix(_)) => true,
+            (Self::Vhf, NavEntry::Navaid(navaid)) => matches!(
+                navaid.type_data,
+                TypeSpecificData::Vor { .. }
+                    | TypeSpecificData::Dme {
+                        display_freq: true,
+                        ..
+                    }
+            ),
+            (Self::Ndb, NavEntry::Navaid(navaid)) => {
+                matches!(navaid.type_data, TypeSpecificData::Ndb { .. })
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    use petgraph::graph::DiGraph;
+
+    use crate::navdata::{
+        airways::{AirwayLevel, AwyEdge},
+        cifp::Procedure,
+        fix::{Fix, FixFunction, FixProcedure, FixType},
+        ident_index::IdentIndex,
+        layers::Provenances,
+        mora::MoraGrid,
+        spatial::SpatialIndex,
+        AirwayTraverseError, DataVersion, Header, NavEdge, NavEntry, NavGraph,
+        ParseError, WptRef, WptType,
+    };
+
+    pub(super) fn nav_graph(
+        graph: DiGraph<NavEntry, NavEdge>,
+        procedures: HashMap<heapless::String<4>, Vec<Procedure>>,
+    ) -> NavGraph {
+        let ident_index = IdentIndex::build(&graph);
+        let spatial_index = SpatialIndex::build(&graph);
+        NavGraph {
+            fix_header: header(),
+            navaids_header: header(),
+            graph,
+            procedures,
+            ident_index,
+            spatial_index,
+            airports: HashMap::new(),
+            mora: MoraGrid::default(),
+            msas: HashMap::new(),
+            provenance: Provenances::default(),
+        }
+    }
+
+    /// The names and contents of a small, complete set of navdata files.
+    pub(super) fn test_data_files() -> Vec<(&'static str, String)> {
+        let header = |version: &str, md_type: &str| {
+            format!(
+                "I\n{version} Version - data cycle 2401, build 20240101, \
+                 metadata {md_type}. Test data.\n\n"
+            )
+        };
+        let files = [
+            (
+                "earth_fix.dat",
+                header("1200", "FixXP1200"),
+                " 37.000000000 -122.000000000 ALPHA ENRT K2 2105430\n \
+                 38.000000000 -122.000000000 BRAVO ENRT K2 2105430\n",
+            ),
+            (
+                "earth_nav.dat",
+                header("1200", "NavXP1200"),
+                "3  37.619483330 -122.373913890     13 11580 130    17.0 SFO ENRT K2 \
+                 SAN FRANCISCO VOR/DME\n",
+            ),
+            (
+                "earth_awy.dat",
+                header("1100", "AwyXP1100"),
+                "ALPHA K2 11 BRAVO K2 11 N 2 180 450 J1-J2\n\
+                 SFO K2 3 ALPHA K2 11 N 2 180 450 J3\n",
+            ),
+            (
+                "earth_hold.dat",
+                header("1140", "HoldXP1140"),
+                "ALPHA K2 ENRT 11 180.0 1.0 0.0 R 5000 18000 230\n",
+            ),
+            (
+                "earth_mora.dat",
+                header("1150", "MORAXP1150"),
+                "N37W123 050 UNK\n",
+            ),
+            (
+                "earth_msa.dat",
+                header("1150", "MSAXP1150"),
+                "SFO K2 3 KSFO A 000 045 25 180 080 25 000 000 00\n",
+            ),
+        ];
+        files
+            .into_iter()
+            .map(|(name, header, rows)| (name, format!("{header}{rows}99\n")))
+            .collect()
+    }
+
+    /// Write [`test_data_files`] to a new folder.
+    pub(super) fn write_test_data(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir()
+            .join(format!("xputils-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        for (name, contents) in test_data_files() {
+            fs::write(folder.join(name), contents).unwrap();
+        }
+        folder
+    }
+
+    fn header() -> Header {
+        Header {
+            version: DataVersion::XP1200,
+            cycle: 2401,
+            build: 0,
+            copyright: String::new(),
+        }
+    }
+
+    pub(super) fn fix(ident: &str, lat: f64, lon: f64) -> NavEntry {
+        NavEntry::Fix(enroute_fix(ident, lat, lon))
+    }
+
+    /// An enroute fix in the `ZZ` region.
+    pub(super) fn enroute_fix(ident: &str, lat: f64, lon: f64) -> Fix {
+        Fix {
+            lat,
+            lon,
+            ident: ident.try_into().unwrap(),
+            terminal_region: "ENRT".try_into().unwrap(),
+            icao_region: "ZZ".try_into().unwrap(),
+            typ: FixType::Unspecified,
+            func: FixFunction::Unspecified,
+            proc: FixProcedure::Unspecified,
+            printed_spoken_name: None,
+        }
+    }
+
+    /// A reference to an [`enroute_fix`].
+    pub(super) fn wpt(ident: &str) -> WptRef {
+        WptRef {
+            ident: ident.try_into().unwrap(),
+            icao_region: "ZZ".try_into().unwrap(),
+            typ: WptType::Fix,
+        }
+    }
+
+    pub(super) fn awy(
+        name: &str,
+        base_fl: u16,
+        top_fl: u16,
+        is_high: bool,
+    ) -> NavEdge {
+        NavEdge::Airway(AwyEdge {
+            base_fl,
+            top_fl,
+            is_high,
+            name: name.try_into().unwrap(),
+        })
+    }
+
+    #[test]
+    fn airway_route_honors_levels() {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::new();
+        let a = graph.add_node(fix("AAAAA", 0.0, 0.0));
+        let b = graph.add_node(fix("BBBBB", 0.0, 1.0));
+        let c = graph.add_node(fix("CCCCC", 0.0, 2.0));
+        let d = graph.add_node(fix("DDDDD", 1.0, 1.0));
+        // The direct line is low-altitude only, the detour is high.
+        graph.add_edge(a, b, awy("V1", 0, 180, false));
+        graph.add_edge(b, c, awy("V1", 0, 180, false));
+        graph.add_edge(a, d, awy("J2", 180, 450, true));
+        graph.add_edge(d, c, awy("J2", 180, 450, true));
+        let nav_graph = nav_graph(graph, HashMap::new());
+
+        let low = nav_graph.airway_route(a, c, 100, AirwayLevel::Any).unwrap();
+        let path: Vec<_> = low
+            .iter()
+            .map(|leg| (leg.to, leg.airway.as_str()))
+            .collect();
+        assert_eq!(path, [(b, "V1"), (c, "V1")]);
+        assert!((low[0].distance_nm - 60.0).abs() < 0.5);
+
+        let high = nav_graph.airway_route(a, c, 350, AirwayLevel::Any).unwrap();
+        let path: Vec<_> = high
+            .iter()
+            .map(|leg| (leg.to, leg.airway.as_str()))
+            .collect();
+        assert_eq!(path, [(d, "J2"), (c, "J2")]);
+
+        assert!(matches!(
+            nav_graph.airway_route(a, c, 100, AirwayLevel::High),
+            Err(AirwayTraverseError::NoPath { .. })
+        ));
+        assert!(nav_graph
+            .airway_route(a, a, 100, AirwayLevel::Any)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn build_from_test_data() {
+        let folder = write_test_data("build");
+        let nav_graph = NavGraph::build_data_from_folder(&folder).unwrap();
+        assert_eq!(nav_graph.fix_header.cycle, 2401);
+        let alpha = nav_graph.find_nav_entry("ALPHA")[0].0;
+        for awy in ["J1", "J2"] {
+            let path = nav_graph.airway_find(alpha, awy, "BRAVO").unwrap();
+            assert_eq!(path.last().unwrap().1.ident(), "BRAVO");
+        }
+        let sfo = nav_graph.find_nav_entry("SFO")[0].0;
+        assert!(nav_graph.airway_find(sfo, "J3", "ALPHA").is_ok());
+        assert!(nav_graph
+            .graph
+            .edges(alpha)
+            .any(|e| matches!(e.weight(), NavEdge::Hold(_))));
+        assert_eq!(nav_graph.mora_ft(37.5, -122.5), Some(5000));
+        assert_eq!(nav_graph.mora_ft(37.5, -121.5), None);
+        let msa = &nav_graph.msas(sfo)[0];
+        assert_eq!(msa.sector_at(270.0).unwrap().altitude_ft, 8000);
+        assert_eq!(msa.sector_at(90.0).unwrap().altitude_ft, 4500);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn build_leniently_skips_bad_rows() {
+        let folder = write_test_data("lenient");
+        let awy_path = folder.join("earth_awy.dat");
+        let awys = fs::read_to_string(&awy_path).unwrap();
+        fs::write(
+            &awy_path,
+            awys.replace("99\n", "ALPHA K2 11 NOPE K2 11 N 2 180 450 J4\n99\n"),
+        )
+        .unwrap();
+        let nav_path = folder.join("earth_nav.dat");
+        let navaids = fs::read_to_string(&nav_path).unwrap();
+        fs::write(&nav_path, navaids.replace("99\n", "3 not a navaid\n99\n"))
+            .unwrap();
+
+        assert!(matches!(
+            NavGraph::build_data_from_folder(&folder)
+                .err()
+                .as_ref()
+                .map(ParseError::without_location),
+            Some(ParseError::Parse { .. })
+        ));
+        let (nav_graph, diagnostics) =
+            NavGraph::build_data_from_folder_lenient(&folder).unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, nav_path);
+        assert!(matches!(
+            diagnostics[0].error.without_location(),
+            ParseError::Parse { .. }
+        ));
+        assert_eq!(diagnostics[1].file, awy_path);
+        assert!(matches!(
+            diagnostics[1].error.without_location(),
+            ParseError::ReferencedNonexistentWpt { wpt, .. } if wpt == "NOPE"
+        ));
+        // The good rows around the bad ones are all there.
+        let sfo = nav_graph.find_nav_entry("SFO")[0].0;
+        assert!(nav_graph.airway_find(sfo, "J3", "ALPHA").is_ok());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn locate_row_errors() {
+        let folder = write_test_data("locate");
+        let nav_path = folder.join("earth_nav.dat");
+        let navaids = fs::read_to_string(&nav_path).unwrap();
+        fs::write(
+            &nav_path,
+            navaids.replace(
+                "99\n",
+                "3  37.5 -122.3 13 11580 abc 17.0 SFO ENRT K2 X\n99\n",
+            ),
+        )
+        .unwrap();
+
+        let err = NavGraph::build_data_from_folder(&folder).err().unwrap();
+        let location = err.location().unwrap();
+        assert_eq!(location.file.as_ref(), Some(&nav_path));
+        // Two lines of header, a blank line, and the good row come first.
+        assert_eq!(location.line, 5);
+        assert_eq!(&location.text[location.span.clone()], "abc");
+        assert_eq!(location.column(), 25);
+        assert!(err.to_string().ends_with(&format!(
+            "5 | {}\n  | {}^^^",
+            location.text,
+            " ".repeat(24)
+        )));
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -9,9 +9,7 @@
 
 use std::io::{BufRead, Read};
 
-use itertools::Itertools;
-use petgraph::Graph;
-use snafu::ensure;
+use petgraph::{graph::NodeIndex, Graph};
 use winnow::{
     ascii::{dec_uint, space0, space1},
     combinator::{delimited, fail, preceded, separated, success},
@@ -23,12 +21,16 @@ use winnow::{
 };
 
 use crate::navdata::{
-    match_wpt_predicate, take_hstring_till, BadLastLineSnafu, Header,
-    InvalidAwyDirSnafu, NavEdge, NavEntry, ParseError, ParseSnafu, ParsedNodeRef,
-    ParsedNodeRefType, ReferencedNonexistentWptSnafu,
+    for_each_row, ident_index::IdentIndex, parse_line, take_hstring_till, Header,
+    InvalidAwyDirSnafu, NavEdge, NavEntry, ParseError,
+    ReferencedNonexistentWptSnafu, WptRef, WptType,
 };
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct AwyEdge {
     pub base_fl: u16,
     pub top_fl: u16,
@@ -36,90 +38,165 @@ pub struct AwyEdge {
     pub name: heapless::String<5>,
 }
 
+impl AwyEdge {
+    /// Whether this airway segment may be flown at `fl`, on an airway of `level`.
+    #[must_use]
+    pub fn usable_at(&self, fl: u16, level: AirwayLevel) -> bool {
+        (self.base_fl..=self.top_fl).contains(&fl)
+            && match level {
+                AirwayLevel::Any => true,
+                AirwayLevel::High => self.is_high,
+                AirwayLevel::Low => !self.is_high,
+            }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which airways a route search may use.
+pub enum AirwayLevel {
+    Any,
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which ways an airway segment may be flown.
+pub enum AirwayDirection {
+    /// From either waypoint to the other.
+    Both,
+    /// Only from the first waypoint to the second.
+    Forward,
+    /// Only from the second waypoint to the first.
+    Backward,
+}
+
+#[derive(Debug, Clone)]
+/// A row of the airway file: a segment between two waypoints, shared by one or more
+/// airways.
+pub struct AirwaySegment {
+    pub first: WptRef,
+    pub second: WptRef,
+    pub direction: AirwayDirection,
+    pub is_high: bool,
+    pub base_fl: u16,
+    pub top_fl: u16,
+    pub names: Vec<heapless::String<5>>,
+}
+
+#[derive(Debug, Clone)]
+/// A single leg of a route along airways.
+pub struct AirwayLeg {
+    pub from: NodeIndex,
+    pub to: NodeIndex,
+    pub airway: heapless::String<5>,
+    /// The great-circle distance of this leg, in nautical miles.
+    pub distance_nm: f64,
+}
+
+/// Parse airways, adding their segments to `nav_graph`. If `diagnostics` is given,
+/// bad rows are skipped, and their errors are added to it.
 pub(super) fn parse_file_buffered<F: Read + BufRead>(
     file: F,
     nav_graph: &mut Graph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+    diagnostics: Option<&mut Vec<ParseError>>,
 ) -> Result<Header, ParseError> {
     let mut lines = file.lines();
     let header = super::parse_header(|md_type| md_type == "AwyXP1100", &mut lines)?;
-    let mut lines = lines
-        .filter(|lin| lin.as_ref().map_or(true, |lin| !lin.is_empty()))
-        .peekable();
-
-    lines
-        .peeking_take_while(|l| l.as_ref().map_or(true, |l| l != "99"))
-        .try_for_each(|line| -> Result<(), ParseError> {
-            let parsed_edge =
-                parse_row.parse(Located::new(&line?)).map_err(|e| {
-                    ParseSnafu {
-                        rendered: e.to_string(),
-                        stage: "airway row",
-                    }
-                    .build()
-                })?;
-            let first_wpt_idx = nav_graph
-                .node_indices()
-                .find(match_wpt_predicate(&parsed_edge.first, nav_graph))
-                .ok_or_else(|| {
-                    ReferencedNonexistentWptSnafu {
-                        wpt: parsed_edge.second.ident.to_string(),
-                    }
-                    .build()
-                })?;
-            let second_wpt_idx = nav_graph
-                .node_indices()
-                .find(match_wpt_predicate(&parsed_edge.second, nav_graph))
-                .ok_or_else(|| {
-                    ReferencedNonexistentWptSnafu {
-                        wpt: parsed_edge.second.ident.to_string(),
-                    }
-                    .build()
-                })?;
-            for name in parsed_edge.names {
-                let awy_edge = AwyEdge {
-                    base_fl: parsed_edge.base_fl,
-                    top_fl: parsed_edge.top_fl,
-                    is_high: parsed_edge.is_high,
-                    name,
-                };
-                ensure!(
-                    matches!(parsed_edge.direction, 'B' | 'F' | 'N'),
-                    InvalidAwyDirSnafu {
-                        dir: parsed_edge.direction
-                    }
-                );
-                if matches!(parsed_edge.direction, 'N' | 'F') {
-                    nav_graph.add_edge(
-                        first_wpt_idx,
-                        second_wpt_idx,
-                        NavEdge::Airway(awy_edge.clone()),
-                    );
-                }
-                if matches!(parsed_edge.direction, 'N' | 'B') {
-                    nav_graph.add_edge(
-                        second_wpt_idx,
-                        first_wpt_idx,
-                        NavEdge::Airway(awy_edge.clone()),
-                    );
+    for_each_row(lines, diagnostics, |line_number, line| {
+        add_row(line_number, line, nav_graph, ident_index)
+    })?;
+    Ok(header)
+}
+
+/// Parse a row, and add its segments to `nav_graph`. Nothing is added if the row is
+/// bad.
+fn add_row(
+    line_number: usize,
+    line: &str,
+    nav_graph: &mut Graph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+) -> Result<(), ParseError> {
+    let parsed_edge = parse_line(line_number, line, "airway row", parse_row)?;
+    let direction = match parsed_edge.direction {
+        'N' => AirwayDirection::Both,
+        'F' => AirwayDirection::Forward,
+        'B' => AirwayDirection::Backward,
+        dir => return InvalidAwyDirSnafu { dir }.fail(),
+    };
+    add_segment(
+        &AirwaySegment {
+            first: parsed_edge.first,
+            second: parsed_edge.second,
+            direction,
+            is_high: parsed_edge.is_high,
+            base_fl: parsed_edge.base_fl,
+            top_fl: parsed_edge.top_fl,
+            names: parsed_edge.names,
+        },
+        nav_graph,
+        ident_index,
+    )
+}
+
+/// Add the edges of `segment` to `nav_graph`. Nothing is added if either waypoint
+/// doesn't exist.
+pub(super) fn add_segment(
+    segment: &AirwaySegment,
+    nav_graph: &mut Graph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+) -> Result<(), ParseError> {
+    let first_wpt_idx =
+        ident_index
+            .find_wpt(&segment.first, nav_graph)
+            .ok_or_else(|| {
+                ReferencedNonexistentWptSnafu {
+                    wpt: segment.first.ident.to_string(),
                 }
+                .build()
+            })?;
+    let second_wpt_idx = ident_index
+        .find_wpt(&segment.second, nav_graph)
+        .ok_or_else(|| {
+            ReferencedNonexistentWptSnafu {
+                wpt: segment.second.ident.to_string(),
             }
-            Ok(())
-        })?;
-
-    lines
-        .next()
-        .ok_or_else(|| ParseError::MissingLine)
-        .and_then(|last_line| {
-            let last_line = last_line?;
-            ensure!(last_line == "99", BadLastLineSnafu { last_line });
-            Ok(())
+            .build()
         })?;
-    Ok(header)
+    for name in &segment.names {
+        let awy_edge = AwyEdge {
+            base_fl: segment.base_fl,
+            top_fl: segment.top_fl,
+            is_high: segment.is_high,
+            name: name.clone(),
+        };
+        if matches!(
+            segment.direction,
+            AirwayDirection::Both | AirwayDirection::Forward
+        ) {
+            nav_graph.add_edge(
+                first_wpt_idx,
+                second_wpt_idx,
+                NavEdge::Airway(awy_edge.clone()),
+            );
+        }
+        if matches!(
+            segment.direction,
+            AirwayDirection::Both | AirwayDirection::Backward
+        ) {
+            nav_graph.add_edge(
+                second_wpt_idx,
+                first_wpt_idx,
+                NavEdge::Airway(awy_edge.clone()),
+            );
+        }
+    }
+    Ok(())
 }
 
 struct ParsedAwyEdge {
-    first: ParsedNodeRef,
-    second: ParsedNodeRef,
+    first: WptRef,
+    second: WptRef,
     direction: char,
     is_high: bool,
     base_fl: u16,
@@ -130,43 +207,33 @@ struct ParsedAwyEdge {
 fn parse_row(input: &mut Located<&str>) -> PResult<ParsedAwyEdge> {
     let first_ident = trace(
         "first waypoint ident",
-        take_hstring_till::<5, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let first_icao_region = trace(
         "first waypoint ICAO region",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let first_typ: ParsedNodeRefType = trace(
+    let first_typ: WptType = trace(
         "first waypoint type",
-        dispatch! {preceded(space1, dec_uint);
-            2 => success(ParsedNodeRefType::Vhf),
-            3 => success(ParsedNodeRefType::Ndb),
-            11 => success(ParsedNodeRefType::Fix),
-            _ => fail
-        },
+        preceded(space1, dec_uint).verify_map(WptType::from_code),
     )
     .parse_next(input)?;
 
     let second_ident = trace(
         "second waypoint ident",
-        take_hstring_till::<5, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let second_icao_region = trace(
         "second waypoint ICAO region",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let second_typ: ParsedNodeRefType = trace(
+    let second_typ: WptType = trace(
         "second waypoint type",
-        dispatch! {preceded(space1, dec_uint);
-            2 => success(ParsedNodeRefType::Vhf),
-            3 => success(ParsedNodeRefType::Ndb),
-            11 => success(ParsedNodeRefType::Fix),
-            _ => fail
-        },
+        preceded(space1, dec_uint).verify_map(WptType::from_code),
     )
     .parse_next(input)?;
 
@@ -189,18 +256,22 @@ fn parse_row(input: &mut Located<&str>) -> PResult<ParsedAwyEdge> {
         "section names",
         delimited(
             space1,
-            separated(1.., take_hstring_till::<5, _>(AsChar::is_space), "-"),
+            separated(
+                1..,
+                take_hstring_till::<5, _>(|c: char| c == '-' || c.is_space()),
+                "-",
+            ),
             space0,
         ),
     )
     .parse_next(input)?;
     Ok(ParsedAwyEdge {
-        first: ParsedNodeRef {
+        first: WptRef {
             ident: first_ident,
             icao_region: first_icao_region,
             typ: first_typ,
         },
-        second: ParsedNodeRef {
+        second: WptRef {
             ident: second_ident,
             icao_region: second_icao_region,
             typ: second_typ,
@@ -212,3 +283,24 @@ fn parse_row(input: &mut Located<&str>) -> PResult<ParsedAwyEdge> {
         names,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use winnow::{Located, Parser};
+
+    use crate::navdata::{airways::parse_row, WptType};
+
+    #[test]
+    fn parse_ndb_and_vhf_wpt_types() {
+        // Type 2 is an NDB, and type 3 a VHF navaid.
+        let row = parse_row
+            .parse(Located::new("OA K2 2 OAK K2 3 N 1 0 180 V25"))
+            .unwrap();
+        assert_eq!(row.first.ident, "OA");
+        assert_eq!(row.first.typ, WptType::Ndb);
+        assert_eq!(row.second.ident, "OAK");
+        assert_eq!(row.second.typ, WptType::Vhf);
+        assert!(!row.is_high);
+        assert_eq!(row.names, ["V25"]);
+    }
+}
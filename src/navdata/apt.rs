@@ -0,0 +1,729 @@
+#![allow(clippy::module_name_repetitions)]
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Structures and parser for X-Plane airport data (`apt.dat`), in the 1100, 1200, and
+//! 1300 formats. Only airport headers, runways, helipads, metadata, and ATC frequencies
+//! are read. Taxiways, signs, lighting objects, and the like are skipped.
+
+use std::io::{BufRead, Read};
+
+use heapless::String as HString;
+use num_enum::{FromPrimitive, IntoPrimitive};
+use petgraph::graph::NodeIndex;
+use winnow::{
+    ascii::{dec_int, dec_uint, float, space0, space1},
+    combinator::{dispatch, fail, opt, peek, preceded, rest, success},
+    prelude::*,
+    stream::AsChar,
+    token::take_till,
+    trace::trace,
+    Located,
+};
+
+use crate::{
+    navdata::{parse_line, take_hstring_till, BadBOMSnafu, ParseError, ParseSnafu},
+    radio::VhfFrequency,
+};
+
+#[derive(Debug, Clone)]
+/// An airport, seaplane base, or heliport.
+pub struct Airport {
+    pub kind: AirportKind,
+    pub elevation_ft: i32,
+    /// Whether this airport has a control tower. Only used by the X-Plane ATC system.
+    pub has_tower: bool,
+    /// The identifier of this airport. This is usually its ICAO code, but airports
+    /// without one may use a local code.
+    pub ident: HString<7>,
+    pub name: String,
+    pub runways: Vec<Runway>,
+    pub water_runways: Vec<WaterRunway>,
+    pub helipads: Vec<Helipad>,
+    /// Only present in the 1300 format, and not always then.
+    pub metadata: AirportMetadata,
+    pub frequencies: Vec<AtcFrequency>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AirportKind {
+    Land,
+    Seaplane,
+    Heliport,
+}
+
+#[derive(Debug, Clone)]
+pub struct Runway {
+    pub width_m: f32,
+    pub surface: SurfaceType,
+    /// 0 for no shoulder, 1 for asphalt, 2 for concrete.
+    pub shoulder_surface: u8,
+    /// 0.0 is smooth, 1.0 is very rough.
+    pub smoothness: f32,
+    pub centerline_lights: bool,
+    /// 0 for none, 2 for medium intensity.
+    pub edge_lights: u8,
+    pub distance_signs: bool,
+    pub ends: [RunwayEnd; 2],
+}
+
+#[derive(Debug, Clone)]
+pub struct RunwayEnd {
+    /// The runway number, like `16L`.
+    pub ident: HString<3>,
+    /// The position of the end of the runway, not taking any displaced threshold into
+    /// account.
+    pub lat: f64,
+    pub lon: f64,
+    pub displaced_threshold_m: f32,
+    pub blast_pad_m: f32,
+    /// The runway markings code, as given in the file.
+    pub markings: u8,
+    /// The approach lighting code, as given in the file.
+    pub approach_lights: u8,
+    pub tdz_lights: bool,
+    /// 0 for none, 1 for omni-directional, 2 for unidirectional.
+    pub reil: u8,
+    /// The localizer, glideslope, and threshold point navaids serving this runway end.
+    /// This is only filled in once the airport has been added to a
+    /// [`NavGraph`](crate::navdata::NavGraph).
+    pub navaids: Vec<NodeIndex>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WaterRunway {
+    pub width_m: f32,
+    pub buoys: bool,
+    pub ends: [WaterRunwayEnd; 2],
+}
+
+#[derive(Debug, Clone)]
+pub struct WaterRunwayEnd {
+    pub ident: HString<3>,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Helipad {
+    /// The designator of this helipad, like `H1`.
+    pub ident: HString<5>,
+    pub lat: f64,
+    pub lon: f64,
+    pub orientation_true: f32,
+    pub length_m: f32,
+    pub width_m: f32,
+    pub surface: SurfaceType,
+    /// The helipad markings code, as given in the file.
+    pub markings: u8,
+    pub shoulder_surface: u8,
+    pub smoothness: f32,
+    pub edge_lights: bool,
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, FromPrimitive, IntoPrimitive, PartialEq, Eq)]
+pub enum SurfaceType {
+    Asphalt = 1,
+    Concrete = 2,
+    TurfOrGrass = 3,
+    Dirt = 4,
+    Gravel = 5,
+    DryLakebed = 12,
+    Water = 13,
+    SnowOrIce = 14,
+    Transparent = 15,
+    #[num_enum(catch_all)]
+    /// xputils does not recognize this value. This includes the shades of asphalt
+    /// (20 to 38) and concrete (50 to 57) added in the 1200 format.
+    Unrecognized(u8),
+}
+
+#[derive(Debug, Clone, Default)]
+/// The `1302` metadata rows of an airport.
+pub struct AirportMetadata {
+    pub icao_code: Option<HString<4>>,
+    pub iata_code: Option<HString<3>>,
+    pub faa_code: Option<HString<5>>,
+    /// The ICAO region code, according to ICAO document No. 7910.
+    pub region_code: Option<HString<2>>,
+    /// The airport reference point.
+    pub datum_lat: Option<f64>,
+    pub datum_lon: Option<f64>,
+    pub transition_alt_ft: Option<u32>,
+    /// The transition level, as written in the file, like `FL180`.
+    pub transition_level: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AtcFrequency {
+    pub kind: AtcFrequencyKind,
+    pub freq: VhfFrequency,
+    /// The name of the facility, like `SEATTLE TWR`.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtcFrequencyKind {
+    /// ATIS, AWOS, or ASOS.
+    Recorded,
+    /// UNICOM or CTAF.
+    Unicom,
+    Clearance,
+    Ground,
+    Tower,
+    Approach,
+    Departure,
+}
+
+impl Airport {
+    #[must_use]
+    /// The ICAO code of this airport from its metadata, or its ident if it has none.
+    pub fn icao(&self) -> &str {
+        self.metadata
+            .icao_code
+            .as_ref()
+            .map_or(self.ident.as_str(), HString::as_str)
+    }
+
+    #[must_use]
+    /// The reference point of this airport. This is the datum from its metadata if
+    /// given, and otherwise the average position of its runway ends and helipads.
+    pub fn reference_point(&self) -> Option<(f64, f64)> {
+        if let (Some(lat), Some(lon)) =
+            (self.metadata.datum_lat, self.metadata.datum_lon)
+        {
+            return Some((lat, lon));
+        }
+        let points: Vec<_> = self
+            .runways
+            .iter()
+            .flat_map(|rwy| rwy.ends.iter().map(|end| (end.lat, end.lon)))
+            .chain(
+                self.water_runways
+                    .iter()
+                    .flat_map(|rwy| rwy.ends.iter().map(|end| (end.lat, end.lon))),
+            )
+            .chain(self.helipads.iter().map(|pad| (pad.lat, pad.lon)))
+            .collect();
+        if points.is_empty() {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = points.len() as f64;
+        let (lat, lon) = points
+            .iter()
+            .fold((0f64, 0f64), |(lat, lon), p| (lat + p.0, lon + p.1));
+        Some((lat / count, lon / count))
+    }
+
+    #[must_use]
+    /// Find a land runway end by its number, like `16L`. A leading `RW`, as used in
+    /// flight plans and CIFP data, is ignored.
+    pub fn runway_end(&self, ident: &str) -> Option<&RunwayEnd> {
+        let ident = ident.strip_prefix("RW").unwrap_or(ident);
+        self.runways
+            .iter()
+            .flat_map(|rwy| rwy.ends.iter())
+            .find(|end| end.ident == ident)
+    }
+}
+
+/// A row of interest in an `apt.dat` file.
+enum Row {
+    Airport(Box<Airport>),
+    Runway(Runway),
+    WaterRunway(WaterRunway),
+    Helipad(Helipad),
+    Metadata(String, Option<String>),
+    Frequency(AtcFrequency),
+    Skipped,
+}
+
+pub(super) fn parse_file_buffered<F: Read + BufRead>(
+    file: F,
+) -> Result<Vec<Airport>, ParseError> {
+    let mut lines = (1..).zip(file.lines());
+    let (_, bom) = lines.next().ok_or(ParseError::MissingLine)?;
+    let bom = bom?;
+    let bom = bom.trim_end();
+    if !matches!(bom, "I" | "A") {
+        return Err(BadBOMSnafu { bom }.build().at_line(1, bom));
+    }
+    let (_, version_line) = lines.next().ok_or(ParseError::MissingLine)?;
+    let version_line = version_line?;
+    if !matches!(version_line.get(..4), Some("1100" | "1200" | "1300")) {
+        return Err(ParseSnafu {
+            rendered: format!("Unsupported airport data version `{version_line}`."),
+            stage: "airport data version",
+        }
+        .build()
+        .at_line(2, &version_line));
+    }
+
+    let mut entries: Vec<Airport> = Vec::new();
+    for (line_number, line) in lines {
+        let line = line?;
+        // Files from custom scenery often have Windows line endings.
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "99" {
+            return Ok(entries);
+        }
+        add_row(&mut entries, line_number, line)
+            .map_err(|e| e.at_line(line_number, line))?;
+    }
+    Err(ParseError::MissingLine)
+}
+
+/// Parse a row, adding it to the airport it belongs to, the last one in `entries`.
+fn add_row(
+    entries: &mut Vec<Airport>,
+    line_number: usize,
+    line: &str,
+) -> Result<(), ParseError> {
+    let row = parse_line(line_number, line, "airport data row", parse_row)?;
+    if let Row::Airport(airport) = row {
+        entries.push(*airport);
+        return Ok(());
+    }
+    // Rows before the first airport have nothing to belong to.
+    let Some(airport) = entries.last_mut() else {
+        return Ok(());
+    };
+    match row {
+        Row::Runway(rwy) => airport.runways.push(rwy),
+        Row::WaterRunway(rwy) => airport.water_runways.push(rwy),
+        Row::Helipad(pad) => airport.helipads.push(pad),
+        Row::Metadata(key, value) => {
+            let value = value.as_deref().unwrap_or_default();
+            airport.metadata.set(&key, value).map_err(|()| {
+                ParseSnafu {
+                    rendered: format!(
+                        "Bad value `{value}` for metadata key `{key}` at airport `{}`.",
+                        airport.ident
+                    ),
+                    stage: "airport metadata",
+                }
+                .build()
+            })?;
+        },
+        Row::Frequency(freq) => airport.frequencies.push(freq),
+        Row::Airport(_) | Row::Skipped => {},
+    }
+    Ok(())
+}
+
+impl AirportMetadata {
+    /// Set the value of a metadata key. Unknown keys are ignored, and empty values
+    /// unset the key. Returns an [`Err`] if the value is malformed.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ()> {
+        fn opt_string(value: &str) -> Option<String> {
+            (!value.is_empty()).then(|| value.to_owned())
+        }
+        fn opt_hstring<const N: usize>(
+            value: &str,
+        ) -> Result<Option<HString<N>>, ()> {
+            if value.is_empty() {
+                Ok(None)
+            } else {
+                HString::try_from(value).map(Some)
+            }
+        }
+        fn opt_parse<T: std::str::FromStr>(value: &str) -> Result<Option<T>, ()> {
+            if value.is_empty() {
+                Ok(None)
+            } else {
+                value.parse().map(Some).map_err(|_| ())
+            }
+        }
+        match key {
+            "icao_code" => self.icao_code = opt_hstring(value)?,
+            "iata_code" => self.iata_code = opt_hstring(value)?,
+            "faa_code" => self.faa_code = opt_hstring(value)?,
+            "region_code" => self.region_code = opt_hstring(value)?,
+            "datum_lat" => self.datum_lat = opt_parse(value)?,
+            "datum_lon" => self.datum_lon = opt_parse(value)?,
+            "transition_alt" => self.transition_alt_ft = opt_parse(value)?,
+            "transition_level" => self.transition_level = opt_string(value),
+            "city" => self.city = opt_string(value),
+            "state" => self.state = opt_string(value),
+            "country" => self.country = opt_string(value),
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+fn parse_row(input: &mut Located<&str>) -> PResult<Row> {
+    trace(
+        "match row code and then parse type",
+        dispatch! {peek(dec_uint::<_, u16, _>);
+            1 | 16 | 17 => trace("airport header", parse_airport)
+                .map(|airport| Row::Airport(Box::new(airport))),
+            100 => trace("land runway", parse_runway).map(Row::Runway),
+            101 => trace("water runway", parse_water_runway).map(Row::WaterRunway),
+            102 => trace("helipad", parse_helipad).map(Row::Helipad),
+            1302 => trace("metadata", parse_metadata),
+            50..=56 | 1050..=1056 => trace("ATC frequency", parse_frequency)
+                .map(Row::Frequency),
+            _ => rest.map(|_| Row::Skipped),
+        },
+    )
+    .parse_next(input)
+}
+
+fn flag(input: &mut Located<&str>) -> PResult<bool> {
+    preceded(space1, dec_uint::<_, u8, _>)
+        .map(|flag| flag != 0)
+        .parse_next(input)
+}
+
+fn name(input: &mut Located<&str>) -> PResult<String> {
+    opt(preceded(space1, rest))
+        .map(|name: Option<&str>| name.unwrap_or_default().trim().to_owned())
+        .parse_next(input)
+}
+
+fn parse_airport(input: &mut Located<&str>) -> PResult<Airport> {
+    let kind = trace(
+        "row code",
+        dispatch! {dec_uint::<_, u16, _>;
+            1 => success(AirportKind::Land),
+            16 => success(AirportKind::Seaplane),
+            17 => success(AirportKind::Heliport),
+            _ => fail,
+        },
+    )
+    .parse_next(input)?;
+    let elevation_ft: i32 =
+        trace("elevation, feet", preceded(space1, dec_int)).parse_next(input)?;
+    let has_tower = trace("has control tower", flag).parse_next(input)?;
+    let _ = trace("deprecated field", preceded(space1, dec_uint::<_, u8, _>))
+        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space1, take_hstring_till::<7, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let name = trace("name", name).parse_next(input)?;
+    Ok(Airport {
+        kind,
+        elevation_ft,
+        has_tower,
+        ident,
+        name,
+        runways: Vec::new(),
+        water_runways: Vec::new(),
+        helipads: Vec::new(),
+        metadata: AirportMetadata::default(),
+        frequencies: Vec::new(),
+    })
+}
+
+fn surface(input: &mut Located<&str>) -> PResult<SurfaceType> {
+    preceded(space1, dec_uint::<_, u8, _>)
+        .map(SurfaceType::from)
+        .parse_next(input)
+}
+
+fn parse_runway(input: &mut Located<&str>) -> PResult<Runway> {
+    let _ = trace("row code", dec_uint::<_, u16, _>).parse_next(input)?;
+    let width_m: f32 =
+        trace("width, meters", preceded(space1, float)).parse_next(input)?;
+    let surface = trace("surface type", surface).parse_next(input)?;
+    let shoulder_surface: u8 =
+        trace("shoulder surface type", preceded(space1, dec_uint))
+            .parse_next(input)?;
+    let smoothness: f32 =
+        trace("smoothness", preceded(space1, float)).parse_next(input)?;
+    let centerline_lights = trace("centerline lights", flag).parse_next(input)?;
+    let edge_lights: u8 =
+        trace("edge lights", preceded(space1, dec_uint)).parse_next(input)?;
+    let distance_signs = trace("distance signs", flag).parse_next(input)?;
+    let first = trace("first end", parse_runway_end).parse_next(input)?;
+    let second = trace("second end", parse_runway_end).parse_next(input)?;
+    let _ = space0.parse_next(input)?;
+    Ok(Runway {
+        width_m,
+        surface,
+        shoulder_surface,
+        smoothness,
+        centerline_lights,
+        edge_lights,
+        distance_signs,
+        ends: [first, second],
+    })
+}
+
+fn parse_runway_end(input: &mut Located<&str>) -> PResult<RunwayEnd> {
+    let ident = trace(
+        "runway number",
+        preceded(space1, take_hstring_till::<3, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let lat: f64 = trace("latitude", preceded(space1, float)).parse_next(input)?;
+    let lon: f64 = trace("longitude", preceded(space1, float)).parse_next(input)?;
+    let displaced_threshold_m: f32 =
+        trace("displaced threshold, meters", preceded(space1, float))
+            .parse_next(input)?;
+    let blast_pad_m: f32 =
+        trace("blast pad, meters", preceded(space1, float)).parse_next(input)?;
+    let markings: u8 =
+        trace("markings", preceded(space1, dec_uint)).parse_next(input)?;
+    let approach_lights: u8 =
+        trace("approach lights", preceded(space1, dec_uint)).parse_next(input)?;
+    let tdz_lights = trace("touchdown zone lights", flag).parse_next(input)?;
+    let reil: u8 = trace("REIL", preceded(space1, dec_uint)).parse_next(input)?;
+    Ok(RunwayEnd {
+        ident,
+        lat,
+        lon,
+        displaced_threshold_m,
+        blast_pad_m,
+        markings,
+        approach_lights,
+        tdz_lights,
+        reil,
+        navaids: Vec::new(),
+    })
+}
+
+fn parse_water_runway_end(input: &mut Located<&str>) -> PResult<WaterRunwayEnd> {
+    let ident = trace(
+        "runway number",
+        preceded(space1, take_hstring_till::<3, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let lat: f64 = trace("latitude", preceded(space1, float)).parse_next(input)?;
+    let lon: f64 = trace("longitude", preceded(space1, float)).parse_next(input)?;
+    Ok(WaterRunwayEnd { ident, lat, lon })
+}
+
+fn parse_water_runway(input: &mut Located<&str>) -> PResult<WaterRunway> {
+    let _ = trace("row code", dec_uint::<_, u16, _>).parse_next(input)?;
+    let width_m: f32 =
+        trace("width, meters", preceded(space1, float)).parse_next(input)?;
+    let buoys = trace("buoys", flag).parse_next(input)?;
+    let first = trace("first end", parse_water_runway_end).parse_next(input)?;
+    let second = trace("second end", parse_water_runway_end).parse_next(input)?;
+    let _ = space0.parse_next(input)?;
+    Ok(WaterRunway {
+        width_m,
+        buoys,
+        ends: [first, second],
+    })
+}
+
+fn parse_helipad(input: &mut Located<&str>) -> PResult<Helipad> {
+    let _ = trace("row code", dec_uint::<_, u16, _>).parse_next(input)?;
+    let ident = trace(
+        "designator",
+        preceded(space1, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let lat: f64 = trace("latitude", preceded(space1, float)).parse_next(input)?;
+    let lon: f64 = trace("longitude", preceded(space1, float)).parse_next(input)?;
+    let orientation_true: f32 =
+        trace("orientation, true degrees", preceded(space1, float))
+            .parse_next(input)?;
+    let length_m: f32 =
+        trace("length, meters", preceded(space1, float)).parse_next(input)?;
+    let width_m: f32 =
+        trace("width, meters", preceded(space1, float)).parse_next(input)?;
+    let surface = trace("surface type", surface).parse_next(input)?;
+    let markings: u8 =
+        trace("markings", preceded(space1, dec_uint)).parse_next(input)?;
+    let shoulder_surface: u8 =
+        trace("shoulder surface type", preceded(space1, dec_uint))
+            .parse_next(input)?;
+    let smoothness: f32 =
+        trace("smoothness", preceded(space1, float)).parse_next(input)?;
+    let edge_lights = trace("edge lights", flag).parse_next(input)?;
+    let _ = space0.parse_next(input)?;
+    Ok(Helipad {
+        ident,
+        lat,
+        lon,
+        orientation_true,
+        length_m,
+        width_m,
+        surface,
+        markings,
+        shoulder_surface,
+        smoothness,
+        edge_lights,
+    })
+}
+
+fn parse_metadata(input: &mut Located<&str>) -> PResult<Row> {
+    let _ = trace("row code", dec_uint::<_, u16, _>).parse_next(input)?;
+    let key = trace(
+        "key",
+        preceded(space1, take_till(1.., |c: char| c.is_space())),
+    )
+    .parse_next(input)?;
+    let value = trace("value", name).parse_next(input)?;
+    Ok(Row::Metadata(
+        key.to_owned(),
+        (!value.is_empty()).then_some(value),
+    ))
+}
+
+fn parse_frequency(input: &mut Located<&str>) -> PResult<AtcFrequency> {
+    let row_code: u16 = trace("row code", dec_uint).parse_next(input)?;
+    // Rows 50 to 56 are from before 8.33 kHz spacing, and are in tens of kHz.
+    let (kind_code, is_10khz) = if row_code >= 1050 {
+        (row_code - 1050, false)
+    } else {
+        (row_code - 50, true)
+    };
+    let kind = match kind_code {
+        0 => AtcFrequencyKind::Recorded,
+        1 => AtcFrequencyKind::Unicom,
+        2 => AtcFrequencyKind::Clearance,
+        3 => AtcFrequencyKind::Ground,
+        4 => AtcFrequencyKind::Tower,
+        5 => AtcFrequencyKind::Approach,
+        6 => AtcFrequencyKind::Departure,
+        _ => unreachable!("Only rows 50-56 and 1050-1056 are dispatched here."),
+    };
+    let freq: u32 =
+        trace("frequency", preceded(space1, dec_uint)).parse_next(input)?;
+    let freq = if is_10khz {
+        VhfFrequency::from_10khz(freq)
+    } else {
+        VhfFrequency::from_khz(freq)
+    };
+    let name = trace("name", name).parse_next(input)?;
+    Ok(AtcFrequency { kind, freq, name })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Cursor};
+
+    use petgraph::graph::DiGraph;
+
+    use super::{parse_file_buffered, AirportKind, AtcFrequencyKind, SurfaceType};
+    use crate::{
+        navdata::{
+            nav::{Navaid, TypeSpecificData},
+            tests::nav_graph,
+            NavEntry,
+        },
+        radio::VhfFrequency,
+    };
+
+    const APT_DAT: &str = "I\r
+1300 Generated by WorldEditor 2.6.0r1\r
+
+1    433 1 0 KSEA Seattle-Tacoma Intl
+1302 city Seattle
+1302 icao_code KSEA
+1302 iata_code SEA
+1302 faa_code
+1302 region_code K1
+1302 datum_lat 47.449888889
+1302 datum_lon -122.311777778
+1302 transition_alt 18000
+100 45.72 1 0 0.25 1 2 1 16L 47.46375658 -122.30790700 0.00 304.80 3 11 1 1 34R 47.43178498 -122.30814010 0.00 0.00 3 11 1 1
+102 H1 47.44 -122.30 0.00 10.00 10.00 2 0 0 0.25 0
+110 2 0.25 150.29 A2 Taxiway
+111 47.44 -122.30
+1050 118000 SEA ATIS
+54 11990 SEATTLE TWR
+17    100 0 0 S01 Some Heliport
+102 H1 47.00 -122.00 90.00 15.00 15.00 1 0 0 0.25 1
+16      0 0 0 W55 Lake Union
+101 50.00 1 04 47.63 -122.33 22 47.64 -122.33
+99
+";
+
+    #[test]
+    fn parse_airports() {
+        let airports = parse_file_buffered(Cursor::new(APT_DAT)).unwrap();
+        let [ksea, heliport, seaplane] = airports.as_slice() else {
+            panic!("Expected three airports.");
+        };
+
+        assert_eq!(ksea.kind, AirportKind::Land);
+        assert_eq!(ksea.name, "Seattle-Tacoma Intl");
+        assert_eq!(ksea.icao(), "KSEA");
+        assert_eq!(ksea.metadata.faa_code, None);
+        assert_eq!(ksea.metadata.transition_alt_ft, Some(18000));
+        assert_eq!(
+            ksea.reference_point(),
+            Some((47.449_888_889, -122.311_777_778))
+        );
+        let rwy = &ksea.runways[0];
+        assert_eq!(rwy.surface, SurfaceType::Asphalt);
+        assert_eq!(rwy.ends[1].ident, "34R");
+        assert!(
+            (ksea.runway_end("RW16L").unwrap().blast_pad_m - 304.8).abs() < 1e-3
+        );
+        assert_eq!(ksea.helipads.len(), 1);
+        let freqs: Vec<_> = ksea
+            .frequencies
+            .iter()
+            .map(|f| (f.kind, f.freq, f.name.as_str()))
+            .collect();
+        assert_eq!(
+            freqs,
+            [
+                (
+                    AtcFrequencyKind::Recorded,
+                    VhfFrequency::from_khz(118_000),
+                    "SEA ATIS"
+                ),
+                (
+                    AtcFrequencyKind::Tower,
+                    VhfFrequency::from_10khz(11990),
+                    "SEATTLE TWR"
+                )
+            ]
+        );
+
+        assert_eq!(heliport.kind, AirportKind::Heliport);
+        assert_eq!(heliport.reference_point(), Some((47.0, -122.0)));
+        assert!(heliport.helipads[0].edge_lights);
+        assert_eq!(seaplane.kind, AirportKind::Seaplane);
+        assert_eq!(seaplane.water_runways[0].ends[1].ident, "22");
+    }
+
+    #[test]
+    fn link_runway_navaids() {
+        let mut graph = DiGraph::new();
+        let loc = graph.add_node(NavEntry::Navaid(Navaid {
+            lat: 47.43,
+            lon: -122.31,
+            elevation: 432,
+            icao_region: "K1".try_into().unwrap(),
+            ident: "ISNQ".try_into().unwrap(),
+            type_data: TypeSpecificData::Localizer {
+                is_with_ils: true,
+                freq: VhfFrequency::from_10khz(11030),
+                max_range: 18,
+                crs_mag: 163.0,
+                crs_true: 180.1,
+                airport_icao: "KSEA".try_into().unwrap(),
+                rwy: "16L".try_into().unwrap(),
+                name: "ILS-cat-III".to_owned(),
+            },
+        }));
+        let mut nav_graph = nav_graph(graph, HashMap::new());
+        nav_graph.add_airports(parse_file_buffered(Cursor::new(APT_DAT)).unwrap());
+
+        let ksea = nav_graph.airport("KSEA").unwrap();
+        assert_eq!(ksea.runway_end("16L").unwrap().navaids, [loc]);
+        assert!(ksea.runway_end("34R").unwrap().navaids.is_empty());
+        assert!(nav_graph.airport("S01").is_some());
+        assert_eq!(nav_graph.airports().count(), 3);
+    }
+}
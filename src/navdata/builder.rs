@@ -0,0 +1,224 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Building a [`NavGraph`] in memory, without any data files, such as for tests or
+//! synthetic data.
+
+use std::collections::HashMap;
+
+use petgraph::graph::DiGraph;
+
+use crate::navdata::{
+    airways::{self, AirwaySegment},
+    cifp::Procedure,
+    fix::Fix,
+    hold::{self, Edge as HoldEdge},
+    ident_index::IdentIndex,
+    layers::Provenances,
+    mora::MoraGrid,
+    nav::Navaid,
+    spatial::SpatialIndex,
+    DataVersion, Header, NavEdge, NavEntry, NavGraph, ParseError, WptRef,
+};
+
+#[derive(Debug, Clone)]
+/// A builder of a [`NavGraph`]. Airways and holds are checked against the entries
+/// when the graph is built, just as when loading from files.
+///
+/// Entries added here have no [`Provenance`](super::layers::Provenance).
+pub struct NavGraphBuilder {
+    header: Header,
+    entries: Vec<NavEntry>,
+    airways: Vec<AirwaySegment>,
+    holds: Vec<(WptRef, heapless::String<4>, HoldEdge)>,
+    procedures: HashMap<heapless::String<4>, Vec<Procedure>>,
+}
+
+impl Default for NavGraphBuilder {
+    fn default() -> Self {
+        Self {
+            header: Header {
+                version: DataVersion::XP1200,
+                cycle: 0,
+                build: 0,
+                copyright: String::new(),
+            },
+            entries: Vec::new(),
+            airways: Vec::new(),
+            holds: Vec::new(),
+            procedures: HashMap::new(),
+        }
+    }
+}
+
+impl NavGraphBuilder {
+    #[must_use]
+    /// An empty builder, for data of cycle 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Use `header` as the header of both the fixes and the navaids.
+    pub fn header(mut self, header: Header) -> Self {
+        self.header = header;
+        self
+    }
+
+    #[must_use]
+    /// Add a fix. Entries become nodes in the order they are added.
+    pub fn fix(mut self, fix: Fix) -> Self {
+        self.entries.push(NavEntry::Fix(fix));
+        self
+    }
+
+    #[must_use]
+    /// Add a navaid. Entries become nodes in the order they are added.
+    pub fn navaid(mut self, navaid: Navaid) -> Self {
+        self.entries.push(NavEntry::Navaid(navaid));
+        self
+    }
+
+    #[must_use]
+    /// Add an airway segment, as if it were a row of the airway file.
+    pub fn airway(mut self, segment: AirwaySegment) -> Self {
+        self.airways.push(segment);
+        self
+    }
+
+    #[must_use]
+    /// Add a hold at `hold_point` in `terminal_region`, as if it were a row of the
+    /// hold file.
+    pub fn hold(
+        mut self,
+        hold_point: WptRef,
+        terminal_region: heapless::String<4>,
+        hold: HoldEdge,
+    ) -> Self {
+        self.holds.push((hold_point, terminal_region, hold));
+        self
+    }
+
+    #[must_use]
+    /// Set the procedures of the airport `airport_icao`, replacing any already set.
+    pub fn procedures(
+        mut self,
+        airport_icao: heapless::String<4>,
+        procedures: Vec<Procedure>,
+    ) -> Self {
+        self.procedures.insert(airport_icao, procedures);
+        self
+    }
+
+    /// Build the graph.
+    /// # Errors
+    /// Returns an [`Err`] if an airway or hold refers to a waypoint that hasn't been
+    /// added.
+    pub fn build(self) -> Result<NavGraph, ParseError> {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::with_capacity(
+            self.entries.len(),
+            self.airways.len() + self.holds.len(),
+        );
+        for entry in self.entries {
+            graph.add_node(entry);
+        }
+        let ident_index = IdentIndex::build(&graph);
+        for segment in &self.airways {
+            airways::add_segment(segment, &mut graph, &ident_index)?;
+        }
+        for (hold_point, terminal_region, hold) in self.holds {
+            hold::add_hold(
+                &hold_point,
+                &terminal_region,
+                hold,
+                &mut graph,
+                &ident_index,
+            )?;
+        }
+        let spatial_index = SpatialIndex::build(&graph);
+        Ok(NavGraph {
+            fix_header: self.header.clone(),
+            navaids_header: self.header,
+            graph,
+            procedures: self.procedures,
+            ident_index,
+            spatial_index,
+            airports: HashMap::new(),
+            mora: MoraGrid::default(),
+            msas: HashMap::new(),
+            provenance: Provenances::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::navdata::{
+        airways::{AirwayDirection, AirwayLevel, AirwaySegment},
+        builder::NavGraphBuilder,
+        hold::{Direction, Edge as HoldEdge, LegLength},
+        tests::{enroute_fix, wpt},
+        NavEdge, ParseError,
+    };
+
+    fn segment(
+        first: &str,
+        second: &str,
+        direction: AirwayDirection,
+        name: &str,
+    ) -> AirwaySegment {
+        AirwaySegment {
+            first: wpt(first),
+            second: wpt(second),
+            direction,
+            is_high: true,
+            base_fl: 180,
+            top_fl: 450,
+            names: vec![name.try_into().unwrap()],
+        }
+    }
+
+    #[test]
+    fn build_in_memory() {
+        let hold = HoldEdge {
+            inbound_crs_mag: 90.0,
+            leg_length: LegLength::Minutes(1.0),
+            turn_direction: Direction::Right,
+            min_alt_ft: None,
+            max_alt_ft: None,
+            max_spd_kts: None,
+        };
+        let nav_graph = NavGraphBuilder::new()
+            .fix(enroute_fix("AAAAA", 0.0, 0.0))
+            .fix(enroute_fix("BBBBB", 0.0, 1.0))
+            .fix(enroute_fix("CCCCC", 0.0, 2.0))
+            .airway(segment("AAAAA", "BBBBB", AirwayDirection::Both, "J1"))
+            .airway(segment("BBBBB", "CCCCC", AirwayDirection::Forward, "J1"))
+            .hold(wpt("CCCCC"), "ENRT".try_into().unwrap(), hold)
+            .build()
+            .unwrap();
+
+        let a = nav_graph.find_nav_entry("AAAAA")[0].0;
+        let c = nav_graph.find_nav_entry("CCCCC")[0].0;
+        let route = nav_graph
+            .airway_route(a, c, 350, AirwayLevel::High)
+            .unwrap();
+        assert_eq!(route.len(), 2);
+        assert!(nav_graph.airway_route(c, a, 350, AirwayLevel::Any).is_err());
+        assert!(nav_graph
+            .graph()
+            .edges(c)
+            .any(|e| matches!(e.weight(), NavEdge::Hold(_))));
+        assert!(nav_graph.provenance(a).is_none());
+
+        let missing = NavGraphBuilder::new()
+            .fix(enroute_fix("AAAAA", 0.0, 0.0))
+            .airway(segment("AAAAA", "NOPE", AirwayDirection::Both, "J1"))
+            .build();
+        assert!(matches!(
+            missing,
+            Err(ParseError::ReferencedNonexistentWpt { wpt, .. }) if wpt == "NOPE"
+        ));
+    }
+}
@@ -0,0 +1,371 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! A binary cache of a parsed [`NavGraph`], so that the data files don't have to be
+//! parsed every time the sim starts.
+//!
+//! The cache is keyed by the cycle and build in the header of `earth_fix.dat`, and by
+//! the [fingerprint](super::source::NavSource::fingerprint) of every file the
+//! [`DataFolders`] supplied: the earth files and CIFP files of the layer they are taken
+//! from, and the user files. If any of those change, including which layer the earth
+//! files are taken from, the cache is stale, and the data is parsed again.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Error as IoError, Read, Write},
+    path::Path,
+};
+
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::{Deserialize, Serialize};
+use snafu::ensure;
+
+use crate::navdata::{
+    cifp::Procedure,
+    ident_index::IdentIndex,
+    layers::{DataFolders, Provenances, EARTH_FILES},
+    mora::MoraGrid,
+    msa::Msa,
+    parse_header,
+    source::NavSource,
+    spatial::SpatialIndex,
+    BadMagicSnafu, CacheError, Header, NavEdge, NavEntry, NavGraph, ParseError,
+    StaleSnafu, UnsupportedFormatSnafu,
+};
+
+const MAGIC: &[u8; 8] = b"XPUNAVC\0";
+/// Bumped whenever the layout of the cached data changes.
+const FORMAT_VERSION: u32 = 5;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheKey {
+    cycle: u16,
+    build: u32,
+    /// Each source file's path, as shown to users, and its fingerprint.
+    sources: Vec<(String, u64)>,
+}
+
+impl CacheKey {
+    fn for_folders(folders: &DataFolders) -> Result<Self, CacheError> {
+        let mut lines = folders.require("earth_fix.dat")?.reader.lines();
+        let header = parse_header(|_| true, &mut lines)?;
+
+        let mut sources = Vec::new();
+        let mut add = |source: &dyn NavSource, path: &str| -> Result<(), IoError> {
+            if let Some(fingerprint) = source.fingerprint(path)? {
+                sources.push((
+                    source.file_path(path).display().to_string(),
+                    fingerprint,
+                ));
+            }
+            Ok(())
+        };
+        if let Some((_, source)) = folders.earth_source()? {
+            for name in EARTH_FILES {
+                add(source, name)?;
+            }
+            for name in source.list("CIFP")?.unwrap_or_default() {
+                add(source, &format!("CIFP/{name}"))?;
+            }
+        }
+        if let Some(source) = folders.custom_data.as_deref() {
+            for name in ["user_fix.dat", "user_nav.dat"] {
+                add(source, name)?;
+            }
+        }
+        Ok(Self {
+            cycle: header.cycle,
+            build: header.build,
+            sources,
+        })
+    }
+}
+
+/// The parts of a [`NavGraph`] that are cached. The indices are rebuilt on load, and
+/// airports are not cached, as they don't come from the navdata folder.
+#[derive(Serialize)]
+struct CachedGraphRef<'a> {
+    fix_header: &'a Header,
+    navaids_header: &'a Header,
+    graph: &'a DiGraph<NavEntry, NavEdge>,
+    procedures: &'a HashMap<heapless::String<4>, Vec<Procedure>>,
+    mora: &'a MoraGrid,
+    msas: &'a HashMap<NodeIndex, Vec<Msa>>,
+    provenance: &'a Provenances,
+}
+
+/// Must have the same fields, in the same order, as [`CachedGraphRef`].
+#[derive(Deserialize)]
+struct CachedGraph {
+    fix_header: Header,
+    navaids_header: Header,
+    graph: DiGraph<NavEntry, NavEdge>,
+    procedures: HashMap<heapless::String<4>, Vec<Procedure>>,
+    mora: MoraGrid,
+    msas: HashMap<NodeIndex, Vec<Msa>>,
+    provenance: Provenances,
+}
+
+impl NavGraph {
+    /// Load navdata from the cache at `cache_path` if it is up to date with `folder`,
+    /// and otherwise parse it with [`NavGraph::build_data_from_folder`] and write a new
+    /// cache.
+    ///
+    /// A cache that is missing, stale, or unreadable, or that cannot be written, is not
+    /// an error; the data is simply parsed instead.
+    ///
+    /// # Errors
+    /// Returns an [`Err`] if the data has to be parsed, and parsing fails.
+    pub fn load_or_build(
+        folder: &Path,
+        cache_path: &Path,
+    ) -> Result<Self, ParseError> {
+        Self::load_or_build_from_folders(
+            &DataFolders::custom_data(folder),
+            cache_path,
+        )
+    }
+
+    /// Like [`load_or_build`](NavGraph::load_or_build), for layered data folders,
+    /// which are parsed with [`NavGraph::build_data_from_folders`].
+    ///
+    /// # Errors
+    /// Returns an [`Err`] if the data has to be parsed, and parsing fails.
+    pub fn load_or_build_from_folders(
+        folders: &DataFolders,
+        cache_path: &Path,
+    ) -> Result<Self, ParseError> {
+        if let Ok(nav_graph) = Self::read_cache_from_folders(folders, cache_path) {
+            return Ok(nav_graph);
+        }
+        // Taken before parsing, so that files changing in the meantime make the cache
+        // stale, rather than matching data parsed from their old contents.
+        let key = CacheKey::for_folders(folders);
+        let nav_graph = Self::build_data_from_folders(folders)?;
+        if let Ok(key) = key {
+            let _ = nav_graph.write_cache_with_key(&key, cache_path);
+        }
+        Ok(nav_graph)
+    }
+
+    /// Load navdata from the cache at `cache_path`, checking that it is up to date
+    /// with the source files in `folder`.
+    ///
+    /// # Errors
+    /// Returns an [`Err`] if the cache cannot be read, is of an unsupported format, or
+    /// is stale.
+    pub fn read_cache(folder: &Path, cache_path: &Path) -> Result<Self, CacheError> {
+        Self::read_cache_from_folders(&DataFolders::custom_data(folder), cache_path)
+    }
+
+    /// Like [`read_cache`](NavGraph::read_cache), checking that the cache is up to
+    /// date with the files the layered data folders supply.
+    ///
+    /// # Errors
+    /// Returns an [`Err`] if the cache cannot be read, is of an unsupported format, or
+    /// is stale.
+    pub fn read_cache_from_folders(
+        folders: &DataFolders,
+        cache_path: &Path,
+    ) -> Result<Self, CacheError> {
+        let mut reader = BufReader::new(File::open(cache_path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        ensure!(&magic == MAGIC, BadMagicSnafu);
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        ensure!(
+            version == FORMAT_VERSION,
+            UnsupportedFormatSnafu { version }
+        );
+
+        let key: CacheKey = bincode::deserialize_from(&mut reader)?;
+        ensure!(key == CacheKey::for_folders(folders)?, StaleSnafu);
+
+        let cached: CachedGraph = bincode::deserialize_from(&mut reader)?;
+        let ident_index = IdentIndex::build(&cached.graph);
+        let spatial_index = SpatialIndex::build(&cached.graph);
+        Ok(Self {
+            fix_header: cached.fix_header,
+            navaids_header: cached.navaids_header,
+            graph: cached.graph,
+            procedures: cached.procedures,
+            ident_index,
+            spatial_index,
+            airports: HashMap::new(),
+            mora: cached.mora,
+            msas: cached.msas,
+            provenance: cached.provenance,
+        })
+    }
+
+    /// Write this navdata to a cache at `cache_path`, keyed by the current state of
+    /// the source files in `folder`. This should be called right after parsing, as
+    /// changes to the source files since then will not be noticed.
+    ///
+    /// # Errors
+    /// Returns an [`Err`] if the source files cannot be inspected, or if the cache
+    /// cannot be written.
+    pub fn write_cache(
+        &self,
+        folder: &Path,
+        cache_path: &Path,
+    ) -> Result<(), CacheError> {
+        self.write_cache_from_folders(&DataFolders::custom_data(folder), cache_path)
+    }
+
+    /// Like [`write_cache`](NavGraph::write_cache), keyed by the files the layered
+    /// data folders supply.
+    ///
+    /// # Errors
+    /// Returns an [`Err`] if the source files cannot be inspected, or if the cache
+    /// cannot be written.
+    pub fn write_cache_from_folders(
+        &self,
+        folders: &DataFolders,
+        cache_path: &Path,
+    ) -> Result<(), CacheError> {
+        self.write_cache_with_key(&CacheKey::for_folders(folders)?, cache_path)
+    }
+
+    fn write_cache_with_key(
+        &self,
+        key: &CacheKey,
+        cache_path: &Path,
+    ) -> Result<(), CacheError> {
+        // Written next to the cache and moved into place, so that a partially written
+        // cache is never read.
+        let tmp_path = cache_path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, key)?;
+        bincode::serialize_into(
+            &mut writer,
+            &CachedGraphRef {
+                fix_header: &self.fix_header,
+                navaids_header: &self.navaids_header,
+                graph: &self.graph,
+                procedures: &self.procedures,
+                mora: &self.mora,
+                msas: &self.msas,
+                provenance: &self.provenance,
+            },
+        )?;
+        writer
+            .into_inner()
+            .map_err(std::io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(tmp_path, cache_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use crate::navdata::{
+        layers::{DataFolders, EARTH_FILES},
+        source::DirSource,
+        tests::write_test_data,
+        CacheError, NavGraph,
+    };
+
+    #[test]
+    fn cache_round_trip() {
+        let folder = write_test_data("cache");
+        let cache_path = folder.join("navdata.cache");
+
+        let parsed = NavGraph::load_or_build(&folder, &cache_path).unwrap();
+        let cached = NavGraph::read_cache(&folder, &cache_path).unwrap();
+        assert_eq!(cached.graph.node_count(), parsed.graph.node_count());
+        assert_eq!(cached.graph.edge_count(), parsed.graph.edge_count());
+        assert_eq!(cached.fix_header.cycle, 2401);
+        assert_eq!(cached.find_nav_entry("SFO").len(), 1);
+        assert_eq!(cached.nearest(37.0, -122.0, 1, None)[0].1.ident(), "ALPHA");
+
+        // Give the file system's timestamps a chance to move on.
+        thread::sleep(Duration::from_millis(20));
+        let hold_path = folder.join("earth_hold.dat");
+        let holds = fs::read_to_string(&hold_path).unwrap();
+        fs::write(&hold_path, holds.replace("99\n", "\n99\n")).unwrap();
+        assert!(matches!(
+            NavGraph::read_cache(&folder, &cache_path),
+            Err(CacheError::Stale { .. })
+        ));
+        NavGraph::load_or_build(&folder, &cache_path).unwrap();
+        NavGraph::read_cache(&folder, &cache_path).unwrap();
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn layered_cache_is_stale_when_any_layer_changes() {
+        let default_data = write_test_data("cache-layers-default");
+        let custom_data = write_test_data("cache-layers-custom");
+        let cache_path = custom_data.join("navdata.cache");
+        for name in EARTH_FILES {
+            fs::remove_file(custom_data.join(name)).unwrap();
+        }
+        let user_fix_path = custom_data.join("user_fix.dat");
+        let header = "I\n1200 Version - data cycle 2401, build 20240102, \
+                      metadata FixXP1200. Test data.\n\n";
+        fs::write(
+            &user_fix_path,
+            format!(
+                "{header} 39.000000000 -122.000000000 CHRLI ENRT K2 2105430\n99\n"
+            ),
+        )
+        .unwrap();
+        let folders = DataFolders {
+            default_data: Some(Box::new(DirSource::new(&default_data))),
+            custom_data: Some(Box::new(DirSource::new(&custom_data))),
+        };
+
+        let parsed =
+            NavGraph::load_or_build_from_folders(&folders, &cache_path).unwrap();
+        let cached =
+            NavGraph::read_cache_from_folders(&folders, &cache_path).unwrap();
+        assert_eq!(cached.graph.node_count(), parsed.graph.node_count());
+        assert_eq!(cached.find_nav_entry("CHRLI").len(), 1);
+        assert_eq!(cached.find_nav_entry("SFO").len(), 1);
+
+        // The earth files come from the default data, so changing them there makes
+        // the cache stale.
+        thread::sleep(Duration::from_millis(20));
+        let hold_path = default_data.join("earth_hold.dat");
+        let holds = fs::read_to_string(&hold_path).unwrap();
+        fs::write(&hold_path, holds.replace("99\n", "\n99\n")).unwrap();
+        assert!(matches!(
+            NavGraph::read_cache_from_folders(&folders, &cache_path),
+            Err(CacheError::Stale { .. })
+        ));
+        NavGraph::load_or_build_from_folders(&folders, &cache_path).unwrap();
+        NavGraph::read_cache_from_folders(&folders, &cache_path).unwrap();
+
+        // So does changing the user files in Custom Data.
+        thread::sleep(Duration::from_millis(20));
+        fs::write(&user_fix_path, format!("{header}99\n")).unwrap();
+        assert!(matches!(
+            NavGraph::read_cache_from_folders(&folders, &cache_path),
+            Err(CacheError::Stale { .. })
+        ));
+        NavGraph::load_or_build_from_folders(&folders, &cache_path).unwrap();
+
+        // And so does Custom Data starting to supply the earth files.
+        for name in EARTH_FILES {
+            fs::copy(default_data.join(name), custom_data.join(name)).unwrap();
+        }
+        assert!(matches!(
+            NavGraph::read_cache_from_folders(&folders, &cache_path),
+            Err(CacheError::Stale { .. })
+        ));
+
+        fs::remove_dir_all(default_data).unwrap();
+        fs::remove_dir_all(custom_data).unwrap();
+    }
+}
@@ -2,24 +2,461 @@
 //
 // SPDX-License-Identifier: Parity-7.0.0
 
-use std::str::FromStr;
+//! Parser and data structures for X-Plane CIFP files, which hold the SIDs, STARs,
+//! and approaches of a single airport.
+
+use std::{
+    io::{BufRead, Read},
+    str::FromStr,
+};
 
 use winnow::{
     ascii::{alpha1, dec_int, dec_uint, float, space0},
     combinator::{dispatch, fail, opt, rest, seq, terminated},
     prelude::*,
     stream::AsChar,
-    token::{none_of, take_until0},
+    token::{none_of, take, take_until0},
     trace::trace,
     Located,
 };
 
 use heapless::String as HString;
 
-use crate::navdata::{fixed_hstring_till, take_hstring_till};
+use snafu::{ensure, OptionExt};
+
+use crate::{
+    geo::wmm::{magnetic_to_true, true_to_magnetic},
+    navdata::{
+        fixed_hstring_till,
+        hold::{Direction, LegLength},
+        parse_line, recover, take_hstring_till, InvalidAltitudeSnafu,
+        MissingLegFieldSnafu, ParseError, UnknownConstraintDescriptorSnafu,
+        UnknownPathTerminatorSnafu, UnknownRouteTypeSnafu,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub enum ProcedureKind {
+    /// A Standard Instrument Departure.
+    Sid,
+    /// A Standard Terminal Arrival Route.
+    Star,
+    /// An approach procedure.
+    Approach,
+}
+
+#[derive(Debug, Clone)]
+/// A SID, STAR, or approach, with all of its transitions.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Procedure {
+    pub kind: ProcedureKind,
+    pub ident: HString<6>,
+    /// The transition altitude given by this procedure, if any.
+    pub trans_alt_ft_msl: Option<u32>,
+    /// Runway transitions. For a SID, these are flown before the common route; for a
+    /// STAR, after it. Approaches have none.
+    ///
+    /// Engine-out SID routes are included here, as they are runway-specific.
+    pub runway_transitions: Vec<Transition>,
+    /// Enroute transitions. For a SID, these are flown after the common route; for a
+    /// STAR, before it. For an approach, these are the approach transitions.
+    pub enroute_transitions: Vec<Transition>,
+    /// The common route, ordered by sequence number. For an approach, this is the
+    /// final approach, including the missed approach.
+    pub legs: Vec<Leg>,
+}
+
+impl Procedure {
+    /// Find a runway transition by its ident, e.g. `RW28L`.
+    #[must_use]
+    pub fn runway_transition(&self, ident: &str) -> Option<&Transition> {
+        self.runway_transitions.iter().find(|t| t.ident == ident)
+    }
+
+    /// Find an enroute or approach transition by its ident.
+    #[must_use]
+    pub fn enroute_transition(&self, ident: &str) -> Option<&Transition> {
+        self.enroute_transitions.iter().find(|t| t.ident == ident)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Transition {
+    pub ident: HString<5>,
+    /// The legs of this transition, ordered by sequence number.
+    pub legs: Vec<Leg>,
+}
+
+#[derive(Debug, Clone)]
+/// A reference to a waypoint or navaid, as given in CIFP data.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct WptRef {
+    pub ident: HString<5>,
+    pub icao_region: Option<HString<2>>,
+    /// The ARINC 424 section code of the database this waypoint is in.
+    pub section: Option<char>,
+    /// The ARINC 424 subsection code of the database this waypoint is in.
+    pub subsection: Option<char>,
+}
+
+#[derive(Debug, Clone)]
+/// A single leg of a procedure.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Leg {
+    pub sequence: u16,
+    /// The ARINC 424 route type this leg was coded with.
+    pub route_typ: char,
+    /// The ARINC 424 waypoint description code.
+    pub waypoint_desc_code: Option<HString<4>>,
+    pub turn_dir: Option<char>,
+    pub rnp: Option<f32>,
+    /// The path and terminator of this leg, with the data it needs to be flown.
+    pub path_term: PathTerminator,
+    /// Whether the turn direction must be respected before capturing the path.
+    pub turn_dir_valid: bool,
+    /// [`None`] if the leg has no constraint, or constrains an unknown or not
+    /// established altitude (`UNKNN`, `NESTB`).
+    pub altitude: Option<AltitudeConstraint>,
+    pub speed: Option<SpeedConstraint>,
+    pub vertical_angle: Option<f32>,
+    pub multiple_code_or_taa_sect_ident: Option<char>,
+    pub gps_fms_indicator: Option<char>,
+    pub rte_qual1: Option<char>,
+    pub rte_qual2: Option<char>,
+}
+
+impl Leg {
+    /// The fix this leg starts or terminates at, if it has one.
+    #[must_use]
+    pub fn fix(&self) -> Option<&WptRef> {
+        self.path_term.fix()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An altitude, normalized to feet.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Altitude {
+    pub ft: i32,
+    /// Whether this altitude was coded as a flight level, e.g. `FL180`.
+    pub is_flight_level: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An altitude constraint on a procedure leg, decoded from the ARINC 424 altitude
+/// description.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub enum AltitudeConstraint {
+    /// `@` or blank: At the altitude.
+    At(Altitude),
+    /// `+`, or `C` with the altitude in the second field: At or above the altitude.
+    AtOrAbove(Altitude),
+    /// `-`: At or below the altitude.
+    AtOrBelow(Altitude),
+    /// `B`: At or above `lower`, and at or below `upper`.
+    Between { upper: Altitude, lower: Altitude },
+    /// `G`: At `at` on the FAF, with the glideslope altitude at `glideslope`.
+    GlideslopeAt { at: Altitude, glideslope: Altitude },
+    /// `H`: At or above `at_or_above` on the FAF, with the glideslope altitude at
+    /// `glideslope`.
+    GlideslopeAtOrAbove {
+        at_or_above: Altitude,
+        glideslope: Altitude,
+    },
+    /// `I`: At `at` on the FACF, with the glideslope intercepted at `intercept`.
+    GlideslopeInterceptAt { at: Altitude, intercept: Altitude },
+    /// `J`: At or above `at_or_above` on the FACF, with the glideslope intercepted at
+    /// `intercept`.
+    GlideslopeInterceptAtOrAbove {
+        at_or_above: Altitude,
+        intercept: Altitude,
+    },
+    /// `V`: At or above `at_or_above` on a step-down fix, and at `on_vertical_angle`
+    /// on the coded vertical angle.
+    StepDownAtOrAbove {
+        at_or_above: Altitude,
+        on_vertical_angle: Altitude,
+    },
+    /// `X`: At `at` on a step-down fix, and at `on_vertical_angle` on the coded
+    /// vertical angle.
+    StepDownAt {
+        at: Altitude,
+        on_vertical_angle: Altitude,
+    },
+    /// `Y`: At or below `at_or_below` on a step-down fix, and at `on_vertical_angle`
+    /// on the coded vertical angle.
+    StepDownAtOrBelow {
+        at_or_below: Altitude,
+        on_vertical_angle: Altitude,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A speed constraint on a procedure leg, in knots of indicated airspeed.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub enum SpeedConstraint {
+    /// `@` or blank: At the speed.
+    At(u16),
+    /// `+`: At or above the speed.
+    AtOrAbove(u16),
+    /// `-`: At or below the speed.
+    AtOrBelow(u16),
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A course or heading, as coded in CIFP data.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Course {
+    pub degrees: f32,
+    /// Whether this course is referenced to true north, rather than magnetic north.
+    pub is_true: bool,
+}
+
+impl Course {
+    #[must_use]
+    /// The course relative to true north, given the magnetic variation where it is
+    /// flown, like from [`NavEntry::magnetic_variation`](super::NavEntry::magnetic_variation).
+    pub fn true_degrees(&self, variation: f64) -> f64 {
+        if self.is_true {
+            f64::from(self.degrees)
+        } else {
+            magnetic_to_true(f64::from(self.degrees), variation)
+        }
+    }
+
+    #[must_use]
+    /// The course relative to magnetic north, given the magnetic variation where it
+    /// is flown.
+    pub fn magnetic_degrees(&self, variation: f64) -> f64 {
+        if self.is_true {
+            true_to_magnetic(f64::from(self.degrees), variation)
+        } else {
+            f64::from(self.degrees)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An ARINC 424 path and terminator, with the fields that leg type uses.
+///
+/// Radials and DME distances (θ and ρ) are measured from the `navaid`.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub enum PathTerminator {
+    /// `IF`: Initial fix.
+    InitialFix { fix: WptRef },
+    /// `TF`: Track to a fix.
+    TrackToFix { fix: WptRef },
+    /// `CF`: Course to a fix.
+    CourseToFix {
+        fix: WptRef,
+        course: Course,
+        distance_nm: Option<f32>,
+        navaid: Option<WptRef>,
+    },
+    /// `DF`: Direct to a fix.
+    DirectToFix { fix: WptRef },
+    /// `FA`: Course from a fix to an altitude.
+    FixToAltitude { fix: WptRef, course: Course },
+    /// `FC`: Course from a fix for a distance.
+    FixToDistance {
+        fix: WptRef,
+        course: Course,
+        distance_nm: f32,
+    },
+    /// `FD`: Course from a fix to a DME distance.
+    FixToDmeDistance {
+        fix: WptRef,
+        course: Course,
+        navaid: WptRef,
+        distance_nm: f32,
+    },
+    /// `FM`: Course from a fix to a manual termination.
+    FixToManual { fix: WptRef, course: Course },
+    /// `CA`: Course to an altitude.
+    CourseToAltitude { course: Course },
+    /// `CD`: Course to a DME distance.
+    CourseToDmeDistance {
+        course: Course,
+        navaid: WptRef,
+        distance_nm: f32,
+    },
+    /// `CI`: Course to intercept the next leg.
+    CourseToIntercept { course: Course },
+    /// `CR`: Course to intercept a radial.
+    CourseToRadial {
+        course: Course,
+        navaid: WptRef,
+        radial_deg: f32,
+    },
+    /// `RF`: Constant radius arc to a fix.
+    RadiusToFix {
+        fix: WptRef,
+        center_fix: WptRef,
+        arc_radius_nm: f32,
+        turn: Direction,
+    },
+    /// `AF`: DME arc to a fix.
+    ArcToFix {
+        fix: WptRef,
+        navaid: WptRef,
+        /// The radial the arc begins at.
+        boundary_radial: Course,
+        /// The radial of `fix`.
+        radial_deg: f32,
+        arc_distance_nm: f32,
+        turn: Direction,
+    },
+    /// `VA`: Heading to an altitude.
+    HeadingToAltitude { heading: Course },
+    /// `VD`: Heading to a DME distance.
+    HeadingToDmeDistance {
+        heading: Course,
+        navaid: WptRef,
+        distance_nm: f32,
+    },
+    /// `VI`: Heading to intercept the next leg.
+    HeadingToIntercept { heading: Course },
+    /// `VM`: Heading to a manual termination.
+    HeadingToManual { heading: Course },
+    /// `VR`: Heading to intercept a radial.
+    HeadingToRadial {
+        heading: Course,
+        navaid: WptRef,
+        radial_deg: f32,
+    },
+    /// `PI`: Procedure turn.
+    ProcedureTurn {
+        fix: WptRef,
+        course: Course,
+        /// The maximum distance from `fix` the turn may be flown within.
+        distance_nm: f32,
+        turn: Direction,
+        navaid: Option<WptRef>,
+    },
+    /// `HA`: Hold, terminating at an altitude.
+    HoldToAltitude {
+        fix: WptRef,
+        course: Course,
+        leg_length: LegLength,
+        turn: Direction,
+    },
+    /// `HF`: Hold, terminating at the fix after one circuit.
+    HoldToFix {
+        fix: WptRef,
+        course: Course,
+        leg_length: LegLength,
+        turn: Direction,
+    },
+    /// `HM`: Hold, terminating manually.
+    HoldToManual {
+        fix: WptRef,
+        course: Course,
+        leg_length: LegLength,
+        turn: Direction,
+    },
+}
+
+impl PathTerminator {
+    /// The two-letter ARINC 424 code for this path and terminator.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            PathTerminator::InitialFix { .. } => "IF",
+            PathTerminator::TrackToFix { .. } => "TF",
+            PathTerminator::CourseToFix { .. } => "CF",
+            PathTerminator::DirectToFix { .. } => "DF",
+            PathTerminator::FixToAltitude { .. } => "FA",
+            PathTerminator::FixToDistance { .. } => "FC",
+            PathTerminator::FixToDmeDistance { .. } => "FD",
+            PathTerminator::FixToManual { .. } => "FM",
+            PathTerminator::CourseToAltitude { .. } => "CA",
+            PathTerminator::CourseToDmeDistance { .. } => "CD",
+            PathTerminator::CourseToIntercept { .. } => "CI",
+            PathTerminator::CourseToRadial { .. } => "CR",
+            PathTerminator::RadiusToFix { .. } => "RF",
+            PathTerminator::ArcToFix { .. } => "AF",
+            PathTerminator::HeadingToAltitude { .. } => "VA",
+            PathTerminator::HeadingToDmeDistance { .. } => "VD",
+            PathTerminator::HeadingToIntercept { .. } => "VI",
+            PathTerminator::HeadingToManual { .. } => "VM",
+            PathTerminator::HeadingToRadial { .. } => "VR",
+            PathTerminator::ProcedureTurn { .. } => "PI",
+            PathTerminator::HoldToAltitude { .. } => "HA",
+            PathTerminator::HoldToFix { .. } => "HF",
+            PathTerminator::HoldToManual { .. } => "HM",
+        }
+    }
+
+    /// The fix this leg starts or terminates at, if it has one.
+    #[must_use]
+    pub fn fix(&self) -> Option<&WptRef> {
+        match self {
+            PathTerminator::InitialFix { fix }
+            | PathTerminator::TrackToFix { fix }
+            | PathTerminator::CourseToFix { fix, .. }
+            | PathTerminator::DirectToFix { fix }
+            | PathTerminator::FixToAltitude { fix, .. }
+            | PathTerminator::FixToDistance { fix, .. }
+            | PathTerminator::FixToDmeDistance { fix, .. }
+            | PathTerminator::FixToManual { fix, .. }
+            | PathTerminator::RadiusToFix { fix, .. }
+            | PathTerminator::ArcToFix { fix, .. }
+            | PathTerminator::ProcedureTurn { fix, .. }
+            | PathTerminator::HoldToAltitude { fix, .. }
+            | PathTerminator::HoldToFix { fix, .. }
+            | PathTerminator::HoldToManual { fix, .. } => Some(fix),
+            PathTerminator::CourseToAltitude { .. }
+            | PathTerminator::CourseToDmeDistance { .. }
+            | PathTerminator::CourseToIntercept { .. }
+            | PathTerminator::CourseToRadial { .. }
+            | PathTerminator::HeadingToAltitude { .. }
+            | PathTerminator::HeadingToDmeDistance { .. }
+            | PathTerminator::HeadingToIntercept { .. }
+            | PathTerminator::HeadingToManual { .. }
+            | PathTerminator::HeadingToRadial { .. } => None,
+        }
+    }
+
+    /// Whether this leg type must be coded with an altitude.
+    fn requires_altitude(code: &str) -> bool {
+        matches!(code, "FA" | "CA" | "VA" | "HA")
+    }
+}
 
 #[derive(Debug, Clone)]
-enum Row {
+pub(super) enum Row {
     Sid(Box<SidStarApchRow>),
     Star(Box<SidStarApchRow>),
     Apch(Box<SidStarApchRow>),
@@ -30,7 +467,7 @@ enum Row {
 }
 
 #[derive(Debug, Clone)]
-struct SidStarApchRow {
+pub(super) struct SidStarApchRow {
     sequence: u16,
     route_typ: char,
     proc_ident: HString<6>,
@@ -55,9 +492,9 @@ struct SidStarApchRow {
     theta: Option<f64>,
     rho: Option<f64>,
     // Except when it isn't magnetic!
-    ob_mag_crs: Option<HString<4>>,
+    ob_mag_crs: Option<Course>,
     // Fuck you, ARINC!
-    rte_dist_from_or_hold_dist_time: Option<HString<4>>,
+    rte_dist_from_or_hold_dist_time: Option<DistOrTime>,
     alt_desc: Option<char>,
     alt_one: Option<HString<5>>,
     alt_two: Option<HString<5>>,
@@ -82,7 +519,7 @@ struct SidStarApchRow {
 }
 
 #[derive(Debug, Clone)]
-struct RwyRow {
+pub(super) struct RwyRow {
     rwy_ident: HString<5>,
     rwy_grad_1_1000_pct: Option<i16>,
     ellipsoidal_height_1_10m: Option<i64>,
@@ -96,6 +533,510 @@ struct RwyRow {
     displaced_thresh_dist_ft: u16,
 }
 
+/// Parses a CIFP file, and groups its rows into procedures.
+/// Unlike the other navdata files, CIFP files have no header, nor a terminating line.
+/// If `diagnostics` is given, bad rows are skipped, and their errors are added to it.
+pub(super) fn parse_file_buffered<F: Read + BufRead>(
+    file: F,
+    mut diagnostics: Option<&mut Vec<ParseError>>,
+) -> Result<Vec<Procedure>, ParseError> {
+    let mut procedures: Vec<Procedure> = Vec::new();
+    for (line_number, line) in (1..).zip(file.lines()) {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let added = parse_line(line_number, &line, "CIFP row", parse_row)
+            .and_then(|row| add_row(&mut procedures, row))
+            .map_err(|e| e.at_line(line_number, &line));
+        recover(added, diagnostics.as_deref_mut())?;
+    }
+    for procedure in &mut procedures {
+        procedure.legs.sort_by_key(|leg| leg.sequence);
+        for transition in procedure
+            .runway_transitions
+            .iter_mut()
+            .chain(procedure.enroute_transitions.iter_mut())
+        {
+            transition.legs.sort_by_key(|leg| leg.sequence);
+        }
+    }
+    Ok(procedures)
+}
+
+/// Where in a procedure a leg belongs.
+enum Segment {
+    RunwayTransition,
+    Common,
+    EnrouteTransition,
+}
+
+fn segment_for_route_typ(kind: ProcedureKind, route_typ: char) -> Option<Segment> {
+    match (kind, route_typ) {
+        (ProcedureKind::Sid, '0' | '1' | '4' | 'F' | 'T')
+        | (ProcedureKind::Star, '3' | '6' | '9' | 'S') => {
+            Some(Segment::RunwayTransition)
+        },
+        (ProcedureKind::Sid, '2' | '5' | 'M' | 'U')
+        | (ProcedureKind::Star, '2' | '5' | '8' | 'M')
+        | (
+            ProcedureKind::Approach,
+            'B' | 'D' | 'F' | 'G' | 'H' | 'I' | 'J' | 'L' | 'M' | 'N' | 'P' | 'Q'
+            | 'R' | 'S' | 'T' | 'U' | 'V' | 'W' | 'X' | 'Y'
+            // Missed approach legs are sequenced after the final approach legs.
+            | 'Z',
+        ) => Some(Segment::Common),
+        (ProcedureKind::Sid, '3' | '6' | 'S' | 'V')
+        | (ProcedureKind::Star, '1' | '4' | '7' | 'F')
+        | (ProcedureKind::Approach, 'A') => Some(Segment::EnrouteTransition),
+        _ => None,
+    }
+}
+
+/// Add the leg in `row`, if it has one, to its procedure in `procedures`. Rows are
+/// grouped by procedure, but legs may be out of order.
+fn add_row(procedures: &mut Vec<Procedure>, row: Row) -> Result<(), ParseError> {
+    let (kind, row) = match row {
+        Row::Sid(row) => (ProcedureKind::Sid, row),
+        Row::Star(row) => (ProcedureKind::Star, row),
+        Row::Apch(row) => (ProcedureKind::Approach, row),
+        Row::Rwy(_) | Row::PrDat => return Ok(()),
+    };
+    let proc_ident = row.proc_ident.clone();
+    let trans_alt_ft_msl = row.trans_alt_ft_msl;
+    let trans_ident = row.trans_ident.clone().unwrap_or_default();
+    // Both are checked before the procedure is looked up, so that a bad leg doesn't
+    // leave an empty procedure behind.
+    let segment = segment_for_route_typ(kind, row.route_typ).context(
+        UnknownRouteTypeSnafu {
+            route_typ: row.route_typ,
+            proc_ident: row.proc_ident.to_string(),
+        },
+    )?;
+    let leg = leg_from_row(*row)?;
+    // Rows are grouped by procedure, so the match is almost always the last one.
+    let proc_pos = procedures
+        .iter()
+        .rposition(|p| p.kind == kind && p.ident == proc_ident)
+        .unwrap_or_else(|| {
+            procedures.push(Procedure {
+                kind,
+                ident: proc_ident,
+                trans_alt_ft_msl: None,
+                runway_transitions: Vec::new(),
+                enroute_transitions: Vec::new(),
+                legs: Vec::new(),
+            });
+            procedures.len() - 1
+        });
+    let procedure = &mut procedures[proc_pos];
+    if procedure.trans_alt_ft_msl.is_none() {
+        procedure.trans_alt_ft_msl = trans_alt_ft_msl;
+    }
+    let transitions = match segment {
+        Segment::Common => {
+            procedure.legs.push(leg);
+            return Ok(());
+        },
+        Segment::RunwayTransition => &mut procedure.runway_transitions,
+        Segment::EnrouteTransition => &mut procedure.enroute_transitions,
+    };
+    if let Some(transition) =
+        transitions.iter_mut().rfind(|t| t.ident == trans_ident)
+    {
+        transition.legs.push(leg);
+    } else {
+        transitions.push(Transition {
+            ident: trans_ident,
+            legs: vec![leg],
+        });
+    }
+    Ok(())
+}
+
+fn wpt_ref<const N: usize>(
+    ident: Option<HString<N>>,
+    icao_region: Option<HString<2>>,
+    section: Option<char>,
+    subsection: Option<char>,
+) -> Option<WptRef> {
+    ident.map(|ident| WptRef {
+        // UNWRAP: All CIFP waypoint idents are at most 5 characters.
+        ident: HString::try_from(ident.as_str()).unwrap(),
+        icao_region,
+        section,
+        subsection,
+    })
+}
+
+/// A route distance or hold leg length, as coded in CIFP data.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum DistOrTime {
+    Nm(f32),
+    Minutes(f32),
+}
+
+/// Courses are coded in tenths of a degree magnetic, or in whole degrees true when
+/// suffixed with `T`.
+fn parse_course(s: &str) -> Option<Course> {
+    if let Some(degrees) = s.strip_suffix('T') {
+        Some(Course {
+            degrees: degrees.parse::<u16>().ok()?.into(),
+            is_true: true,
+        })
+    } else {
+        Some(Course {
+            degrees: f32::from(s.parse::<u16>().ok()?) / 10f32,
+            is_true: false,
+        })
+    }
+}
+
+/// Distances are coded in tenths of a nautical mile, or in tenths of a minute when
+/// prefixed with `T`.
+fn parse_dist_or_time(s: &str) -> Option<DistOrTime> {
+    if let Some(time) = s.strip_prefix('T') {
+        Some(DistOrTime::Minutes(
+            f32::from(time.parse::<u16>().ok()?) / 10f32,
+        ))
+    } else {
+        Some(DistOrTime::Nm(f32::from(s.parse::<u16>().ok()?) / 10f32))
+    }
+}
+
+/// An altitude field of a procedure leg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AltitudeField {
+    Known(Altitude),
+    /// An unknown or not established altitude, coded as `UNKNN` or `NESTB`.
+    Unknown,
+}
+
+/// Altitudes are coded in feet, or as a flight level when prefixed with `FL`.
+/// Returns [`None`] if the altitude is malformed.
+fn parse_altitude(s: &str) -> Option<AltitudeField> {
+    let s = s.trim();
+    if matches!(s, "UNKNN" | "NESTB") {
+        return Some(AltitudeField::Unknown);
+    }
+    let altitude = if let Some(fl) = s.strip_prefix("FL") {
+        Altitude {
+            ft: fl.trim().parse::<i32>().ok()? * 100,
+            is_flight_level: true,
+        }
+    } else {
+        Altitude {
+            ft: s.parse().ok()?,
+            is_flight_level: false,
+        }
+    };
+    Some(AltitudeField::Known(altitude))
+}
+
+/// Decodes an ARINC 424 altitude description.
+///
+/// On failure, returns the name of the missing altitude field, or [`None`] if the
+/// descriptor is not recognized.
+fn altitude_constraint(
+    desc: Option<char>,
+    one: Option<Altitude>,
+    two: Option<Altitude>,
+) -> Result<Option<AltitudeConstraint>, Option<&'static str>> {
+    let one = || one.ok_or(Some("altitude"));
+    let two = || two.ok_or(Some("second altitude"));
+    let constraint = match desc {
+        None if one().is_err() => return Ok(None),
+        None | Some('@') => AltitudeConstraint::At(one()?),
+        Some('+') => AltitudeConstraint::AtOrAbove(one()?),
+        Some('-') => AltitudeConstraint::AtOrBelow(one()?),
+        Some('B') => AltitudeConstraint::Between {
+            upper: one()?,
+            lower: two()?,
+        },
+        Some('C') => AltitudeConstraint::AtOrAbove(two()?),
+        Some('G') => AltitudeConstraint::GlideslopeAt {
+            at: one()?,
+            glideslope: two()?,
+        },
+        Some('H') => AltitudeConstraint::GlideslopeAtOrAbove {
+            at_or_above: one()?,
+            glideslope: two()?,
+        },
+        Some('I') => AltitudeConstraint::GlideslopeInterceptAt {
+            at: one()?,
+            intercept: two()?,
+        },
+        Some('J') => AltitudeConstraint::GlideslopeInterceptAtOrAbove {
+            at_or_above: one()?,
+            intercept: two()?,
+        },
+        Some('V') => AltitudeConstraint::StepDownAtOrAbove {
+            at_or_above: one()?,
+            on_vertical_angle: two()?,
+        },
+        Some('X') => AltitudeConstraint::StepDownAt {
+            at: one()?,
+            on_vertical_angle: two()?,
+        },
+        Some('Y') => AltitudeConstraint::StepDownAtOrBelow {
+            at_or_below: one()?,
+            on_vertical_angle: two()?,
+        },
+        Some(_) => return Err(None),
+    };
+    Ok(Some(constraint))
+}
+
+fn turn_direction(turn_dir: Option<char>) -> Option<Direction> {
+    match turn_dir {
+        Some('L') => Some(Direction::Left),
+        Some('R') => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+#[allow(clippy::too_many_lines, clippy::cast_possible_truncation)]
+fn leg_from_row(row: SidStarApchRow) -> Result<Leg, ParseError> {
+    let path_term_code = row.path_and_term.clone().unwrap_or_default();
+    let missing = |field: &'static str| {
+        MissingLegFieldSnafu {
+            path_term: path_term_code.to_string(),
+            field,
+            proc_ident: row.proc_ident.to_string(),
+            sequence: row.sequence,
+        }
+        .build()
+    };
+    ensure!(
+        !path_term_code.is_empty(),
+        MissingLegFieldSnafu {
+            path_term: "??",
+            field: "path and terminator",
+            proc_ident: row.proc_ident.to_string(),
+            sequence: row.sequence,
+        }
+    );
+
+    let fix = wpt_ref(
+        row.wpt_ident.clone(),
+        row.wpt_icao_region.clone(),
+        row.section,
+        row.subsection,
+    );
+    let navaid = wpt_ref(
+        row.rcmd_navaid.clone(),
+        row.rcmd_navaid_icao_region.clone(),
+        row.rcmd_navaid_section,
+        row.rcmd_navaid_subsection,
+    );
+    let center_fix = wpt_ref(
+        row.center_fix_or_proc_turn.clone(),
+        row.center_fix_icao_region.clone(),
+        row.center_fix_section,
+        row.center_fix_subsection,
+    );
+    let turn = turn_direction(row.turn_dir);
+    let course = row.ob_mag_crs;
+    let distance_nm = match row.rte_dist_from_or_hold_dist_time {
+        Some(DistOrTime::Nm(nm)) => Some(nm),
+        _ => None,
+    };
+    let leg_length = match row.rte_dist_from_or_hold_dist_time {
+        Some(DistOrTime::Nm(nm)) => Some(LegLength::DME(nm)),
+        Some(DistOrTime::Minutes(minutes)) => Some(LegLength::Minutes(minutes)),
+        None => None,
+    };
+    let theta = row.theta.map(|theta| theta as f32);
+    let rho = row.rho.map(|rho| rho as f32);
+
+    let altitude_field = |alt: Option<&str>| {
+        alt.map(|alt| {
+            parse_altitude(alt).context(InvalidAltitudeSnafu {
+                alt: alt.trim(),
+                proc_ident: row.proc_ident.to_string(),
+                sequence: row.sequence,
+            })
+        })
+        .transpose()
+    };
+    let alt_one = altitude_field(row.alt_one.as_deref())?;
+    let alt_two = altitude_field(row.alt_two.as_deref())?;
+    // A constraint on an unknown altitude can't be flown, so the leg is kept without
+    // one.
+    let is_unknown_altitude =
+        [alt_one, alt_two].contains(&Some(AltitudeField::Unknown));
+    let known = |alt| match alt {
+        Some(AltitudeField::Known(alt)) => Some(alt),
+        _ => None,
+    };
+    let altitude = if is_unknown_altitude {
+        None
+    } else {
+        altitude_constraint(row.alt_desc, known(alt_one), known(alt_two)).map_err(
+            |field| match field {
+                Some(field) => missing(field),
+                None => UnknownConstraintDescriptorSnafu {
+                    // UNWRAP: Only a descriptor can be unknown.
+                    desc: row.alt_desc.unwrap(),
+                    proc_ident: row.proc_ident.to_string(),
+                    sequence: row.sequence,
+                }
+                .build(),
+            },
+        )?
+    };
+    if PathTerminator::requires_altitude(&path_term_code)
+        && altitude.is_none()
+        && !is_unknown_altitude
+    {
+        return Err(missing("altitude"));
+    }
+    let speed = match (row.speed_lim_desc, row.speed_lim) {
+        (_, None) => None,
+        (None | Some('@'), Some(kts)) => Some(SpeedConstraint::At(kts)),
+        (Some('+'), Some(kts)) => Some(SpeedConstraint::AtOrAbove(kts)),
+        (Some('-'), Some(kts)) => Some(SpeedConstraint::AtOrBelow(kts)),
+        (Some(desc), Some(_)) => {
+            return UnknownConstraintDescriptorSnafu {
+                desc,
+                proc_ident: row.proc_ident.to_string(),
+                sequence: row.sequence,
+            }
+            .fail()
+        },
+    };
+
+    let fix = || fix.clone().ok_or_else(|| missing("fix"));
+    let course = || course.ok_or_else(|| missing("course"));
+    let distance_nm = || distance_nm.ok_or_else(|| missing("distance"));
+    let navaid_req = || navaid.clone().ok_or_else(|| missing("recommended navaid"));
+    let turn = || turn.ok_or_else(|| missing("turn direction"));
+    let theta = || theta.ok_or_else(|| missing("theta"));
+    let leg_length = || leg_length.ok_or_else(|| missing("hold distance/time"));
+
+    let path_term = match path_term_code.as_str() {
+        "IF" => PathTerminator::InitialFix { fix: fix()? },
+        "TF" => PathTerminator::TrackToFix { fix: fix()? },
+        "CF" => PathTerminator::CourseToFix {
+            fix: fix()?,
+            course: course()?,
+            distance_nm: distance_nm().ok(),
+            navaid: navaid.clone(),
+        },
+        "DF" => PathTerminator::DirectToFix { fix: fix()? },
+        "FA" => PathTerminator::FixToAltitude {
+            fix: fix()?,
+            course: course()?,
+        },
+        "FC" => PathTerminator::FixToDistance {
+            fix: fix()?,
+            course: course()?,
+            distance_nm: distance_nm()?,
+        },
+        "FD" => PathTerminator::FixToDmeDistance {
+            fix: fix()?,
+            course: course()?,
+            navaid: navaid_req()?,
+            distance_nm: distance_nm()?,
+        },
+        "FM" => PathTerminator::FixToManual {
+            fix: fix()?,
+            course: course()?,
+        },
+        "CA" => PathTerminator::CourseToAltitude { course: course()? },
+        "CD" => PathTerminator::CourseToDmeDistance {
+            course: course()?,
+            navaid: navaid_req()?,
+            distance_nm: distance_nm()?,
+        },
+        "CI" => PathTerminator::CourseToIntercept { course: course()? },
+        "CR" => PathTerminator::CourseToRadial {
+            course: course()?,
+            navaid: navaid_req()?,
+            radial_deg: theta()?,
+        },
+        "RF" => PathTerminator::RadiusToFix {
+            fix: fix()?,
+            center_fix: center_fix.ok_or_else(|| missing("center fix"))?,
+            arc_radius_nm: row
+                .arc_radius_nm
+                .map(|radius| radius as f32)
+                .ok_or_else(|| missing("arc radius"))?,
+            turn: turn()?,
+        },
+        "AF" => PathTerminator::ArcToFix {
+            fix: fix()?,
+            navaid: navaid_req()?,
+            boundary_radial: course()?,
+            radial_deg: theta()?,
+            arc_distance_nm: rho.ok_or_else(|| missing("rho"))?,
+            turn: turn()?,
+        },
+        "VA" => PathTerminator::HeadingToAltitude { heading: course()? },
+        "VD" => PathTerminator::HeadingToDmeDistance {
+            heading: course()?,
+            navaid: navaid_req()?,
+            distance_nm: distance_nm()?,
+        },
+        "VI" => PathTerminator::HeadingToIntercept { heading: course()? },
+        "VM" => PathTerminator::HeadingToManual { heading: course()? },
+        "VR" => PathTerminator::HeadingToRadial {
+            heading: course()?,
+            navaid: navaid_req()?,
+            radial_deg: theta()?,
+        },
+        "PI" => PathTerminator::ProcedureTurn {
+            fix: fix()?,
+            course: course()?,
+            distance_nm: distance_nm()?,
+            turn: turn()?,
+            navaid: navaid.clone(),
+        },
+        "HA" => PathTerminator::HoldToAltitude {
+            fix: fix()?,
+            course: course()?,
+            leg_length: leg_length()?,
+            turn: turn()?,
+        },
+        "HF" => PathTerminator::HoldToFix {
+            fix: fix()?,
+            course: course()?,
+            leg_length: leg_length()?,
+            turn: turn()?,
+        },
+        "HM" => PathTerminator::HoldToManual {
+            fix: fix()?,
+            course: course()?,
+            leg_length: leg_length()?,
+            turn: turn()?,
+        },
+        _ => {
+            return UnknownPathTerminatorSnafu {
+                path_term: path_term_code.to_string(),
+                proc_ident: row.proc_ident.to_string(),
+            }
+            .fail()
+        },
+    };
+
+    Ok(Leg {
+        sequence: row.sequence,
+        route_typ: row.route_typ,
+        waypoint_desc_code: row.waypoint_desc_code,
+        turn_dir: row.turn_dir,
+        rnp: row.rnp,
+        path_term,
+        turn_dir_valid: row.turn_dir_valid == Some('Y'),
+        altitude,
+        speed,
+        vertical_angle: row.vertical_angle,
+        multiple_code_or_taa_sect_ident: row.multiple_code_or_taa_sect_ident,
+        gps_fms_indicator: row.gps_fms_indicator,
+        rte_qual1: row.rte_qual1,
+        rte_qual2: row.rte_qual2,
+    })
+}
+
 fn parse_row(input: &mut Located<&str>) -> PResult<Row> {
     dispatch! {terminated(alpha1, ':');
         "SID" => parse_ssa_row.map(Row::Sid),
@@ -169,22 +1110,22 @@ fn parse_ssa_row(input: &mut Located<&str>) -> PResult<Box<SidStarApchRow>> {
             _: (space0, ','),
             arc_radius_nm: trace("arc radius, 1/1000 nm",
                 opt(float)
-                .map(|aro| aro.map(|ar: f64| ar * 1000f64))
+                .map(|aro| aro.map(|ar: f64| ar / 1000f64))
             ),
             _: (space0, ','),
             theta: trace("θ, 1/10°",
                 opt(float)
-                .map(|th| th.map(|th: f64| th * 10f64))
+                .map(|th| th.map(|th: f64| th / 10f64))
             ),
             _: (space0, ','),
             rho: trace("ρ, 1/10nm",
                 opt(float)
-                .map(|rho| rho.map(|rho: f64| rho * 10f64))
+                .map(|rho| rho.map(|rho: f64| rho / 10f64))
             ),
             _: (space0, ','),
-            ob_mag_crs: trace("outbound magnetic course", fixed_hstring_till(comma)).map(handle_empty),
+            ob_mag_crs: trace("outbound magnetic course", opt(take(4usize).verify_map(parse_course))),
             _: (space0, ','),
-            rte_dist_from_or_hold_dist_time: trace("rte dist. from/hold dist/time", fixed_hstring_till(comma)).map(handle_empty),
+            rte_dist_from_or_hold_dist_time: trace("rte dist. from/hold dist/time", opt(take(4usize).verify_map(parse_dist_or_time))),
             _: (space0, ','),
             alt_desc: trace("altitude descriptor", opt(none_of([' ', ',']))),
             _: (space0, ','),
@@ -264,7 +1205,13 @@ mod tests {
     use snafu::{OptionExt, Report, ResultExt, Whatever};
     use winnow::{Located, Parser};
 
-    use crate::navdata::cifp::{parse_row, Row};
+    use crate::navdata::{
+        cifp::{
+            altitude_constraint, parse_altitude, parse_file_buffered, parse_row,
+            Altitude, AltitudeConstraint, AltitudeField, ProcedureKind, Row,
+        },
+        ParseError,
+    };
 
     #[test]
     fn parse_a_bunch_of_rows() -> Report<Whatever> {
@@ -331,4 +1278,177 @@ mod tests {
             Ok(())
         })
     }
+
+    fn ssa_row(
+        kind: &str,
+        seq: u16,
+        route_typ: char,
+        trans: &str,
+        wpt: &str,
+        path_term: &str,
+    ) -> String {
+        format!(
+            "{kind}:{seq:03},{route_typ},TEST1,{trans:<5},{wpt:<5},K2,P,C,E   , ,   ,\
+             {path_term}, ,    ,  , , ,      ,    ,    ,    ,    , ,     ,     ,18000, ,   ,\
+             ,,     ,  , , , , , , ;"
+        )
+    }
+
+    #[test]
+    fn group_rows_into_procedures() -> Report<Whatever> {
+        Report::capture(|| {
+            let cifp = [
+                ssa_row("SID", 20, '1', "RW01L", "BBBBB", "TF"),
+                ssa_row("SID", 10, '1', "RW01L", "AAAAA", "TF"),
+                ssa_row("SID", 30, '2', "", "CCCCC", "TF"),
+                ssa_row("SID", 40, '3', "DDDDD", "DDDDD", "TF"),
+                ssa_row("STAR", 10, '2', "", "EEEEE", "TF"),
+                ssa_row("APPCH", 20, 'Z', "", "GGGGG", "TF"),
+                ssa_row("APPCH", 10, 'R', "", "FFFFF", "TF"),
+                "PRDAT:,,,,,;".to_owned(),
+            ]
+            .join("\n");
+            let procedures = parse_file_buffered(cifp.as_bytes(), None)
+                .map_err(|e| e.to_string())
+                .whatever_context("failed to parse CIFP rows")?;
+
+            assert_eq!(procedures.len(), 3);
+            let sid = &procedures[0];
+            assert_eq!(sid.kind, ProcedureKind::Sid);
+            assert_eq!(sid.trans_alt_ft_msl, Some(18000));
+            let rwy_trans = sid
+                .runway_transition("RW01L")
+                .whatever_context("missing runway transition")?;
+            let idents: Vec<_> = rwy_trans
+                .legs
+                .iter()
+                .filter_map(|leg| leg.fix().map(|fix| fix.ident.as_str()))
+                .collect();
+            assert_eq!(idents, ["AAAAA", "BBBBB"]);
+            assert_eq!(sid.legs.len(), 1);
+            assert!(sid.enroute_transition("DDDDD").is_some());
+            assert_eq!(procedures[1].kind, ProcedureKind::Star);
+            let approach = &procedures[2];
+            assert_eq!(approach.kind, ProcedureKind::Approach);
+            let idents: Vec<_> = approach
+                .legs
+                .iter()
+                .filter_map(|leg| leg.fix().map(|fix| fix.ident.as_str()))
+                .collect();
+            assert_eq!(idents, ["FFFFF", "GGGGG"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn leg_missing_required_field() {
+        let cifp = ssa_row("SID", 10, '2', "", "AAAAA", "CF");
+        let err = parse_file_buffered(cifp.as_bytes(), None).unwrap_err();
+        assert!(
+            matches!(
+                err.without_location(),
+                ParseError::MissingLegField { path_term, field, .. }
+                    if path_term == "CF" && *field == "course"
+            ),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn decode_altitude_constraints() {
+        let fl180 = Altitude {
+            ft: 18000,
+            is_flight_level: true,
+        };
+        assert_eq!(parse_altitude("FL180"), Some(AltitudeField::Known(fl180)));
+        let ft = Altitude {
+            ft: 5000,
+            is_flight_level: false,
+        };
+        assert_eq!(parse_altitude("05000"), Some(AltitudeField::Known(ft)));
+        assert_eq!(parse_altitude("UNKNN"), Some(AltitudeField::Unknown));
+        assert_eq!(parse_altitude("NESTB"), Some(AltitudeField::Unknown));
+        assert_eq!(parse_altitude("5O00"), None);
+        assert_eq!(
+            altitude_constraint(Some('B'), Some(fl180), Some(ft)),
+            Ok(Some(AltitudeConstraint::Between {
+                upper: fl180,
+                lower: ft,
+            }))
+        );
+        assert_eq!(altitude_constraint(None, None, None), Ok(None));
+        assert_eq!(
+            altitude_constraint(Some('+'), None, None),
+            Err(Some("altitude"))
+        );
+        assert_eq!(altitude_constraint(Some('Q'), Some(ft), None), Err(None));
+    }
+
+    /// Codes an altitude descriptor and the first altitude into an [`ssa_row`].
+    fn with_altitude(row: &str, desc: char, alt: &str) -> String {
+        row.replacen(
+            " ,     ,     ,18000",
+            &format!("{desc},{alt:<5},     ,18000"),
+            1,
+        )
+    }
+
+    #[test]
+    fn keep_legs_with_unknown_altitudes() -> Report<Whatever> {
+        Report::capture(|| {
+            let cifp = [
+                with_altitude(
+                    &ssa_row("SID", 10, '2', "", "AAAAA", "TF"),
+                    '+',
+                    "UNKNN",
+                ),
+                with_altitude(
+                    &ssa_row("SID", 20, '2', "", "BBBBB", "TF"),
+                    ' ',
+                    "NESTB",
+                ),
+                with_altitude(
+                    &ssa_row("SID", 30, '2', "", "CCCCC", "TF"),
+                    '+',
+                    "05000",
+                ),
+            ]
+            .join("\n");
+            let procedures = parse_file_buffered(cifp.as_bytes(), None)
+                .map_err(|e| e.to_string())
+                .whatever_context("failed to parse CIFP rows")?;
+
+            let legs = &procedures[0].legs;
+            assert_eq!(legs.len(), 3);
+            assert_eq!(legs[0].altitude, None);
+            assert_eq!(legs[1].altitude, None);
+            assert_eq!(
+                legs[2].altitude,
+                Some(AltitudeConstraint::AtOrAbove(Altitude {
+                    ft: 5000,
+                    is_flight_level: false,
+                }))
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn leg_with_malformed_altitude() {
+        for desc in ['+', ' '] {
+            let cifp = with_altitude(
+                &ssa_row("SID", 10, '2', "", "AAAAA", "TF"),
+                desc,
+                "5O00",
+            );
+            let err = parse_file_buffered(cifp.as_bytes(), None).unwrap_err();
+            assert!(
+                matches!(
+                    err.without_location(),
+                    ParseError::InvalidAltitude { alt, .. } if alt == "5O00"
+                ),
+                "unexpected error: {err}"
+            );
+        }
+    }
 }
@@ -6,9 +6,8 @@
 //! Structures and parsers for XPFIX1200 and XPFIX1101.
 //! Older versions of navdata are not supported.
 
-use std::io::{BufRead, Read};
+use std::io::{BufRead, BufReader, Read, Write};
 
-use itertools::Itertools;
 use snafu::ensure;
 use winnow::{
     ascii::{dec_uint, float, space0, space1},
@@ -20,17 +19,15 @@ use winnow::{
 };
 
 use crate::navdata::{
-    take_hstring_till, BadLastLineSnafu, DataVersion, Header, ParseError,
-    ParseSnafu, UnsupportedVersionSnafu,
+    parse_line, take_hstring_till, DataVersion, Header, ParseError, Rows,
+    UnsupportedVersionSnafu, UnwritableVersionSnafu, WriteError,
 };
 
-#[derive(Debug)]
-pub(super) struct Fixes {
-    pub header: Header,
-    pub entries: Vec<Fix>,
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct Fix {
     pub lat: f64,
     pub lon: f64,
@@ -49,8 +46,12 @@ pub struct Fix {
     pub printed_spoken_name: Option<heapless::String<32>>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// First column of the "Waypoint Type" field.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum FixType {
     /// ARC Center Fix Waypoint
     ArcCenterFix,
@@ -76,8 +77,12 @@ pub enum FixType {
     Unrecognized(u8),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Second column of the "Waypoint Type" field.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum FixFunction {
     /// Final Approach Fix
     FinalAppFix,
@@ -121,8 +126,12 @@ pub enum FixFunction {
     Unrecognized(u8),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// What procedures this fix is on, if any.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum FixProcedure {
     /// A Standard Instrument Departure.
     SID,
@@ -137,9 +146,22 @@ pub enum FixProcedure {
     Unrecognized(u8),
 }
 
-pub(super) fn parse_file_buffered<F: Read + BufRead>(
-    file: F,
-) -> Result<Fixes, ParseError> {
+/// Parse the header of a fix file, returning a streaming reader of its rows. This works
+/// for both `earth_fix.dat` and `user_fix.dat`.
+///
+/// # Errors
+/// Returns an [`Err`] if there is an I/O error, or if the header is malformed or of an
+/// unsupported version.
+pub fn parse_file<F: Read>(file: F) -> Result<Rows<BufReader<F>, Fix>, ParseError> {
+    parse_file_buffered(BufReader::new(file))
+}
+
+/// Like [`parse_file`], for an already buffered reader.
+///
+/// # Errors
+/// Returns an [`Err`] if there is an I/O error, or if the header is malformed or of an
+/// unsupported version.
+pub fn parse_file_buffered<F: BufRead>(file: F) -> Result<Rows<F, Fix>, ParseError> {
     let mut lines = file.lines();
     let header = super::parse_header(
         |md_type| md_type == "FixXP1100" || md_type == "FixXP1200",
@@ -151,51 +173,27 @@ pub(super) fn parse_file_buffered<F: Read + BufRead>(
             version: header.version,
         }
     );
-    let mut lines = lines
-        .filter(|lin| lin.as_ref().map_or(true, |lin| !lin.is_empty()))
-        .peekable();
-
-    #[allow(clippy::let_and_return)]
-    // Have to let and return to fix a lifetime error.
-    let entries: Result<Vec<_>, ParseError> = lines
-        .peeking_take_while(|l| l.as_ref().map_or(true, |l| l != "99"))
-        .map(|line| {
-            let line = line?;
-            let ret = trace("fix row", parse_row)
-                .parse(Located::new(&line))
-                .map_err(|e| {
-                    ParseSnafu {
-                        rendered: e.to_string(),
-                        stage: "fix row",
-                    }
-                    .build()
-                });
-            ret
-        })
-        .collect();
-    let entries = entries?;
-    lines
-        .next()
-        .ok_or_else(|| ParseError::MissingLine)
-        .and_then(|last_line| {
-            let last_line = last_line?;
-            ensure!(last_line == "99", BadLastLineSnafu { last_line });
-            Ok(())
-        })?;
-    Ok(Fixes { header, entries })
+    Ok(Rows::new(header, lines, |line_number, line| {
+        parse_line(line_number, line, "fix row", parse_row)
+    }))
 }
 
 fn parse_row(input: &mut Located<&str>) -> PResult<Fix> {
     let lat: f64 = trace("latitude", preceded(space0, float)).parse_next(input)?;
     let lon: f64 = trace("longitude", preceded(space1, float)).parse_next(input)?;
-    let ident = trace("ident", take_hstring_till::<8, _>(AsChar::is_space))
-        .parse_next(input)?;
-    let terminal_area =
-        trace("terminal area", take_hstring_till::<4, _>(AsChar::is_space))
-            .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<8, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let terminal_area = trace(
+        "terminal area",
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let icao_region = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let funny_flags: u32 =
@@ -229,6 +227,7 @@ fn parse_wpt_flags(
         b'N' => FixType::NdbAsWpt,
         b'O' => FixType::OuterMarker,
         b'R' => FixType::NamedIntx,
+        b'U' => FixType::UnchartedAwyIntx,
         b'V' => FixType::VfrWpt,
         b'W' => FixType::RnavWpt,
         b' ' => FixType::Unspecified,
@@ -277,3 +276,181 @@ fn parse_wpt_flags(
     };
     (typ, func, proc)
 }
+
+/// Write `fixes` in the `earth_fix.dat` format, in the version given by `header`. This is
+/// also the format of `user_fix.dat`.
+///
+/// Printed/spoken names are only written in XPFIX1200, as XPFIX1101 has no such column.
+///
+/// # Errors
+/// Returns an [`Err`] if the version in `header` is not XPFIX1200 or XPFIX1101, or if
+/// writing fails.
+pub fn write_file<W: Write>(
+    header: &Header,
+    fixes: &[Fix],
+    mut out: W,
+) -> Result<(), WriteError> {
+    let md_type = match header.version {
+        DataVersion::XP1101 => "FixXP1100",
+        DataVersion::XP1200 => "FixXP1200",
+        version => return UnwritableVersionSnafu { version }.fail(),
+    };
+    super::write_header(header, md_type, &mut out)?;
+    for fix in fixes {
+        write!(
+            out,
+            "{:.9} {:.9} {} {} {} {}",
+            fix.lat,
+            fix.lon,
+            fix.ident,
+            fix.terminal_region,
+            fix.icao_region,
+            encode_wpt_flags(fix.typ, fix.func, fix.proc),
+        )?;
+        match &fix.printed_spoken_name {
+            Some(name) if header.version == DataVersion::XP1200 => {
+                writeln!(out, " {name}")?;
+            },
+            _ => writeln!(out)?,
+        }
+    }
+    writeln!(out, "99")?;
+    Ok(())
+}
+
+/// The inverse of [`parse_wpt_flags`]. Whether a fix is in a terminal area is implied by
+/// the function, for the codes that depend on it.
+fn encode_wpt_flags(typ: FixType, func: FixFunction, proc: FixProcedure) -> u32 {
+    let typ = match typ {
+        FixType::ArcCenterFix => b'A',
+        FixType::NamedIntxAndRnav => b'C',
+        FixType::UnnamedChartedIntx => b'I',
+        FixType::MiddleMarker => b'M',
+        FixType::NdbAsWpt => b'N',
+        FixType::OuterMarker => b'O',
+        FixType::NamedIntx => b'R',
+        FixType::UnchartedAwyIntx => b'U',
+        FixType::VfrWpt => b'V',
+        FixType::RnavWpt => b'W',
+        FixType::Unspecified => b' ',
+        FixType::Unrecognized(b) => b,
+    };
+    let func = match func {
+        FixFunction::FinalAppFix => b'A',
+        FixFunction::InitialAndFinalAppFix => b'B',
+        FixFunction::FinalAppCrsFix => b'C',
+        FixFunction::IntermediateAppFix => b'D',
+        FixFunction::OffRouteIntxFAA => b'E',
+        FixFunction::OffRouteIntx => b'F',
+        FixFunction::InitialAppFix => b'I',
+        FixFunction::FinalAppCrsFixAtIAF => b'K',
+        FixFunction::FinalAppCrsFixAtIF => b'L',
+        FixFunction::MissedAppFix => b'M',
+        FixFunction::InitialAppFixAndMAF => b'N',
+        FixFunction::OceanicEntryExitWpt => b'O',
+        FixFunction::UnnamedStepdownFix | FixFunction::PitchAndCatchPoint => b'P',
+        FixFunction::NamedStepdownFix | FixFunction::AacaaAndSuaWpt => b'S',
+        FixFunction::FirUirCtrlIntx => b'U',
+        FixFunction::LatLonFullDegIntx => b'V',
+        FixFunction::LatLonHalfDegIntx => b'W',
+        FixFunction::Unspecified => b' ',
+        FixFunction::Unrecognized(b) => b,
+    };
+    let proc = match proc {
+        FixProcedure::SID => b'D',
+        FixProcedure::STAR => b'E',
+        FixProcedure::Approach => b'F',
+        FixProcedure::Multiple => b'Z',
+        FixProcedure::Unspecified => b' ',
+        FixProcedure::Unrecognized(b) => b,
+    };
+    u32::from_le_bytes([typ, func, proc, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::{parse_file, write_file, FixFunction, FixType};
+    use crate::navdata::ParseError;
+
+    #[test]
+    fn round_trip_fixes() {
+        let data = "I
+1200 Version - data cycle 2401, build 20240101, metadata FixXP1200. Test data.
+
+ 37.000000000 -122.000000000 ALPHA ENRT K2 2105431
+ 47.123456789 -122.300000000 CF16L KSEA K1 4604704 CEDAR FINAL
+ -33.500000000  151.250000000 BRAVO YSSY YM 4542546
+99
+";
+        let (header, fixes) =
+            parse_file(data.as_bytes()).unwrap().collect_all().unwrap();
+        assert_eq!(fixes.len(), 3);
+        assert_eq!(fixes[1].typ, FixType::Unspecified);
+
+        let mut written = Vec::new();
+        write_file(&header, &fixes, &mut written).unwrap();
+        let (reheader, refixes) = parse_file(written.as_slice())
+            .unwrap()
+            .collect_all()
+            .unwrap();
+        assert_eq!(reheader, header);
+        assert_eq!(refixes, fixes);
+        assert_eq!(refixes[2].func, FixFunction::UnnamedStepdownFix);
+    }
+
+    #[test]
+    fn stream_fixes_without_terminator() {
+        let data = "I
+1200 Version - data cycle 2401, build 20240101, metadata FixXP1200. Test data.
+
+ 37.000000000 -122.000000000 ALPHA ENRT K2 2105431
+";
+        let mut rows = parse_file(data.as_bytes()).unwrap();
+        assert_eq!(rows.header().cycle, 2401);
+        assert_eq!(rows.next().unwrap().unwrap().ident, "ALPHA");
+        assert!(matches!(rows.next(), Some(Err(ParseError::MissingLine))));
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn collect_many_fixes_in_order() {
+        let mut data = "I
+1200 Version - data cycle 2401, build 20240101, metadata FixXP1200. Test data.
+"
+        .to_owned();
+        for i in 0..10_000 {
+            if i == 5000 || i == 9000 {
+                data.push_str(" not a fix\n");
+            } else {
+                writeln!(data, " 37.0 -122.0 F{i} ENRT K2 2105431").unwrap();
+            }
+        }
+        data.push_str("99\n");
+
+        let err = parse_file(data.as_bytes())
+            .unwrap()
+            .collect_all()
+            .err()
+            .unwrap();
+        assert_eq!(err.location().unwrap().line, 5003);
+
+        let mut diagnostics = Vec::new();
+        let (_, fixes) = parse_file(data.as_bytes())
+            .unwrap()
+            .collect_lenient(&mut diagnostics)
+            .unwrap();
+        let lines: Vec<_> = diagnostics
+            .iter()
+            .map(|e| e.location().unwrap().line)
+            .collect();
+        assert_eq!(lines, [5003, 9003]);
+        assert_eq!(fixes.len(), 9998);
+        let numbers: Vec<u32> = fixes
+            .iter()
+            .map(|fix| fix.ident[1..].parse().unwrap())
+            .collect();
+        assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,544 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Reader and writer for X-Plane `.fms` flight plans, as used by the default GPS and
+//! FMS. Only the `1100 Version` format is supported.
+
+use std::io::{BufRead, Error as IoError, Lines, Read, Write};
+
+use heapless::String as HString;
+use snafu::{ensure, OptionExt};
+use winnow::{
+    ascii::{dec_uint, float, space0, space1},
+    combinator::{preceded, terminated},
+    stream::AsChar,
+    token::take_till,
+    trace::trace,
+    Located, PResult, Parser,
+};
+
+use crate::navdata::{
+    parse_line,
+    route::{ProcedureUse, ResolvedRoute, RouteLeg, RoutePoint, Via},
+    BadBOMSnafu, NavEntryKind, NavGraph, ParseError, ParseSnafu,
+    ReferencedNonexistentWptSnafu, WptType,
+};
+
+#[derive(Debug, Clone, Default)]
+/// A flight plan, as stored in an `.fms` file.
+pub struct FlightPlan {
+    /// The AIRAC cycle the plan was made with.
+    pub cycle: Option<u16>,
+    pub route: ResolvedRoute,
+    /// The departure runway, like `RW28L`.
+    pub departure_runway: Option<HString<5>>,
+    /// The arrival runway, like `RW24R`.
+    pub arrival_runway: Option<HString<5>>,
+    pub approach: Option<ProcedureUse>,
+    /// The latitude and longitude of the departure airport, if known.
+    pub departure_position: Option<(f64, f64)>,
+    /// The latitude and longitude of the arrival airport, if known.
+    pub arrival_position: Option<(f64, f64)>,
+    /// The altitude of each of the legs of `route`, in feet. Missing or zero altitudes
+    /// mean no altitude has been set.
+    pub altitudes_ft: Vec<f64>,
+}
+
+impl From<ResolvedRoute> for FlightPlan {
+    fn from(route: ResolvedRoute) -> Self {
+        Self {
+            route,
+            ..Self::default()
+        }
+    }
+}
+
+/// Waypoint type codes that are not shared with the airway and hold files.
+const AIRPORT: u8 = 1;
+const LAT_LON: u8 = 28;
+
+struct FmsRow<'a> {
+    typ: u8,
+    ident: &'a str,
+    via: &'a str,
+    altitude_ft: f64,
+    lat: f64,
+    lon: f64,
+}
+
+/// Read a flight plan, resolving its waypoints against `nav_graph`.
+///
+/// Waypoints are resolved to the entry of the right type with the same ident that is
+/// nearest to the position given in the file. Airports along the route, other than the
+/// departure and arrival, are resolved to [`RoutePoint::Airport`]s at their reference
+/// point if they have been added to `nav_graph`, or else to the nearest entry with the
+/// same ident.
+///
+/// # Errors
+/// An error will be returned if the file is malformed, or if a waypoint cannot be
+/// found in `nav_graph`.
+pub fn parse_file<F: Read + BufRead>(
+    file: F,
+    nav_graph: &NavGraph,
+) -> Result<FlightPlan, ParseError> {
+    let mut lines = PlanLines {
+        lines: file.lines(),
+        line_number: 0,
+        line: String::new(),
+    };
+    // Every error is about the line last read.
+    read_plan(&mut lines, nav_graph)
+        .map_err(|e| e.at_line(lines.line_number, &lines.line))
+}
+
+/// The non-empty lines of a flight plan, keeping the last one read, so that errors can
+/// point at it.
+struct PlanLines<F: BufRead> {
+    lines: Lines<F>,
+    line_number: usize,
+    line: String,
+}
+
+impl<F: BufRead> PlanLines<F> {
+    /// Read the next non-empty line, and its line number.
+    fn next_line(&mut self) -> Result<(usize, &str), ParseError> {
+        loop {
+            self.line = self.lines.next().ok_or(ParseError::MissingLine)??;
+            self.line_number += 1;
+            self.line.truncate(self.line.trim_end().len());
+            if !self.line.is_empty() {
+                return Ok((self.line_number, &self.line));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn read_plan<F: BufRead>(
+    lines: &mut PlanLines<F>,
+    nav_graph: &NavGraph,
+) -> Result<FlightPlan, ParseError> {
+    let (_, bom) = lines.next_line()?;
+    ensure!(matches!(bom, "I" | "A"), BadBOMSnafu { bom });
+    let (_, version) = lines.next_line()?;
+    ensure!(
+        version.starts_with("1100 "),
+        ParseSnafu {
+            rendered: format!("Unsupported flight plan version `{version}`."),
+            stage: "FMS version",
+        }
+    );
+
+    let mut plan = FlightPlan::default();
+    let mut sid_trans = None;
+    let mut star_trans = None;
+    let mut app_trans = None;
+    let num_enr: usize = loop {
+        let (_, line) = lines.next_line()?;
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+        match key {
+            "CYCLE" => plan.cycle = Some(parse_value(value, key)?),
+            "ADEP" => plan.route.departure = hstring_value(value, key)?,
+            "DEPRWY" => plan.departure_runway = hstring_value(value, key)?,
+            "SID" => plan.route.sid = procedure(hstring_value(value, key)?),
+            "SIDTRANS" => sid_trans = hstring_value(value, key)?,
+            "ADES" => plan.route.arrival = hstring_value(value, key)?,
+            "DESRWY" => plan.arrival_runway = hstring_value(value, key)?,
+            "STAR" => plan.route.star = procedure(hstring_value(value, key)?),
+            "STARTRANS" => star_trans = hstring_value(value, key)?,
+            "APP" => plan.approach = procedure(hstring_value(value, key)?),
+            "APPTRANS" => app_trans = hstring_value(value, key)?,
+            "NUMENR" => break parse_value(value, key)?,
+            // `DEP` and `DES` name a non-airport origin or destination, which is also
+            // the first or last waypoint.
+            _ => {},
+        }
+    };
+    for (proc_use, trans) in [
+        (&mut plan.route.sid, sid_trans),
+        (&mut plan.route.star, star_trans),
+        (&mut plan.approach, app_trans),
+    ] {
+        if let Some(proc_use) = proc_use {
+            proc_use.transition = trans;
+        }
+    }
+
+    for _ in 0..num_enr {
+        let (line_number, line) = lines.next_line()?;
+        let row =
+            parse_line(line_number, line, "flight plan waypoint row", parse_row)?;
+        let to = match (row.typ, row.via) {
+            (AIRPORT, "ADEP") => {
+                plan.departure_position = Some((row.lat, row.lon));
+                continue;
+            },
+            (AIRPORT, "ADES") => {
+                plan.arrival_position = Some((row.lat, row.lon));
+                continue;
+            },
+            (AIRPORT, _) => {
+                if let Some(airport) = nav_graph.airport(row.ident) {
+                    let (lat, lon) =
+                        airport.reference_point().unwrap_or((row.lat, row.lon));
+                    RoutePoint::Airport {
+                        ident: hstring_value(row.ident, "airport")?
+                            .unwrap_or_default(),
+                        lat,
+                        lon,
+                    }
+                } else {
+                    let candidates = nav_graph
+                        .find_nav_entry(row.ident)
+                        .into_iter()
+                        .map(|(idx, _)| idx)
+                        .collect();
+                    RoutePoint::Node(
+                        nav_graph
+                            .nearest_of(candidates, Some((row.lat, row.lon)))
+                            .context(ReferencedNonexistentWptSnafu { wpt: row.ident })?,
+                    )
+                }
+            },
+            (LAT_LON, _) => RoutePoint::LatLon {
+                lat: row.lat,
+                lon: row.lon,
+            },
+            (typ, _) => {
+                let typ = WptType::from_code(typ).with_context(|| ParseSnafu {
+                    rendered: format!("Unsupported waypoint type {typ}."),
+                    stage: "flight plan waypoint row",
+                })?;
+                let candidates = nav_graph
+                    .find_nav_entry(row.ident)
+                    .into_iter()
+                    .filter(|(_, entry)| typ.matches(entry))
+                    .map(|(idx, _)| idx)
+                    .collect();
+                RoutePoint::Node(
+                    nav_graph
+                        .nearest_of(candidates, Some((row.lat, row.lon)))
+                        .context(ReferencedNonexistentWptSnafu { wpt: row.ident })?,
+                )
+            },
+        };
+        let via = match row.via {
+            "DRCT" | "DEP" | "DES"
+                if plan.route.legs.is_empty() && plan.route.sid.is_some() =>
+            {
+                Via::Sid
+            },
+            "DRCT" | "DEP" | "DES" => Via::Direct,
+            awy => Via::Airway(hstring_value(awy, "airway")?.unwrap_or_default()),
+        };
+        plan.route.legs.push(RouteLeg { via, to });
+        plan.altitudes_ft.push(row.altitude_ft);
+    }
+    Ok(plan)
+}
+
+fn procedure(ident: Option<HString<6>>) -> Option<ProcedureUse> {
+    ident.map(|ident| ProcedureUse {
+        ident,
+        transition: None,
+    })
+}
+
+fn parse_value<T: std::str::FromStr>(
+    value: &str,
+    key: &str,
+) -> Result<T, ParseError> {
+    value.parse().ok().with_context(|| ParseSnafu {
+        rendered: format!("Invalid value `{value}`."),
+        stage: format!("FMS {key}"),
+    })
+}
+
+fn hstring_value<const N: usize>(
+    value: &str,
+    key: &str,
+) -> Result<Option<HString<N>>, ParseError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    HString::try_from(value)
+        .map(Some)
+        .ok()
+        .with_context(|| ParseSnafu {
+            rendered: format!("`{value}` is longer than {N} characters."),
+            stage: format!("FMS {key}"),
+        })
+}
+
+fn parse_row<'a>(input: &mut Located<&'a str>) -> PResult<FmsRow<'a>> {
+    let typ: u8 = trace("waypoint type", dec_uint).parse_next(input)?;
+    let ident = trace("ident", preceded(space1, take_till(1.., AsChar::is_space)))
+        .parse_next(input)?;
+    let via = trace("via", preceded(space1, take_till(1.., AsChar::is_space)))
+        .parse_next(input)?;
+    let altitude_ft: f64 =
+        trace("altitude", preceded(space1, float)).parse_next(input)?;
+    let lat: f64 = trace("latitude", preceded(space1, float)).parse_next(input)?;
+    let lon: f64 = trace("longitude", terminated(preceded(space1, float), space0))
+        .parse_next(input)?;
+    Ok(FmsRow {
+        typ,
+        ident,
+        via,
+        altitude_ft,
+        lat,
+        lon,
+    })
+}
+
+/// Write a flight plan. Waypoints are written with their positions from `nav_graph`, as
+/// are airports whose positions the plan doesn't have, if they have been added to it.
+/// If the plan has no cycle, the cycle of `nav_graph` is written.
+///
+/// # Errors
+/// An error will be returned if writing to `out` fails.
+pub fn write_file<W: Write>(
+    plan: &FlightPlan,
+    nav_graph: &NavGraph,
+    mut out: W,
+) -> Result<(), IoError> {
+    let route = &plan.route;
+    writeln!(out, "I")?;
+    writeln!(out, "1100 Version")?;
+    writeln!(
+        out,
+        "CYCLE {:04}",
+        plan.cycle.unwrap_or(nav_graph.fix_header().cycle)
+    )?;
+
+    let first_ident = route.legs.first().map(|leg| ident(nav_graph, &leg.to));
+    let last_ident = route.legs.last().map(|leg| ident(nav_graph, &leg.to));
+    match (&route.departure, &first_ident) {
+        (Some(airport), _) => writeln!(out, "ADEP {airport}")?,
+        (None, Some(ident)) => writeln!(out, "DEP {ident}")?,
+        (None, None) => {},
+    }
+    if let Some(rwy) = &plan.departure_runway {
+        writeln!(out, "DEPRWY {rwy}")?;
+    }
+    write_procedure(&mut out, "SID", route.sid.as_ref())?;
+    match (&route.arrival, &last_ident) {
+        (Some(airport), _) => writeln!(out, "ADES {airport}")?,
+        (None, Some(ident)) => writeln!(out, "DES {ident}")?,
+        (None, None) => {},
+    }
+    if let Some(rwy) = &plan.arrival_runway {
+        writeln!(out, "DESRWY {rwy}")?;
+    }
+    write_procedure(&mut out, "STAR", route.star.as_ref())?;
+    write_procedure(&mut out, "APP", plan.approach.as_ref())?;
+
+    let num_enr = route.legs.len()
+        + usize::from(route.departure.is_some())
+        + usize::from(route.arrival.is_some());
+    writeln!(out, "NUMENR {num_enr}")?;
+
+    if let Some(airport) = &route.departure {
+        let (lat, lon) = plan
+            .departure_position
+            .or_else(|| nav_graph.airport(airport)?.reference_point())
+            .unwrap_or_default();
+        write_row(&mut out, AIRPORT, airport, "ADEP", 0f64, lat, lon)?;
+    }
+    for (i, leg) in route.legs.iter().enumerate() {
+        let (lat, lon) = leg.to.position(nav_graph);
+        let typ = match leg.to {
+            RoutePoint::Node(idx) => match nav_graph.graph[idx].kind() {
+                NavEntryKind::Fix => WptType::Fix.code(),
+                NavEntryKind::Ndb => WptType::Ndb.code(),
+                NavEntryKind::Vor | NavEntryKind::Dme => WptType::Vhf.code(),
+                _ => LAT_LON,
+            },
+            RoutePoint::LatLon { .. } => LAT_LON,
+            RoutePoint::Airport { .. } => AIRPORT,
+        };
+        let ident = if typ == LAT_LON {
+            format!("{lat:+.3}_{lon:+.3}")
+        } else {
+            ident(nav_graph, &leg.to)
+        };
+        let via = match &leg.via {
+            Via::Direct | Via::Sid => "DRCT",
+            Via::Airway(name) => name,
+        };
+        let altitude_ft = plan.altitudes_ft.get(i).copied().unwrap_or_default();
+        write_row(&mut out, typ, &ident, via, altitude_ft, lat, lon)?;
+    }
+    if let Some(airport) = &route.arrival {
+        let (lat, lon) = plan
+            .arrival_position
+            .or_else(|| nav_graph.airport(airport)?.reference_point())
+            .unwrap_or_default();
+        write_row(&mut out, AIRPORT, airport, "ADES", 0f64, lat, lon)?;
+    }
+    Ok(())
+}
+
+fn ident(nav_graph: &NavGraph, point: &RoutePoint) -> String {
+    match point {
+        RoutePoint::Node(idx) => nav_graph.graph[*idx].ident().to_owned(),
+        RoutePoint::LatLon { lat, lon } => format!("{lat:+.3}_{lon:+.3}"),
+        RoutePoint::Airport { ident, .. } => ident.to_string(),
+    }
+}
+
+fn write_procedure<W: Write>(
+    out: &mut W,
+    key: &str,
+    proc_use: Option<&ProcedureUse>,
+) -> Result<(), IoError> {
+    if let Some(proc_use) = proc_use {
+        writeln!(out, "{key} {}", proc_use.ident)?;
+        if let Some(trans) = &proc_use.transition {
+            writeln!(out, "{key}TRANS {trans}")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_row<W: Write>(
+    out: &mut W,
+    typ: u8,
+    ident: &str,
+    via: &str,
+    altitude_ft: f64,
+    lat: f64,
+    lon: f64,
+) -> Result<(), IoError> {
+    writeln!(
+        out,
+        "{typ} {ident} {via} {altitude_ft:.6} {lat:.6} {lon:.6}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Cursor};
+
+    use petgraph::graph::DiGraph;
+
+    use crate::navdata::{
+        apt,
+        fms::{parse_file, write_file},
+        route::{RoutePoint, Via},
+        tests::{awy, fix, nav_graph},
+        NavEdge, NavEntry, ParseError,
+    };
+
+    const PLAN: &str = "I
+1100 Version
+CYCLE 2401
+ADEP KSFO
+DEPRWY RW28L
+ADES KLAX
+NUMENR 5
+1 KSFO ADEP 0.000000 37.618999 -122.375000
+11 SSTIK DRCT 0.000000 38.000000 -123.000000
+11 LKV J5 35000.000000 38.000000 -121.000000
+28 +37.500_-120.500 DRCT 0.000000 37.500000 -120.500000
+1 KLAX ADES 0.000000 33.942501 -118.408096
+";
+
+    #[test]
+    fn round_trip_fms() {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::new();
+        let sstik = graph.add_node(fix("SSTIK", 38.0, -123.0));
+        graph.add_node(fix("SSTIK", -38.0, 123.0));
+        let lkv = graph.add_node(fix("LKV", 38.0, -121.0));
+        graph.add_edge(sstik, lkv, awy("J5", 180, 450, true));
+        let nav_graph = nav_graph(graph, HashMap::new());
+
+        let plan = parse_file(PLAN.as_bytes(), &nav_graph).unwrap();
+        assert_eq!(plan.route.departure.as_deref(), Some("KSFO"));
+        assert_eq!(plan.route.arrival.as_deref(), Some("KLAX"));
+        assert_eq!(plan.departure_runway.as_deref(), Some("RW28L"));
+        let legs: Vec<_> = plan
+            .route
+            .legs
+            .iter()
+            .map(|leg| (&leg.via, leg.to.clone()))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                (&Via::Direct, RoutePoint::Node(sstik)),
+                (
+                    &Via::Airway("J5".try_into().unwrap()),
+                    RoutePoint::Node(lkv)
+                ),
+                (
+                    &Via::Direct,
+                    RoutePoint::LatLon {
+                        lat: 37.5,
+                        lon: -120.5
+                    }
+                ),
+            ]
+        );
+        assert_eq!(plan.altitudes_ft, [0.0, 35000.0, 0.0]);
+
+        let mut written = Vec::new();
+        write_file(&plan, &nav_graph, &mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), PLAN);
+
+        let unknown = PLAN.replace("11 LKV", "11 XXXXX");
+        let err = parse_file(unknown.as_bytes(), &nav_graph).unwrap_err();
+        assert!(matches!(
+            err.without_location(),
+            ParseError::ReferencedNonexistentWpt { wpt, .. } if wpt == "XXXXX"
+        ));
+        let lkv_line = PLAN.lines().position(|l| l.contains("LKV")).unwrap() + 1;
+        assert_eq!(err.location().unwrap().line, lkv_line);
+    }
+
+    #[test]
+    fn resolve_airports_along_route() {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::new();
+        let lkv = graph.add_node(fix("LKV", 38.0, -121.0));
+        let mut nav_graph = nav_graph(graph, HashMap::new());
+        nav_graph.add_airports(
+            apt::parse_file_buffered(Cursor::new(
+                "I\n1300 Test\n\n1 9 0 0 KOAK Oakland\n1302 datum_lat 37.721\n\
+                 1302 datum_lon -122.221\n99\n",
+            ))
+            .unwrap(),
+        );
+
+        let with_airport = PLAN.replace(
+            "11 SSTIK DRCT 0.000000 38.000000 -123.000000",
+            "1 KOAK DRCT 0.000000 37.721000 -122.221000",
+        );
+        let plan = parse_file(with_airport.as_bytes(), &nav_graph).unwrap();
+        assert_eq!(
+            plan.route.legs[0].to,
+            RoutePoint::Airport {
+                ident: "KOAK".try_into().unwrap(),
+                lat: 37.721,
+                lon: -122.221
+            }
+        );
+        assert_eq!(plan.route.legs[1].to, RoutePoint::Node(lkv));
+        let mut written = Vec::new();
+        write_file(&plan, &nav_graph, &mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), with_airport);
+
+        // An airport that hasn't been added may still be a waypoint.
+        let as_waypoint = with_airport.replace("11 LKV J5", "1 LKV J5");
+        let plan = parse_file(as_waypoint.as_bytes(), &nav_graph).unwrap();
+        assert_eq!(plan.route.legs[1].to, RoutePoint::Node(lkv));
+
+        let unknown = with_airport.replace("11 LKV J5", "1 KXYZ J5");
+        let err = parse_file(unknown.as_bytes(), &nav_graph).unwrap_err();
+        assert!(matches!(
+            err.without_location(),
+            ParseError::ReferencedNonexistentWpt { wpt, .. } if wpt == "KXYZ"
+        ));
+    }
+}
@@ -4,29 +4,35 @@
 
 use std::io::{BufRead, Read};
 
-use itertools::Itertools;
 use petgraph::graph::DiGraph;
 use snafu::ensure;
 use winnow::{
-    ascii::{dec_uint, float, space1},
-    combinator::{fail, preceded, success},
-    dispatch,
+    ascii::{dec_uint, float, space0, space1},
+    combinator::preceded,
     stream::AsChar,
     token::any,
     trace::trace,
     Located, PResult, Parser,
 };
 
-use crate::navdata::{
-    match_wpt_predicate,
-    nav::{Navaid, TypeSpecificData},
-    take_hstring_till, BadLastLineSnafu, ConflictingHoldLegLengthsSnafu,
-    DataVersion, Header, InvalidHoldDirSnafu, NavEdge, NavEntry, ParseError,
-    ParseSnafu, ParsedNodeRef, ParsedNodeRefType, ReferencedNonexistentWptSnafu,
-    UnsupportedVersionSnafu,
+use crate::{
+    geo::wmm::magnetic_to_true,
+    navdata::{
+        for_each_row,
+        ident_index::IdentIndex,
+        match_wpt_predicate,
+        nav::{Navaid, TypeSpecificData},
+        parse_line, take_hstring_till, ConflictingHoldLegLengthsSnafu, DataVersion,
+        Header, InvalidHoldDirSnafu, NavEdge, NavEntry, ParseError,
+        ReferencedNonexistentWptSnafu, UnsupportedVersionSnafu, WptRef, WptType,
+    },
 };
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct Edge {
     pub inbound_crs_mag: f32,
     pub leg_length: LegLength,
@@ -37,22 +43,42 @@ pub struct Edge {
     pub max_spd_kts: Option<u16>,
 }
 
+impl Edge {
+    #[must_use]
+    /// The inbound course relative to true north, given the magnetic variation at the
+    /// hold point, like from [`NavEntry::magnetic_variation`].
+    pub fn inbound_crs_true(&self, variation: f64) -> f64 {
+        magnetic_to_true(f64::from(self.inbound_crs_mag), variation)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum LegLength {
     Minutes(f32),
     DME(f32),
 }
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum Direction {
     Left,
     Right,
 }
 
-#[allow(clippy::too_many_lines)]
+/// Parse holds, adding them to `nav_graph`. If `diagnostics` is given, bad rows are
+/// skipped, and their errors are added to it.
 pub(super) fn parse_file_buffered<F: Read + BufRead>(
     file: F,
     nav_graph: &mut DiGraph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+    diagnostics: Option<&mut Vec<ParseError>>,
 ) -> Result<Header, ParseError> {
     let mut lines = file.lines();
     let header = super::parse_header(|md_type| md_type == "HoldXP1140", &mut lines)?;
@@ -64,122 +90,125 @@ pub(super) fn parse_file_buffered<F: Read + BufRead>(
         }
     );
 
-    let mut lines = lines
-        .filter(|lin| lin.as_ref().map_or(true, |lin| !lin.is_empty()))
-        .peekable();
-
-    lines
-        .peeking_take_while(|l| l.as_ref().map_or(true, |l| l != "99"))
-        .try_for_each(|line| -> Result<(), ParseError> {
-            let line = line?;
-            let parsed_edge = trace("hold row", parse_row)
-                .parse(Located::new(&line))
-                .map_err(|e| {
-                    ParseSnafu {
-                        rendered: e.to_string(),
-                        stage: "hold row",
-                    }
-                    .build()
-                })?;
-
-            let hold_point_idx = nav_graph
-                .node_indices()
-                .filter(|idx| match &nav_graph[*idx] {
-                    NavEntry::Fix(fix) => {
-                        fix.terminal_region == parsed_edge.terminal_region
-                    },
-                    NavEntry::Navaid(Navaid {
-                        type_data: TypeSpecificData::Vor { .. },
-                        ..
-                    }) => parsed_edge.terminal_region == "ENRT",
-                    NavEntry::Navaid(Navaid {
-                        type_data:
-                            TypeSpecificData::Ndb {
-                                terminal_region, ..
-                            }
-                            | TypeSpecificData::Dme {
-                                terminal_region, ..
-                            },
+    for_each_row(lines, diagnostics, |line_number, line| {
+        add_row(line_number, line, nav_graph, ident_index)
+    })?;
+
+    Ok(header)
+}
+
+/// Parse a row, and add its hold to `nav_graph`.
+fn add_row(
+    line_number: usize,
+    line: &str,
+    nav_graph: &mut DiGraph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+) -> Result<(), ParseError> {
+    let parsed_edge = parse_line(line_number, line, "hold row", parse_row)?;
+
+    let turn_direction = match parsed_edge.direction {
+        'L' => Direction::Left,
+        'R' => Direction::Right,
+        _ => {
+            return InvalidHoldDirSnafu {
+                dir: parsed_edge.direction,
+            }
+            .fail()
+        },
+    };
+
+    #[allow(illegal_floating_point_literal_pattern)]
+    let leg_length = match (parsed_edge.leg_time_min, parsed_edge.leg_length_nm) {
+        (minutes, 0f32) => LegLength::Minutes(minutes),
+        (0f32, dme) => LegLength::DME(dme),
+        (minutes, dme) => {
+            return ConflictingHoldLegLengthsSnafu { minutes, dme }.fail()
+        },
+    };
+
+    let min_alt_ft = if parsed_edge.min_alt_ft == 0 {
+        None
+    } else {
+        Some(parsed_edge.min_alt_ft)
+    };
+
+    let max_alt_ft = if parsed_edge.max_alt_ft == 0 {
+        None
+    } else {
+        Some(parsed_edge.max_alt_ft)
+    };
+
+    let max_spd_kts = if parsed_edge.max_spd_kts == 0 {
+        None
+    } else {
+        Some(parsed_edge.max_spd_kts)
+    };
+
+    let edge = Edge {
+        inbound_crs_mag: parsed_edge.inbound_crs_mag,
+        leg_length,
+        turn_direction,
+        min_alt_ft,
+        max_alt_ft,
+        max_spd_kts,
+    };
+
+    add_hold(
+        &parsed_edge.hold_point,
+        &parsed_edge.terminal_region,
+        edge,
+        nav_graph,
+        ident_index,
+    )
+}
+
+/// Add a hold at the waypoint `hold_point` in `terminal_region` to `nav_graph`.
+pub(super) fn add_hold(
+    hold_point: &WptRef,
+    terminal_region: &str,
+    edge: Edge,
+    nav_graph: &mut DiGraph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+) -> Result<(), ParseError> {
+    let hold_point_idx = ident_index
+        .get(&hold_point.ident)
+        .iter()
+        .copied()
+        .filter(|idx| match &nav_graph[*idx] {
+            NavEntry::Fix(fix) => fix.terminal_region == terminal_region,
+            NavEntry::Navaid(Navaid {
+                type_data: TypeSpecificData::Vor { .. },
+                ..
+            }) => terminal_region == "ENRT",
+            NavEntry::Navaid(Navaid {
+                type_data:
+                    TypeSpecificData::Ndb {
+                        terminal_region: navaid_terminal_region,
                         ..
-                    }) => terminal_region == &parsed_edge.terminal_region,
-                    NavEntry::Navaid(_) => false,
-                })
-                .find(match_wpt_predicate(&parsed_edge.hold_point, nav_graph))
-                .ok_or_else(|| {
-                    ReferencedNonexistentWptSnafu {
-                        wpt: parsed_edge.hold_point.ident.to_string(),
                     }
-                    .build()
-                })?;
-
-            let turn_direction = match parsed_edge.direction {
-                'L' => Direction::Left,
-                'R' => Direction::Right,
-                _ => {
-                    return InvalidHoldDirSnafu {
-                        dir: parsed_edge.direction,
-                    }
-                    .fail()
-                },
-            };
-
-            #[allow(illegal_floating_point_literal_pattern)]
-            let leg_length =
-                match (parsed_edge.leg_time_min, parsed_edge.leg_length_nm) {
-                    (minutes, 0f32) => LegLength::Minutes(minutes),
-                    (0f32, dme) => LegLength::DME(dme),
-                    (minutes, dme) => {
-                        return ConflictingHoldLegLengthsSnafu { minutes, dme }
-                            .fail()
+                    | TypeSpecificData::Dme {
+                        terminal_region: navaid_terminal_region,
+                        ..
                     },
-                };
-
-            let min_alt_ft = if parsed_edge.min_alt_ft == 0 {
-                None
-            } else {
-                Some(parsed_edge.min_alt_ft)
-            };
-
-            let max_alt_ft = if parsed_edge.max_alt_ft == 0 {
-                None
-            } else {
-                Some(parsed_edge.max_alt_ft)
-            };
-
-            let max_spd_kts = if parsed_edge.max_spd_kts == 0 {
-                None
-            } else {
-                Some(parsed_edge.max_spd_kts)
-            };
-
-            let edge = Edge {
-                inbound_crs_mag: parsed_edge.inbound_crs_mag,
-                leg_length,
-                turn_direction,
-                min_alt_ft,
-                max_alt_ft,
-                max_spd_kts,
-            };
-
-            nav_graph.add_edge(hold_point_idx, hold_point_idx, NavEdge::Hold(edge));
-
-            Ok(())
+                ..
+            }) => navaid_terminal_region == terminal_region,
+            NavEntry::Navaid(_) => false,
+        })
+        .find(match_wpt_predicate(hold_point, nav_graph))
+        .ok_or_else(|| {
+            ReferencedNonexistentWptSnafu {
+                wpt: hold_point.ident.to_string(),
+            }
+            .build()
         })?;
 
-    lines
-        .next()
-        .ok_or_else(|| ParseError::MissingLine)
-        .and_then(|last_line| {
-            let last_line = last_line?;
-            ensure!(last_line == "99", BadLastLineSnafu { last_line });
-            Ok(())
-        })?;
+    nav_graph.add_edge(hold_point_idx, hold_point_idx, NavEdge::Hold(edge));
 
-    Ok(header)
+    Ok(())
 }
 
 struct ParsedEdge {
-    hold_point: ParsedNodeRef,
+    hold_point: WptRef,
     terminal_region: heapless::String<4>,
     inbound_crs_mag: f32,
     leg_time_min: f32,
@@ -191,27 +220,24 @@ struct ParsedEdge {
 }
 
 fn parse_row(input: &mut Located<&str>) -> PResult<ParsedEdge> {
-    let hold_point_ident =
-        trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-            .parse_next(input)?;
+    let hold_point_ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let icao_region = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let terminal_region = trace(
         "terminal region",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let point_typ: ParsedNodeRefType = trace(
+    let point_typ: WptType = trace(
         "point type",
-        dispatch! { preceded(space1, dec_uint);
-            2 => success(ParsedNodeRefType::Vhf),
-            3 => success(ParsedNodeRefType::Ndb),
-            11 => success(ParsedNodeRefType::Fix),
-            _ => fail,
-        },
+        preceded(space1, dec_uint).verify_map(WptType::from_code),
     )
     .parse_next(input)?;
 
@@ -235,7 +261,7 @@ fn parse_row(input: &mut Located<&str>) -> PResult<ParsedEdge> {
     let max_spd_kts: u16 = trace("maximum speed, NM/h", preceded(space1, dec_uint))
         .parse_next(input)?;
     Ok(ParsedEdge {
-        hold_point: ParsedNodeRef {
+        hold_point: WptRef {
             ident: hold_point_ident,
             icao_region,
             typ: point_typ,
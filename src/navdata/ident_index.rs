@@ -0,0 +1,55 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! An index of [`NavEntry`]s by ident, so that lookups during loading and by the public
+//! API don't have to scan the whole graph. Lookups by ICAO region and type filter the
+//! handful of entries sharing an ident.
+
+use std::collections::HashMap;
+
+use heapless::String as HString;
+use petgraph::graph::{DiGraph, NodeIndex};
+
+use crate::navdata::{match_wpt_predicate, NavEdge, NavEntry, WptRef};
+
+#[derive(Debug, Default)]
+pub(super) struct IdentIndex {
+    entries: HashMap<HString<8>, Vec<NodeIndex>>,
+}
+
+impl IdentIndex {
+    pub(super) fn build(graph: &DiGraph<NavEntry, NavEdge>) -> Self {
+        let mut index = Self::default();
+        for idx in graph.node_indices() {
+            index.insert(idx, &graph[idx]);
+        }
+        index
+    }
+
+    pub(super) fn insert(&mut self, idx: NodeIndex, entry: &NavEntry) {
+        // UNWRAP: No ident is longer than a fix ident, which is at most 8 characters.
+        let ident = HString::try_from(entry.ident()).unwrap();
+        self.entries.entry(ident).or_default().push(idx);
+    }
+
+    /// All nodes with the ident `ident`, in the order they were added to the graph.
+    pub(super) fn get(&self, ident: &str) -> &[NodeIndex] {
+        HString::<8>::try_from(ident)
+            .ok()
+            .and_then(|ident| self.entries.get(&ident))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The first node matching the ident, ICAO region, and type of `wpt`.
+    pub(super) fn find_wpt(
+        &self,
+        wpt: &WptRef,
+        graph: &DiGraph<NavEntry, NavEdge>,
+    ) -> Option<NodeIndex> {
+        self.get(&wpt.ident)
+            .iter()
+            .copied()
+            .find(match_wpt_predicate(wpt, graph))
+    }
+}
@@ -0,0 +1,522 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Navdata layers, and where each [`NavEntry`] came from.
+//!
+//! Navdata is gathered from several [`Layer`]s, from lowest to highest precedence:
+//! `Resources/default data`, `Custom Data`, the user files in `Custom Data`, and
+//! entries added at runtime. The rules are:
+//!
+//! - The `earth_*.dat` files and the `CIFP` folder are all taken from `Custom Data` if
+//!   it has any of them, and otherwise from `Resources/default data`. The two are never
+//!   mixed, as they are usually of different AIRAC cycles, so a `Custom Data` folder
+//!   that is missing a required earth file is an error, rather than falling back to
+//!   the default data.
+//! - Entries from `user_fix.dat` and `user_nav.dat` replace matching entries from the
+//!   earth files, and are otherwise added.
+//! - Entries added at runtime replace matching entries from any layer, and are
+//!   otherwise added.
+//!
+//! Fixes match if they have the same ident, ICAO region, and terminal region. Navaids
+//! match if they have the same ident, ICAO region, and type.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Error as IoError, ErrorKind},
+    mem::Discriminant,
+    path::{Path, PathBuf},
+};
+
+use petgraph::graph::NodeIndex;
+
+use crate::navdata::{
+    nav::TypeSpecificData,
+    source::{DirSource, NavSource},
+    NavEntry, NavGraph,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// A source of navdata, in order of increasing precedence.
+pub enum Layer {
+    /// `Resources/default data`, shipped with X-Plane.
+    DefaultData,
+    /// `Custom Data`, as installed by navdata providers.
+    CustomData,
+    /// `user_fix.dat` and `user_nav.dat`, in `Custom Data`.
+    User,
+    /// Entries added with [`NavGraph::add_entry`].
+    Runtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where a [`NavEntry`] came from.
+pub struct Provenance<'a> {
+    /// The layer the entry is from.
+    pub layer: Layer,
+    /// The file the entry was read from, or [`None`] if it was added at runtime.
+    pub file: Option<&'a Path>,
+    /// The layer of the entry this one replaced, if it replaced one.
+    pub replaced: Option<Layer>,
+}
+
+/// The earth files, which are all taken from the same layer, with the `CIFP` folder.
+pub(super) const EARTH_FILES: [&str; 6] = [
+    "earth_fix.dat",
+    "earth_nav.dat",
+    "earth_awy.dat",
+    "earth_hold.dat",
+    "earth_mora.dat",
+    "earth_msa.dat",
+];
+
+#[derive(Debug, Default)]
+/// The folders navdata is loaded from. The earth files are taken from `custom_data` if
+/// it has any, and otherwise from `default_data`. Either can be any [`NavSource`], not
+/// just a folder on disk.
+pub struct DataFolders {
+    /// `Resources/default data`.
+    pub default_data: Option<Box<dyn NavSource>>,
+    /// `Custom Data`. User files are only read from here.
+    pub custom_data: Option<Box<dyn NavSource>>,
+}
+
+/// A file opened from one of the [`DataFolders`].
+pub(super) struct LayerFile<'a> {
+    pub(super) layer: Layer,
+    /// The path to show for the file, from [`NavSource::file_path`].
+    pub(super) path: PathBuf,
+    pub(super) reader: Box<dyn BufRead + 'a>,
+}
+
+/// A folder found in one of the [`DataFolders`].
+pub(super) struct LayerFolder<'a> {
+    pub(super) source: &'a dyn NavSource,
+    /// The names of the files in the folder.
+    pub(super) names: Vec<String>,
+}
+
+impl DataFolders {
+    #[must_use]
+    /// The data folders of the X-Plane installation at `xplane_root`.
+    pub fn xplane(xplane_root: &Path) -> Self {
+        Self {
+            default_data: Some(Box::new(DirSource::new(
+                xplane_root.join("Resources").join("default data"),
+            ))),
+            custom_data: Some(Box::new(DirSource::new(
+                xplane_root.join("Custom Data"),
+            ))),
+        }
+    }
+
+    #[must_use]
+    /// Just a `Custom Data` folder.
+    pub fn custom_data(folder: &Path) -> Self {
+        Self::from_source(DirSource::new(folder))
+    }
+
+    #[must_use]
+    /// Just a `Custom Data` source, like an archive shipped with an aircraft.
+    pub fn from_source(source: impl NavSource + 'static) -> Self {
+        Self {
+            default_data: None,
+            custom_data: Some(Box::new(source)),
+        }
+    }
+
+    /// The layer the earth files and the `CIFP` folder are taken from: `Custom Data` if
+    /// it has any of them, and otherwise the default data.
+    pub(super) fn earth_source(
+        &self,
+    ) -> Result<Option<(Layer, &dyn NavSource)>, IoError> {
+        if let Some(custom_data) = self.custom_data.as_deref() {
+            for name in EARTH_FILES {
+                if custom_data.open(name)?.is_some() {
+                    return Ok(Some((Layer::CustomData, custom_data)));
+                }
+            }
+            if custom_data.list("CIFP")?.is_some() {
+                return Ok(Some((Layer::CustomData, custom_data)));
+            }
+        }
+        Ok(self
+            .default_data
+            .as_deref()
+            .map(|default_data| (Layer::DefaultData, default_data)))
+    }
+
+    /// The earth file `name`, from the [`earth_source`](DataFolders::earth_source).
+    pub(super) fn find(&self, name: &str) -> Result<Option<LayerFile<'_>>, IoError> {
+        let Some((layer, source)) = self.earth_source()? else {
+            return Ok(None);
+        };
+        Ok(source.open(name)?.map(|reader| LayerFile {
+            layer,
+            path: source.file_path(name),
+            reader,
+        }))
+    }
+
+    /// Like [`find`](DataFolders::find), for files that must be there.
+    pub(super) fn require(&self, name: &str) -> Result<LayerFile<'_>, IoError> {
+        self.find(name)?.ok_or_else(|| {
+            let message = match self.earth_source() {
+                Ok(Some((layer, source))) => format!(
+                    "`{}` is missing, and the other earth files are taken from \
+                     the {layer:?} layer",
+                    source.file_path(name).display()
+                ),
+                _ => format!("`{name}` is in none of the data folders"),
+            };
+            IoError::new(ErrorKind::NotFound, message)
+        })
+    }
+
+    /// The folder `name`, from the [`earth_source`](DataFolders::earth_source).
+    pub(super) fn find_folder(
+        &self,
+        name: &str,
+    ) -> Result<Option<LayerFolder<'_>>, IoError> {
+        let Some((_, source)) = self.earth_source()? else {
+            return Ok(None);
+        };
+        Ok(source
+            .list(name)?
+            .map(|names| LayerFolder { source, names }))
+    }
+
+    /// The user file `name`, if there is one.
+    pub(super) fn user_file(
+        &self,
+        name: &str,
+    ) -> Result<Option<LayerFile<'_>>, IoError> {
+        let Some(source) = self.custom_data.as_deref() else {
+            return Ok(None);
+        };
+        Ok(source.open(name)?.map(|reader| LayerFile {
+            layer: Layer::User,
+            path: source.file_path(name),
+            reader,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub(super) struct EntryProvenance {
+    layer: Layer,
+    /// Index into [`Provenances::files`].
+    file: Option<u32>,
+    replaced: Option<Layer>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// The provenance of each node of a [`NavGraph`]. Files are stored once, rather than
+/// with every entry from them.
+pub(super) struct Provenances {
+    files: Vec<PathBuf>,
+    /// By node index. Nodes added without a provenance, like in tests, have none.
+    entries: Vec<Option<EntryProvenance>>,
+}
+
+impl Provenances {
+    pub(super) fn get(&self, idx: NodeIndex) -> Option<Provenance<'_>> {
+        let entry = self.entries.get(idx.index()).copied().flatten()?;
+        Some(Provenance {
+            layer: entry.layer,
+            file: entry.file.map(|file| self.files[file as usize].as_path()),
+            replaced: entry.replaced,
+        })
+    }
+
+    fn set(&mut self, idx: NodeIndex, entry: EntryProvenance) {
+        if self.entries.len() <= idx.index() {
+            self.entries.resize(idx.index() + 1, None);
+        }
+        self.entries[idx.index()] = Some(entry);
+    }
+}
+
+/// What makes two entries the same, for one to replace the other.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum OverrideKey {
+    Fix {
+        ident: heapless::String<8>,
+        icao_region: heapless::String<2>,
+        terminal_region: heapless::String<4>,
+    },
+    Navaid {
+        ident: heapless::String<5>,
+        icao_region: heapless::String<2>,
+        typ: Discriminant<TypeSpecificData>,
+    },
+}
+
+impl OverrideKey {
+    fn of(entry: &NavEntry) -> Self {
+        match entry {
+            NavEntry::Fix(fix) => Self::Fix {
+                ident: fix.ident.clone(),
+                icao_region: fix.icao_region.clone(),
+                terminal_region: fix.terminal_region.clone(),
+            },
+            NavEntry::Navaid(navaid) => Self::Navaid {
+                ident: navaid.ident.clone(),
+                icao_region: navaid.icao_region.clone(),
+                typ: std::mem::discriminant(&navaid.type_data),
+            },
+        }
+    }
+}
+
+/// Entries gathered from the layers while loading, before they are added to the graph.
+#[derive(Default)]
+pub(super) struct LayeredEntries {
+    pub(super) entries: Vec<NavEntry>,
+    provenance: Provenances,
+    /// The position of the first entry with each key.
+    positions: HashMap<OverrideKey, usize>,
+}
+
+impl LayeredEntries {
+    fn file_id(&mut self, file: &Path) -> u32 {
+        let files = &mut self.provenance.files;
+        let pos = files.iter().position(|f| f == file).unwrap_or_else(|| {
+            files.push(file.to_owned());
+            files.len() - 1
+        });
+        // UNWRAP: There are only ever a handful of files.
+        u32::try_from(pos).unwrap()
+    }
+
+    /// Add the entries of an earth file. Duplicates within the file are all kept.
+    pub(super) fn extend(
+        &mut self,
+        entries: impl IntoIterator<Item = NavEntry>,
+        layer: Layer,
+        file: &Path,
+    ) {
+        let file = Some(self.file_id(file));
+        for entry in entries {
+            self.positions
+                .entry(OverrideKey::of(&entry))
+                .or_insert(self.entries.len());
+            self.entries.push(entry);
+            self.provenance.entries.push(Some(EntryProvenance {
+                layer,
+                file,
+                replaced: None,
+            }));
+        }
+    }
+
+    /// Add an entry from a layer of higher precedence than those so far, replacing the
+    /// first matching entry, if there is one.
+    pub(super) fn overlay(&mut self, entry: NavEntry, layer: Layer, file: &Path) {
+        let file = Some(self.file_id(file));
+        let key = OverrideKey::of(&entry);
+        if let Some(&pos) = self.positions.get(&key) {
+            let replaced = self.provenance.entries[pos].map(|old| old.layer);
+            self.entries[pos] = entry;
+            self.provenance.entries[pos] = Some(EntryProvenance {
+                layer,
+                file,
+                replaced,
+            });
+        } else {
+            self.positions.insert(key, self.entries.len());
+            self.entries.push(entry);
+            self.provenance.entries.push(Some(EntryProvenance {
+                layer,
+                file,
+                replaced: None,
+            }));
+        }
+    }
+
+    /// The provenance of the entries, by their position, which will be their node
+    /// index.
+    pub(super) fn into_provenance(self) -> Provenances {
+        self.provenance
+    }
+}
+
+impl NavGraph {
+    #[must_use]
+    /// Where the entry at `idx` came from, if known.
+    pub fn provenance(&self, idx: NodeIndex) -> Option<Provenance<'_>> {
+        self.provenance.get(idx)
+    }
+
+    /// Add an entry at runtime, in the [`Layer::Runtime`] layer. If there is a matching
+    /// entry, it is replaced, keeping its airways and holds. Otherwise, the entry is
+    /// added as a new node.
+    pub fn add_entry(&mut self, entry: NavEntry) -> NodeIndex {
+        let key = OverrideKey::of(&entry);
+        let existing = self
+            .ident_index
+            .get(entry.ident())
+            .iter()
+            .copied()
+            .find(|idx| OverrideKey::of(&self.graph[*idx]) == key);
+        if let Some(idx) = existing {
+            let replaced = self.provenance.get(idx).map(|old| old.layer);
+            self.spatial_index.remove(idx, &self.graph[idx]);
+            self.spatial_index.insert(idx, &entry);
+            self.graph[idx] = entry;
+            self.provenance.set(
+                idx,
+                EntryProvenance {
+                    layer: Layer::Runtime,
+                    file: None,
+                    replaced,
+                },
+            );
+            idx
+        } else {
+            let idx = self.graph.add_node(entry);
+            self.ident_index.insert(idx, &self.graph[idx]);
+            self.spatial_index.insert(idx, &self.graph[idx]);
+            self.provenance.set(
+                idx,
+                EntryProvenance {
+                    layer: Layer::Runtime,
+                    file: None,
+                    replaced: None,
+                },
+            );
+            idx
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::ErrorKind};
+
+    use crate::navdata::{
+        layers::{DataFolders, Layer, EARTH_FILES},
+        source::DirSource,
+        tests::{fix, write_test_data},
+        NavEntry, NavGraph, ParseError,
+    };
+
+    #[test]
+    fn layers_take_precedence() {
+        let default_data = write_test_data("layers-default");
+        // Custom Data with only user files, so the earth files are the default data.
+        let custom_data = write_test_data("layers-custom");
+        for name in EARTH_FILES {
+            fs::remove_file(custom_data.join(name)).unwrap();
+        }
+        let header = "I\n1200 Version - data cycle 2401, build 20240102, \
+                      metadata FixXP1200. Test data.\n\n";
+        fs::write(
+            custom_data.join("user_fix.dat"),
+            format!(
+                "{header} 37.500000000 -122.000000000 ALPHA ENRT K2 2105430\n \
+                 39.000000000 -122.000000000 CHRLI ENRT K2 2105430\n99\n"
+            ),
+        )
+        .unwrap();
+        let folders = DataFolders {
+            default_data: Some(Box::new(DirSource::new(&default_data))),
+            custom_data: Some(Box::new(DirSource::new(&custom_data))),
+        };
+        let mut nav_graph = NavGraph::build_data_from_folders(&folders).unwrap();
+
+        let alpha = nav_graph.find_nav_entry("ALPHA");
+        assert_eq!(alpha.len(), 1);
+        let (alpha, entry) = alpha[0];
+        assert!((entry.lat() - 37.5).abs() < f64::EPSILON);
+        let provenance = nav_graph.provenance(alpha).unwrap();
+        assert_eq!(provenance.layer, Layer::User);
+        assert_eq!(
+            provenance.file,
+            Some(custom_data.join("user_fix.dat").as_path())
+        );
+        assert_eq!(provenance.replaced, Some(Layer::DefaultData));
+
+        let bravo = nav_graph.find_nav_entry("BRAVO")[0].0;
+        let provenance = nav_graph.provenance(bravo).unwrap();
+        assert_eq!(provenance.layer, Layer::DefaultData);
+        assert_eq!(provenance.replaced, None);
+
+        let chrli = nav_graph.find_nav_entry("CHRLI")[0].0;
+        assert_eq!(nav_graph.provenance(chrli).unwrap().layer, Layer::User);
+
+        let sfo = nav_graph.find_nav_entry("SFO")[0].0;
+        let provenance = nav_graph.provenance(sfo).unwrap();
+        assert_eq!(provenance.layer, Layer::DefaultData);
+        assert_eq!(
+            provenance.file,
+            Some(default_data.join("earth_nav.dat").as_path())
+        );
+
+        // The user's ALPHA is replaced in place, keeping its airways.
+        let mut runtime_alpha = fix("ALPHA", 36.0, -122.0);
+        if let NavEntry::Fix(fix) = &mut runtime_alpha {
+            fix.icao_region = "K2".try_into().unwrap();
+        }
+        assert_eq!(nav_graph.add_entry(runtime_alpha), alpha);
+        let provenance = nav_graph.provenance(alpha).unwrap();
+        assert_eq!(provenance.layer, Layer::Runtime);
+        assert_eq!(provenance.file, None);
+        assert_eq!(provenance.replaced, Some(Layer::User));
+        assert!(nav_graph.airway_find(alpha, "J1", "BRAVO").is_ok());
+        assert_eq!(nav_graph.nearest(36.0, -122.0, 1, None)[0].0, alpha);
+
+        let delta = nav_graph.add_entry(fix("DELTA", 40.0, -122.0));
+        assert_ne!(delta, alpha);
+        assert_eq!(nav_graph.provenance(delta).unwrap().replaced, None);
+
+        fs::remove_dir_all(default_data).unwrap();
+        fs::remove_dir_all(custom_data).unwrap();
+    }
+
+    #[test]
+    fn earth_files_are_not_mixed() {
+        let default_data = write_test_data("layers-mixed-default");
+        let custom_data = write_test_data("layers-mixed-custom");
+        let folders = DataFolders {
+            default_data: Some(Box::new(DirSource::new(&default_data))),
+            custom_data: Some(Box::new(DirSource::new(&custom_data))),
+        };
+        let nav_graph = NavGraph::build_data_from_folders(&folders).unwrap();
+        let sfo = nav_graph.find_nav_entry("SFO")[0].0;
+        assert_eq!(nav_graph.provenance(sfo).unwrap().layer, Layer::CustomData);
+
+        // A partial Custom Data folder doesn't fall back to the default data.
+        for name in EARTH_FILES
+            .into_iter()
+            .filter(|&name| name != "earth_fix.dat")
+        {
+            fs::remove_file(custom_data.join(name)).unwrap();
+        }
+        let err = NavGraph::build_data_from_folders(&folders).err().unwrap();
+        assert!(
+            matches!(
+                err.without_location(),
+                ParseError::Io { source, .. }
+                    if source.kind() == ErrorKind::NotFound
+                        && source.to_string().contains("earth_nav.dat")
+            ),
+            "unexpected error: {err}"
+        );
+
+        fs::remove_dir_all(default_data).unwrap();
+        fs::remove_dir_all(custom_data).unwrap();
+    }
+}
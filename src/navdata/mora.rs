@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Structures and parser for grid minimum off-route altitudes (`earth_mora.dat`), in
+//! XPMORA1150.
+//!
+//! Each row starts with the latitude and longitude of the southwest corner of a 1° by
+//! 1° cell, like `N47W123`, followed by the MORAs of that cell and the 29 cells east of
+//! it, in hundreds of feet. Unknown MORAs are written as `UNK`.
+
+use std::io::{BufRead, Read};
+
+use snafu::ensure;
+use winnow::{
+    ascii::{dec_uint, space0, space1},
+    combinator::{alt, preceded, repeat},
+    prelude::*,
+    token::{one_of, take},
+    trace::trace,
+    Located,
+};
+
+use crate::navdata::{
+    for_each_row, parse_line, DataVersion, Header, ParseError,
+    UnsupportedVersionSnafu,
+};
+
+const CELLS_PER_ROW: usize = 30;
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// A worldwide grid of MORAs, in 1° by 1° cells.
+pub struct MoraGrid {
+    /// MORAs in hundreds of feet, by latitude from the south, then by longitude from
+    /// the west. Empty if no MORAs have been loaded.
+    cells: Vec<Option<u16>>,
+}
+
+impl MoraGrid {
+    fn cell_index(lat: i16, lon: i16) -> Option<usize> {
+        let lat = usize::try_from(lat + 90).ok().filter(|lat| *lat < 180)?;
+        let lon = usize::try_from(lon + 180).ok().filter(|lon| *lon < 360)?;
+        Some(lat * 360 + lon)
+    }
+
+    #[must_use]
+    /// The MORA in feet of the cell containing the given position, if known.
+    pub fn mora_ft(&self, lat: f64, lon: f64) -> Option<u32> {
+        #[allow(clippy::cast_possible_truncation)]
+        let index = Self::cell_index(lat.floor() as i16, lon.floor() as i16)?;
+        self.cells
+            .get(index)
+            .copied()
+            .flatten()
+            .map(|mora| u32::from(mora) * 100)
+    }
+}
+
+pub(super) struct Moras {
+    pub header: Header,
+    pub grid: MoraGrid,
+}
+
+/// Parse a MORA grid. If `diagnostics` is given, bad rows are skipped, and their
+/// errors are added to it.
+pub(super) fn parse_file_buffered<F: Read + BufRead>(
+    file: F,
+    diagnostics: Option<&mut Vec<ParseError>>,
+) -> Result<Moras, ParseError> {
+    let mut lines = file.lines();
+    let header = super::parse_header(|md_type| md_type == "MORAXP1150", &mut lines)?;
+    ensure!(
+        matches!(header.version, DataVersion::XP1150),
+        UnsupportedVersionSnafu {
+            version: header.version,
+        }
+    );
+    let mut grid = MoraGrid {
+        cells: vec![None; 180 * 360],
+    };
+    for_each_row(lines, diagnostics, |line_number, line| {
+        let (lat, lon, moras) =
+            parse_line(line_number, line, "MORA row", parse_row)?;
+        for (offset, mora) in (0i16..).zip(moras) {
+            // Rows may run past the antimeridian.
+            let lon = (lon + offset + 180).rem_euclid(360) - 180;
+            if let Some(index) = MoraGrid::cell_index(lat, lon) {
+                grid.cells[index] = mora;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(Moras { header, grid })
+}
+
+fn coordinate(
+    digits: usize,
+    positive: char,
+    negative: char,
+) -> impl Fn(&mut Located<&str>) -> PResult<i16> {
+    move |input: &mut Located<&str>| {
+        let hemisphere = one_of([positive, negative]).parse_next(input)?;
+        let value: u16 = take(digits).and_then(dec_uint).parse_next(input)?;
+        // No more than three digits, so this can't wrap.
+        #[allow(clippy::cast_possible_wrap)]
+        let value = value as i16;
+        Ok(if hemisphere == negative {
+            -value
+        } else {
+            value
+        })
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn parse_row(input: &mut Located<&str>) -> PResult<(i16, i16, Vec<Option<u16>>)> {
+    let lat = trace("latitude", preceded(space0, coordinate(2, 'N', 'S')))
+        .parse_next(input)?;
+    let lon = trace("longitude", coordinate(3, 'E', 'W')).parse_next(input)?;
+    let moras = trace(
+        "MORAs, hundreds of feet",
+        repeat(
+            1..=CELLS_PER_ROW,
+            preceded(space1, alt(("UNK".map(|_| None), dec_uint.map(Some)))),
+        ),
+    )
+    .parse_next(input)?;
+    let _ = space0.parse_next(input)?;
+    Ok((lat, lon, moras))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::navdata::mora::parse_file_buffered;
+
+    fn parse(rows: &str) -> super::MoraGrid {
+        let file = format!(
+            "I\n1150 Version - data cycle 2401, build 20240101, metadata MORAXP1150. \
+             Test data.\n\n{rows}99\n"
+        );
+        parse_file_buffered(file.as_bytes(), None).unwrap().grid
+    }
+
+    #[test]
+    fn unknown_cells() {
+        let grid = parse("N37W123 050 UNK 065\n");
+        assert_eq!(grid.mora_ft(37.5, -122.5), Some(5000));
+        assert_eq!(grid.mora_ft(37.5, -121.5), None);
+        assert_eq!(grid.mora_ft(37.5, -120.5), Some(6500));
+        // Cells past the end of the row, and rows that aren't in the file.
+        assert_eq!(grid.mora_ft(37.5, -119.5), None);
+        assert_eq!(grid.mora_ft(38.5, -122.5), None);
+    }
+
+    #[test]
+    fn row_past_antimeridian() {
+        let grid = parse("N10E178 010 020 030 040\n");
+        assert_eq!(grid.mora_ft(10.5, 178.5), Some(1000));
+        assert_eq!(grid.mora_ft(10.5, 179.5), Some(2000));
+        assert_eq!(grid.mora_ft(10.5, -179.5), Some(3000));
+        assert_eq!(grid.mora_ft(10.5, -178.5), Some(4000));
+    }
+
+    #[test]
+    fn southern_and_western_cells() {
+        let grid = parse("S34W071 120 080\nS01E000 015\n");
+        assert_eq!(grid.mora_ft(-33.5, -70.5), Some(12000));
+        assert_eq!(grid.mora_ft(-34.0, -71.0), Some(12000));
+        assert_eq!(grid.mora_ft(-33.5, -69.5), Some(8000));
+        assert_eq!(grid.mora_ft(-32.5, -70.5), None);
+        assert_eq!(grid.mora_ft(-0.5, 0.5), Some(1500));
+        assert_eq!(grid.mora_ft(0.5, 0.5), None);
+    }
+}
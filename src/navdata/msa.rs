@@ -0,0 +1,276 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Structures and parser for minimum sector altitudes (`earth_msa.dat`), in XPMSA1150.
+//!
+//! Each row gives the reference point of the MSA by ident, ICAO region, and type code
+//! (as in `earth_awy.dat`), the airport it belongs to, and its MSA multiple code. Up to
+//! seven sectors follow, each as the magnetic bearing it starts at, its altitude in
+//! hundreds of feet, and its radius in nautical miles. Unused sectors are all zeroes.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Read},
+};
+
+use petgraph::graph::{DiGraph, NodeIndex};
+use snafu::ensure;
+use winnow::{
+    ascii::{dec_uint, space0, space1},
+    combinator::{preceded, repeat},
+    prelude::*,
+    stream::AsChar,
+    token::any,
+    trace::trace,
+    Located,
+};
+
+use crate::navdata::{
+    for_each_row, ident_index::IdentIndex, parse_line, take_hstring_till,
+    DataVersion, Header, NavEdge, NavEntry, ParseError, UnsupportedVersionSnafu,
+    WptRef, WptType,
+};
+
+const MAX_SECTORS: usize = 7;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// A minimum sector altitude, centered on a fix or navaid.
+pub struct Msa {
+    /// The airport this MSA is published for.
+    pub airport_icao: heapless::String<4>,
+    /// Tells apart MSAs of one airport with the same reference point.
+    pub multiple_code: char,
+    /// The sectors of this MSA, clockwise.
+    pub sectors: Vec<MsaSector>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// An arc of an MSA, running clockwise from `from_bearing_mag` to `to_bearing_mag`.
+pub struct MsaSector {
+    pub from_bearing_mag: u16,
+    pub to_bearing_mag: u16,
+    pub altitude_ft: u32,
+    pub radius_nm: u8,
+}
+
+impl Msa {
+    #[must_use]
+    /// The sector containing the magnetic bearing `bearing_mag`, if any.
+    pub fn sector_at(&self, bearing_mag: f32) -> Option<&MsaSector> {
+        let bearing = bearing_mag.rem_euclid(360.0);
+        self.sectors.iter().find(|sector| {
+            let from = f32::from(sector.from_bearing_mag);
+            let to = f32::from(sector.to_bearing_mag);
+            if from < to {
+                (from..to).contains(&bearing)
+            } else {
+                // The sector wraps through north, or is a full circle.
+                bearing >= from || bearing < to
+            }
+        })
+    }
+}
+
+/// Parse MSAs, attaching them to the nodes of their reference points. MSAs centered on
+/// points that are not in `nav_graph`, like runway thresholds, are skipped. If
+/// `diagnostics` is given, bad rows are skipped too, and their errors are added to it.
+pub(super) fn parse_file_buffered<F: Read + BufRead>(
+    file: F,
+    nav_graph: &DiGraph<NavEntry, NavEdge>,
+    ident_index: &IdentIndex,
+    diagnostics: Option<&mut Vec<ParseError>>,
+) -> Result<(Header, HashMap<NodeIndex, Vec<Msa>>), ParseError> {
+    let mut lines = file.lines();
+    let header = super::parse_header(|md_type| md_type == "MSAXP1150", &mut lines)?;
+    ensure!(
+        matches!(header.version, DataVersion::XP1150),
+        UnsupportedVersionSnafu {
+            version: header.version,
+        }
+    );
+    let mut msas: HashMap<NodeIndex, Vec<Msa>> = HashMap::new();
+    for_each_row(lines, diagnostics, |line_number, line| {
+        let (center, msa) = parse_line(line_number, line, "MSA row", parse_row)?;
+        if let Some(idx) = ident_index.find_wpt(&center, nav_graph) {
+            msas.entry(idx).or_default().push(msa);
+        }
+        Ok(())
+    })?;
+    Ok((header, msas))
+}
+
+fn parse_row(input: &mut Located<&str>) -> PResult<(WptRef, Msa)> {
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let icao_region = trace(
+        "ICAO region code",
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let typ = trace(
+        "point type",
+        preceded(space1, dec_uint).verify_map(WptType::from_code),
+    )
+    .parse_next(input)?;
+    let airport_icao = trace(
+        "airport ICAO code",
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let multiple_code =
+        trace("MSA multiple code", preceded(space1, any)).parse_next(input)?;
+    let raw_sectors: Vec<(u16, u16, u8)> = trace(
+        "sectors",
+        repeat(
+            1..=MAX_SECTORS,
+            (
+                preceded(space1, dec_uint),
+                preceded(space1, dec_uint),
+                preceded(space1, dec_uint),
+            ),
+        ),
+    )
+    .parse_next(input)?;
+    let _ = space0.parse_next(input)?;
+
+    let raw_sectors: Vec<_> = raw_sectors
+        .into_iter()
+        .filter(|(_, _, radius_nm)| *radius_nm != 0)
+        .collect();
+    let sectors = raw_sectors
+        .iter()
+        .zip(raw_sectors.iter().cycle().skip(1))
+        .map(|(&(from, altitude, radius_nm), &(to, _, _))| MsaSector {
+            from_bearing_mag: from % 360,
+            to_bearing_mag: to % 360,
+            altitude_ft: u32::from(altitude) * 100,
+            radius_nm,
+        })
+        .collect();
+    Ok((
+        WptRef {
+            ident,
+            icao_region,
+            typ,
+        },
+        Msa {
+            airport_icao,
+            multiple_code,
+            sectors,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+    use winnow::{Located, Parser};
+
+    use crate::navdata::{
+        ident_index::IdentIndex,
+        msa::{parse_file_buffered, parse_row, Msa, MsaSector},
+        tests::fix,
+    };
+
+    fn msa(row: &str) -> Msa {
+        parse_row.parse(Located::new(row)).unwrap().1
+    }
+
+    fn altitude_at(msa: &Msa, bearing_mag: f32) -> Option<u32> {
+        msa.sector_at(bearing_mag).map(|sector| sector.altitude_ft)
+    }
+
+    #[test]
+    fn sector_wrapping_through_north() {
+        let msa = msa("SFO K2 3 KSFO A 270 030 25 090 020 25");
+        assert_eq!(
+            msa.sectors[0],
+            MsaSector {
+                from_bearing_mag: 270,
+                to_bearing_mag: 90,
+                altitude_ft: 3000,
+                radius_nm: 25,
+            }
+        );
+        assert_eq!(altitude_at(&msa, 270.0), Some(3000));
+        assert_eq!(altitude_at(&msa, 0.0), Some(3000));
+        assert_eq!(altitude_at(&msa, 359.5), Some(3000));
+        assert_eq!(altitude_at(&msa, -10.0), Some(3000));
+        assert_eq!(altitude_at(&msa, 90.0), Some(2000));
+        assert_eq!(altitude_at(&msa, 180.0), Some(2000));
+    }
+
+    #[test]
+    fn single_sector_is_full_circle() {
+        for row in [
+            "SFO K2 3 KSFO A 000 045 25",
+            "SFO K2 3 KSFO A 090 045 25 000 000 00",
+        ] {
+            let msa = msa(row);
+            assert_eq!(msa.sectors.len(), 1);
+            for bearing in [0.0, 89.0, 90.0, 180.0, 359.9] {
+                assert_eq!(altitude_at(&msa, bearing), Some(4500));
+            }
+        }
+    }
+
+    #[test]
+    fn zero_padding_sectors_are_skipped() {
+        let msa = msa(
+            "SFO K2 3 KSFO A 000 045 25 180 080 25 000 000 00 000 000 00 000 000 00",
+        );
+        assert_eq!(msa.sectors.len(), 2);
+        assert_eq!(
+            (
+                msa.sectors[0].from_bearing_mag,
+                msa.sectors[0].to_bearing_mag
+            ),
+            (0, 180)
+        );
+        assert_eq!(
+            (
+                msa.sectors[1].from_bearing_mag,
+                msa.sectors[1].to_bearing_mag
+            ),
+            (180, 0)
+        );
+        assert_eq!(altitude_at(&msa, 90.0), Some(4500));
+        assert_eq!(altitude_at(&msa, 270.0), Some(8000));
+    }
+
+    #[test]
+    fn msa_of_missing_reference_point_is_skipped() {
+        let mut graph = DiGraph::new();
+        let alpha = graph.add_node(fix("ALPHA", 37.0, -122.0));
+        let ident_index = IdentIndex::build(&graph);
+        let file = "I\n1150 Version - data cycle 2401, build 20240101, metadata \
+                    MSAXP1150. Test data.\n\n\
+                    ALPHA ZZ 11 KSFO A 000 045 25\n\
+                    RW28L K2 11 KSFO B 000 050 25\n\
+                    99\n";
+        let mut diagnostics = Vec::new();
+        let (_, msas) = parse_file_buffered(
+            file.as_bytes(),
+            &graph,
+            &ident_index,
+            Some(&mut diagnostics),
+        )
+        .unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(msas.len(), 1);
+        assert_eq!(msas[&alpha].len(), 1);
+        assert_eq!(msas[&alpha][0].multiple_code, 'A');
+    }
+}
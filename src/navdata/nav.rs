@@ -6,9 +6,8 @@
 //! Structures and parsers for XPNAV1200 and XPNAV1150.
 //! Older versions of navdata are not supported.
 
-use std::io::{BufRead, Read};
+use std::io::{BufRead, BufReader, Read, Write};
 
-use itertools::Itertools;
 use num_enum::{FromPrimitive, IntoPrimitive};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
@@ -23,18 +22,20 @@ use winnow::{
     Located,
 };
 
-use crate::navdata::{
-    take_hstring_till, BadLastLineSnafu, DataVersion, Header, ParseError,
-    ParseSnafu, UnsupportedVersionSnafu,
+use crate::{
+    navdata::{
+        parse_line, take_hstring_till, DataVersion, Header, ParseError, Rows,
+        UnsupportedVersionSnafu, UnwritableVersionSnafu, WriteError,
+    },
+    radio::{Channel, LfFrequency, VhfFrequency},
 };
 
-pub(super) struct Navaids {
-    pub header: Header,
-    pub entries: Vec<Navaid>,
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// A navaid.
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct Navaid {
     pub lat: f64,
     pub lon: f64,
@@ -44,11 +45,46 @@ pub struct Navaid {
     pub type_data: TypeSpecificData,
 }
 
-#[derive(Debug, Clone)]
+impl Navaid {
+    #[must_use]
+    /// The VHF frequency to tune for this navaid: that of a VOR, localizer, or
+    /// glideslope, the one paired with a DME, or the VDB frequency of a GLS. [`None`]
+    /// for other navaids.
+    pub fn vhf_frequency(&self) -> Option<VhfFrequency> {
+        match &self.type_data {
+            TypeSpecificData::Vor { freq, .. }
+            | TypeSpecificData::Localizer { freq, .. }
+            | TypeSpecificData::Glideslope { freq, .. } => Some(*freq),
+            TypeSpecificData::Dme { paired_freq, .. } => Some(*paired_freq),
+            TypeSpecificData::Gls { channel, .. } => channel.paired_frequency(),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    /// The DME or TACAN channel of this navaid, for TACAN radios: that of a DME, or
+    /// the one paired with a VOR or localizer. [`None`] for other navaids, or if the
+    /// frequency is not paired with a channel.
+    pub fn tacan_channel(&self) -> Option<Channel> {
+        match &self.type_data {
+            TypeSpecificData::Vor { freq, .. }
+            | TypeSpecificData::Localizer { freq, .. } => freq.paired_channel(),
+            TypeSpecificData::Dme { paired_freq, .. } => {
+                paired_freq.paired_channel()
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum TypeSpecificData {
     Ndb {
-        /// The frequency of this NDB, in whole kHz.
-        freq_khz: u16,
+        freq: LfFrequency,
         class: NdbClass,
         /// 1.0 if use of BFO is required.
         /// 0.0 otherwise.
@@ -61,8 +97,7 @@ pub enum TypeSpecificData {
     },
 
     Vor {
-        /// The frequency of this VOR, in 10s of kHz.
-        freq_10khz: u32,
+        freq: VhfFrequency,
         class: VorClass,
         slaved_variation: f32,
         name: String,
@@ -70,7 +105,7 @@ pub enum TypeSpecificData {
 
     Localizer {
         is_with_ils: bool,
-        freq_10khz: u32,
+        freq: VhfFrequency,
         max_range: u16,
         crs_mag: f32,
         crs_true: f32,
@@ -85,7 +120,7 @@ pub enum TypeSpecificData {
     },
 
     Glideslope {
-        freq_10khz: u32,
+        freq: VhfFrequency,
         max_range: u16,
         loc_crs_true: f32,
         /// Hundredths of a degree. `u16::MAX` should be interpreted as an error.
@@ -106,7 +141,7 @@ pub enum TypeSpecificData {
 
     Dme {
         display_freq: bool,
-        paired_freq_10khz: u32,
+        paired_freq: VhfFrequency,
         service_volume: u16,
         bias: f32,
         terminal_region: heapless::String<4>,
@@ -114,7 +149,8 @@ pub enum TypeSpecificData {
     },
 
     Fpap {
-        channel: u32,
+        /// An approach channel. Rows can only be written with approach channels.
+        channel: Channel,
         length_offset: f32,
         final_app_crs_true: f32,
         airport_icao: heapless::String<4>,
@@ -123,7 +159,8 @@ pub enum TypeSpecificData {
     },
 
     ThresholdPoint {
-        channel: u32,
+        /// An approach channel. Rows can only be written with approach channels.
+        channel: Channel,
         thres_cross_height: f32,
         final_app_crs_true: f32,
 
@@ -143,7 +180,8 @@ pub enum TypeSpecificData {
     },
 
     Gls {
-        channel: u32,
+        /// An approach channel. Rows can only be written with approach channels.
+        channel: Channel,
         final_app_crs_true: f32,
         /// Hundredths of a degree. `u16::MAX` should be interpreted as an error.
         glide_path_angle: u16,
@@ -155,7 +193,11 @@ pub enum TypeSpecificData {
 }
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, FromPrimitive, IntoPrimitive)]
+#[derive(Debug, Copy, Clone, FromPrimitive, IntoPrimitive, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum NdbClass {
     Locator = 15,
     LowPower = 25,
@@ -165,8 +207,21 @@ pub enum NdbClass {
     Unrecognized(u8),
 }
 
+impl NdbClass {
+    #[must_use]
+    /// The nominal reception range of NDBs of this class, in nautical miles. The
+    /// class is coded as this range.
+    pub fn range_nm(self) -> u8 {
+        self.into()
+    }
+}
+
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, FromPrimitive, IntoPrimitive)]
+#[derive(Debug, Copy, Clone, FromPrimitive, IntoPrimitive, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum VorClass {
     /// Terminal, low power.
     Terminal = 25,
@@ -181,16 +236,46 @@ pub enum VorClass {
     Unrecognized(u8),
 }
 
-#[derive(Debug, Clone, Copy)]
+impl VorClass {
+    #[must_use]
+    /// The nominal reception range of VORs of this class, in nautical miles. The
+    /// class is coded as this range.
+    pub fn range_nm(self) -> u8 {
+        self.into()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub enum MarkerType {
     Outer,
     Middle,
     Inner,
 }
 
-pub(super) fn parse_file_buffered<F: Read + BufRead>(
+/// Parse the header of a navaid file, returning a streaming reader of its rows. This
+/// works for both `earth_nav.dat` and `user_nav.dat`.
+///
+/// # Errors
+/// Returns an [`Err`] if there is an I/O error, or if the header is malformed or of an
+/// unsupported version.
+pub fn parse_file<F: Read>(
     file: F,
-) -> Result<Navaids, ParseError> {
+) -> Result<Rows<BufReader<F>, Navaid>, ParseError> {
+    parse_file_buffered(BufReader::new(file))
+}
+
+/// Like [`parse_file`], for an already buffered reader.
+///
+/// # Errors
+/// Returns an [`Err`] if there is an I/O error, or if the header is malformed or of an
+/// unsupported version.
+pub fn parse_file_buffered<F: BufRead>(
+    file: F,
+) -> Result<Rows<F, Navaid>, ParseError> {
     let mut lines = file.lines();
     let header = super::parse_header(
         |md_type| md_type == "NavXP1200" || md_type == "NavXP1150",
@@ -204,38 +289,9 @@ pub(super) fn parse_file_buffered<F: Read + BufRead>(
         }
     );
 
-    let mut lines = lines
-        .filter(|lin| lin.as_ref().map_or(true, |lin| !lin.is_empty()))
-        .peekable();
-
-    #[allow(clippy::let_and_return)]
-    // Have to let and return to fix a lifetime error.
-    let entries: Result<Vec<_>, ParseError> = lines
-        .peeking_take_while(|l| l.as_ref().map_or(true, |l| l != "99"))
-        .map(|line| {
-            let line = line?;
-            let ret = trace("parse navaid row", parse_row)
-                .parse(Located::new(&line))
-                .map_err(|e| {
-                    ParseSnafu {
-                        rendered: e.to_string(),
-                        stage: "navaid row",
-                    }
-                    .build()
-                });
-            ret
-        })
-        .collect();
-    let entries = entries?;
-    lines
-        .next()
-        .ok_or_else(|| ParseError::MissingLine)
-        .and_then(|last_line| {
-            let last_line = last_line?;
-            ensure!(last_line == "99", BadLastLineSnafu { last_line });
-            Ok(())
-        })?;
-    Ok(Navaids { header, entries })
+    Ok(Rows::new(header, lines, |line_number, line| {
+        parse_line(line_number, line, "navaid row", parse_row)
+    }))
 }
 
 fn parse_row(input: &mut Located<&str>) -> PResult<Navaid> {
@@ -282,22 +338,26 @@ fn parse_row_lead(input: &mut Located<&str>) -> PResult<RowLead> {
 
 fn parse_ndb(input: &mut Located<&str>) -> PResult<Navaid> {
     let lead = trace("row lead", parse_row_lead).parse_next(input)?;
-    let freq_khz: u16 =
-        trace("frequency, kHz", preceded(space1, dec_uint)).parse_next(input)?;
+    let freq = trace("frequency, kHz", preceded(space1, dec_uint))
+        .map(LfFrequency::from_khz)
+        .parse_next(input)?;
     let class: NdbClass = trace("class", preceded(space1, dec_uint::<_, u8, _>))
         .parse_next(input)?
         .into();
     let flags: f32 = trace("flags", preceded(space1, float)).parse_next(input)?;
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let terminal_region = trace(
         "terminal region",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let name = trace("name", delimited(space1, rest, space0))
@@ -310,7 +370,7 @@ fn parse_ndb(input: &mut Located<&str>) -> PResult<Navaid> {
         icao_region: icao_region_code,
         ident,
         type_data: TypeSpecificData::Ndb {
-            freq_khz,
+            freq,
             class,
             flags,
             terminal_region,
@@ -321,21 +381,25 @@ fn parse_ndb(input: &mut Located<&str>) -> PResult<Navaid> {
 
 fn parse_vor(input: &mut Located<&str>) -> PResult<Navaid> {
     let lead = trace("row lead", parse_row_lead).parse_next(input)?;
-    let freq_10khz: u32 =
-        trace("frequency, 10 kHz", preceded(space1, dec_uint)).parse_next(input)?;
+    let freq = trace("frequency, 10 kHz", preceded(space1, dec_uint))
+        .map(VhfFrequency::from_10khz)
+        .parse_next(input)?;
     let class: VorClass = trace("class", preceded(space1, dec_uint::<_, u8, _>))
         .parse_next(input)?
         .into();
     let slaved_variation: f32 =
         trace("slaved variation, degrees", preceded(space1, float))
             .parse_next(input)?;
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let _ = trace("ensure terminal region for VOR is ENRT", " ENRT")
         .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let name = trace("name", delimited(space1, rest, space0))
@@ -348,7 +412,7 @@ fn parse_vor(input: &mut Located<&str>) -> PResult<Navaid> {
         icao_region: icao_region_code,
         ident,
         type_data: TypeSpecificData::Vor {
-            freq_10khz,
+            freq,
             class,
             slaved_variation,
             name,
@@ -364,8 +428,9 @@ fn parse_loc(input: &mut Located<&str>) -> PResult<Navaid> {
         5 => false,
         _ => unreachable!("What the hell?"),
     };
-    let freq_10khz: u32 =
-        trace("frequency, 10 kHz", preceded(space1, dec_uint)).parse_next(input)?;
+    let freq = trace("frequency, 10 kHz", preceded(space1, dec_uint))
+        .map(VhfFrequency::from_10khz)
+        .parse_next(input)?;
     let max_range: u16 =
         trace("maximum reception range", preceded(space1, dec_uint))
             .parse_next(input)?;
@@ -381,20 +446,26 @@ fn parse_loc(input: &mut Located<&str>) -> PResult<Navaid> {
         .to_f32()
         .unwrap_or(f32::NAN);
     let crs_true: f32 = crs_true.to_f32().unwrap_or(f32::NAN);
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let airport_icao = trace(
         "airport ICAO code",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let rwy = trace(
+        "runway",
+        preceded(space0, take_hstring_till::<3, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let rwy = trace("runway", take_hstring_till::<3, _>(AsChar::is_space))
-        .parse_next(input)?;
     let name = trace("name", delimited(space1, rest, space0))
         .parse_next(input)?
         .to_owned();
@@ -406,7 +477,7 @@ fn parse_loc(input: &mut Located<&str>) -> PResult<Navaid> {
         ident,
         type_data: TypeSpecificData::Localizer {
             is_with_ils,
-            freq_10khz,
+            freq,
             max_range,
             crs_mag,
             crs_true,
@@ -419,8 +490,9 @@ fn parse_loc(input: &mut Located<&str>) -> PResult<Navaid> {
 
 fn parse_gs(input: &mut Located<&str>) -> PResult<Navaid> {
     let lead = trace("row lead", parse_row_lead).parse_next(input)?;
-    let freq_10khz: u32 =
-        trace("frequency, 10 kHz", preceded(space1, dec_uint)).parse_next(input)?;
+    let freq = trace("frequency, 10 kHz", preceded(space1, dec_uint))
+        .map(VhfFrequency::from_10khz)
+        .parse_next(input)?;
     let max_range: u16 =
         trace("maximum reception range", preceded(space1, dec_uint))
             .parse_next(input)?;
@@ -436,20 +508,26 @@ fn parse_gs(input: &mut Located<&str>) -> PResult<Navaid> {
         .trunc()
         .to_u16()
         .unwrap_or(u16::MAX);
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let airport_icao = trace(
         "airport ICAO code",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let rwy = trace(
+        "runway",
+        preceded(space0, take_hstring_till::<3, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let rwy = trace("runway", take_hstring_till::<3, _>(AsChar::is_space))
-        .parse_next(input)?;
     let name = trace("name", delimited(space1, rest, space0))
         .parse_next(input)?
         .to_owned();
@@ -460,7 +538,7 @@ fn parse_gs(input: &mut Located<&str>) -> PResult<Navaid> {
         icao_region: icao_region_code,
         ident,
         type_data: TypeSpecificData::Glideslope {
-            freq_10khz,
+            freq,
             max_range,
             loc_crs_true,
             glide_angle,
@@ -484,22 +562,31 @@ fn parse_mkr(input: &mut Located<&str>) -> PResult<Navaid> {
     let loc_crs_true: f32 =
         trace("localizer course, true degrees", preceded(space1, float))
             .parse_next(input)?;
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let airport_icao = trace(
         "airport ICAO code",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let rwy = trace(
+        "runway",
+        preceded(space0, take_hstring_till::<3, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let name = trace(
+        "name",
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let rwy = trace("runway", take_hstring_till::<3, _>(AsChar::is_space))
-        .parse_next(input)?;
-    let name = trace("name", take_hstring_till::<2, _>(AsChar::is_space))
-        .parse_next(input)?;
     Ok(Navaid {
         lat: lead.lat,
         lon: lead.lon,
@@ -523,22 +610,25 @@ fn parse_dme(input: &mut Located<&str>) -> PResult<Navaid> {
         13 => true,
         _ => unreachable!(),
     };
-    let paired_freq_10khz: u32 =
-        trace("paired frequency, 10 kHz", preceded(space1, dec_uint))
-            .parse_next(input)?;
+    let paired_freq = trace("paired frequency, 10 kHz", preceded(space1, dec_uint))
+        .map(VhfFrequency::from_10khz)
+        .parse_next(input)?;
     let service_volume: u16 =
         trace("service volume", preceded(space1, dec_uint)).parse_next(input)?;
     let bias: f32 = trace("bias", preceded(space1, float)).parse_next(input)?;
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let terminal_region = trace(
         "terminal region",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let name = trace("name", delimited(space1, rest, space0))
@@ -552,7 +642,7 @@ fn parse_dme(input: &mut Located<&str>) -> PResult<Navaid> {
         ident,
         type_data: TypeSpecificData::Dme {
             display_freq,
-            paired_freq_10khz,
+            paired_freq,
             service_volume,
             bias,
             terminal_region,
@@ -563,8 +653,9 @@ fn parse_dme(input: &mut Located<&str>) -> PResult<Navaid> {
 
 fn parse_fpap(input: &mut Located<&str>) -> PResult<Navaid> {
     let lead = trace("row lead", parse_row_lead).parse_next(input)?;
-    let channel: u32 =
-        trace("channel", preceded(space1, dec_uint)).parse_next(input)?;
+    let channel = trace("channel", preceded(space1, dec_uint))
+        .map(Channel::Approach)
+        .parse_next(input)?;
     let length_offset: f32 =
         trace("length offset", preceded(space1, float)).parse_next(input)?;
     let final_app_crs_true: f32 = trace(
@@ -572,20 +663,26 @@ fn parse_fpap(input: &mut Located<&str>) -> PResult<Navaid> {
         preceded(space1, float),
     )
     .parse_next(input)?;
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let airport_icao = trace(
         "airport ICAO code",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let rwy = trace(
+        "runway",
+        preceded(space0, take_hstring_till::<3, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let rwy = trace("runway", take_hstring_till::<3, _>(AsChar::is_space))
-        .parse_next(input)?;
     let perf = trace("performance type", delimited(space1, rest, space0))
         .parse_next(input)?
         .to_owned();
@@ -608,8 +705,9 @@ fn parse_fpap(input: &mut Located<&str>) -> PResult<Navaid> {
 
 fn parse_gls(input: &mut Located<&str>) -> PResult<Navaid> {
     let lead = trace("row lead", parse_row_lead).parse_next(input)?;
-    let channel: u32 =
-        trace("channel", preceded(space1, dec_uint)).parse_next(input)?;
+    let channel = trace("channel", preceded(space1, dec_uint))
+        .map(Channel::Approach)
+        .parse_next(input)?;
     let _ = trace("unused number", preceded(space1, digit1)).parse_next(input)?;
     // Listen, the specification about the way this number works is really funny.
     let funny_number: Decimal = trace(
@@ -624,20 +722,26 @@ fn parse_gls(input: &mut Located<&str>) -> PResult<Navaid> {
         .trunc()
         .to_u16()
         .unwrap_or(u16::MAX);
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let airport_icao = trace(
         "airport ICAO code",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let rwy = trace(
+        "runway",
+        preceded(space0, take_hstring_till::<3, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let rwy = trace("runway", take_hstring_till::<3, _>(AsChar::is_space))
-        .parse_next(input)?;
     let ref_path_ident = trace("ref path ident", delimited(space1, rest, space0))
         .parse_next(input)?
         .to_owned();
@@ -660,8 +764,9 @@ fn parse_gls(input: &mut Located<&str>) -> PResult<Navaid> {
 
 fn parse_threshold(input: &mut Located<&str>) -> PResult<Navaid> {
     let lead = trace("row lead", parse_row_lead).parse_next(input)?;
-    let channel: u32 =
-        trace("channel", preceded(space1, dec_uint)).parse_next(input)?;
+    let channel = trace("channel", preceded(space1, dec_uint))
+        .map(Channel::Approach)
+        .parse_next(input)?;
     let thres_cross_height: f32 =
         trace("threshold crossing height", preceded(space1, float))
             .parse_next(input)?;
@@ -678,20 +783,26 @@ fn parse_threshold(input: &mut Located<&str>) -> PResult<Navaid> {
         .trunc()
         .to_u16()
         .unwrap_or(u16::MAX);
-    let ident = trace("ident", take_hstring_till::<5, _>(AsChar::is_space))
-        .parse_next(input)?;
+    let ident = trace(
+        "ident",
+        preceded(space0, take_hstring_till::<5, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
     let airport_icao = trace(
         "airport ICAO code",
-        take_hstring_till::<4, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<4, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
     let icao_region_code = trace(
         "ICAO region code",
-        take_hstring_till::<2, _>(AsChar::is_space),
+        preceded(space0, take_hstring_till::<2, _>(AsChar::is_space)),
+    )
+    .parse_next(input)?;
+    let rwy = trace(
+        "runway",
+        preceded(space0, take_hstring_till::<3, _>(AsChar::is_space)),
     )
     .parse_next(input)?;
-    let rwy = trace("runway", take_hstring_till::<3, _>(AsChar::is_space))
-        .parse_next(input)?;
     let ref_path_ident = trace("ref path ident", delimited(space1, rest, space0))
         .parse_next(input)?
         .to_owned();
@@ -712,3 +823,252 @@ fn parse_threshold(input: &mut Located<&str>) -> PResult<Navaid> {
         },
     })
 }
+
+/// Write `navaids` in the `earth_nav.dat` format, in the version given by `header`. This
+/// is also the format of `user_nav.dat`.
+///
+/// # Errors
+/// Returns an [`Err`] if the version in `header` is not XPNAV1200 or XPNAV1150, or if
+/// writing fails.
+pub fn write_file<W: Write>(
+    header: &Header,
+    navaids: &[Navaid],
+    mut out: W,
+) -> Result<(), WriteError> {
+    let md_type = match header.version {
+        DataVersion::XP1150 => "NavXP1150",
+        DataVersion::XP1200 => "NavXP1200",
+        version => return UnwritableVersionSnafu { version }.fail(),
+    };
+    super::write_header(header, md_type, &mut out)?;
+    for navaid in navaids {
+        write_row(navaid, &mut out)?;
+    }
+    writeln!(out, "99")?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+fn write_row<W: Write>(navaid: &Navaid, out: &mut W) -> Result<(), WriteError> {
+    let Navaid {
+        lat,
+        lon,
+        elevation,
+        icao_region,
+        ident,
+        type_data,
+    } = navaid;
+    let row_code: u8 = match type_data {
+        TypeSpecificData::Ndb { .. } => 2,
+        TypeSpecificData::Vor { .. } => 3,
+        TypeSpecificData::Localizer { is_with_ils, .. } => {
+            if *is_with_ils {
+                4
+            } else {
+                5
+            }
+        },
+        TypeSpecificData::Glideslope { .. } => 6,
+        TypeSpecificData::MarkerBeacon { typ, .. } => match typ {
+            MarkerType::Outer => 7,
+            MarkerType::Middle => 8,
+            MarkerType::Inner => 9,
+        },
+        TypeSpecificData::Dme { display_freq, .. } => {
+            if *display_freq {
+                13
+            } else {
+                12
+            }
+        },
+        TypeSpecificData::Fpap { .. } => 14,
+        TypeSpecificData::Gls { .. } => 15,
+        TypeSpecificData::ThresholdPoint { .. } => 16,
+    };
+    write!(out, "{row_code} {lat:.9} {lon:.9} {elevation} ")?;
+    match type_data {
+        TypeSpecificData::Ndb {
+            freq,
+            class,
+            flags,
+            terminal_region,
+            name,
+        } => writeln!(
+            out,
+            "{} {} {flags} {ident} {terminal_region} {icao_region} {name}",
+            freq.khz(),
+            u8::from(*class)
+        )?,
+        TypeSpecificData::Vor {
+            freq,
+            class,
+            slaved_variation,
+            name,
+        } => writeln!(
+            out,
+            "{} {} {slaved_variation} {ident} ENRT {icao_region} {name}",
+            freq.to_10khz(),
+            u8::from(*class)
+        )?,
+        TypeSpecificData::Localizer {
+            freq,
+            max_range,
+            crs_mag,
+            crs_true,
+            airport_icao,
+            rwy,
+            name,
+            ..
+        } => {
+            let freq_10khz = freq.to_10khz();
+            // The inverse of the funny number in `parse_loc`.
+            let funny_number = decimal(*crs_mag) * dec!(360) + decimal(*crs_true);
+            writeln!(
+                out,
+                "{freq_10khz} {max_range} {funny_number} {ident} {airport_icao} \
+                 {icao_region} {rwy} {name}"
+            )?;
+        },
+        TypeSpecificData::Glideslope {
+            freq,
+            max_range,
+            loc_crs_true,
+            glide_angle,
+            airport_icao,
+            rwy,
+            name,
+        } => {
+            let freq_10khz = freq.to_10khz();
+            let funny_number = pack_angle(*glide_angle, *loc_crs_true);
+            writeln!(
+                out,
+                "{freq_10khz} {max_range} {funny_number} {ident} {airport_icao} \
+                 {icao_region} {rwy} {name}"
+            )?;
+        },
+        TypeSpecificData::MarkerBeacon {
+            loc_crs_true,
+            airport_icao,
+            rwy,
+            name,
+            ..
+        } => writeln!(
+            out,
+            "0 0 {loc_crs_true} {ident} {airport_icao} {icao_region} {rwy} {name}"
+        )?,
+        TypeSpecificData::Dme {
+            paired_freq,
+            service_volume,
+            bias,
+            terminal_region,
+            name,
+            ..
+        } => writeln!(
+            out,
+            "{} {service_volume} {bias} {ident} {terminal_region} {icao_region} \
+             {name}",
+            paired_freq.to_10khz()
+        )?,
+        TypeSpecificData::Fpap {
+            channel,
+            length_offset,
+            final_app_crs_true,
+            airport_icao,
+            rwy,
+            perf,
+        } => writeln!(
+            out,
+            "{channel} {length_offset} {final_app_crs_true} {ident} {airport_icao} \
+             {icao_region} {rwy} {perf}"
+        )?,
+        TypeSpecificData::Gls {
+            channel,
+            final_app_crs_true,
+            glide_path_angle,
+            airport_icao,
+            rwy,
+            ref_path_ident,
+        } => {
+            let funny_number = pack_angle(*glide_path_angle, *final_app_crs_true);
+            writeln!(
+                out,
+                "{channel} 0 {funny_number} {ident} {airport_icao} {icao_region} \
+                 {rwy} {ref_path_ident}"
+            )?;
+        },
+        TypeSpecificData::ThresholdPoint {
+            channel,
+            thres_cross_height,
+            final_app_crs_true,
+            glide_path_angle,
+            airport_icao,
+            rwy,
+            ref_path_ident,
+        } => {
+            let funny_number = pack_angle(*glide_path_angle, *final_app_crs_true);
+            writeln!(
+                out,
+                "{channel} {thres_cross_height} {funny_number} {ident} \
+                 {airport_icao} {icao_region} {rwy} {ref_path_ident}"
+            )?;
+        },
+    }
+    Ok(())
+}
+
+/// The shortest decimal that reads back as `value`, so that courses survive a round
+/// trip through the funny numbers unchanged.
+fn decimal(value: f32) -> Decimal {
+    Decimal::try_from(value).unwrap_or_default().normalize()
+}
+
+/// The inverse of the funny number in `parse_gs`, `parse_gls`, and `parse_threshold`.
+fn pack_angle(angle: u16, crs_true: f32) -> Decimal {
+    Decimal::from(angle) * dec!(1000) + decimal(crs_true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_file, write_file, TypeSpecificData};
+
+    #[test]
+    fn round_trip_navaids() {
+        let data = "I
+1200 Version - data cycle 2401, build 20240101, metadata NavXP1200. Test data.
+
+2  38.087777780  -77.323888890      0   396  25      0.0 APH  ENRT K6 A P HILL NDB
+3  47.435388890 -122.309611110    354 11680 130    19.0 SEA ENRT K1 SEATTLE VORTAC
+4  47.460819440 -122.307897220    432 11030  18 58860.100 ISNQ KSEA K1 16L ILS-cat-III
+6  47.440038890 -122.310788890    363 11030  10 300180.100 ISNQ KSEA K1 16L GS
+7  47.525000000 -122.300000000      0     0   0   180.100 ---- KSEA K1 16L OM
+12 47.435388890 -122.309611110    354 11680 130     0.000 SEA  ENRT K1 SEATTLE VORTAC DME
+13 47.460819440 -122.307897220    432 11030  18     0.150 ISNQ KSEA K1 SEATTLE-TACOMA INTL
+14 47.460000000 -122.300000000    432 56789   0.0 180.100 R16LY KSEA K1 16L LPV
+15 47.460000000 -122.300000000    432 21234   0 300180.100 G16A KSEA K1 16L GLS
+16 47.460000000 -122.300000000    432 56789  56.0 300180.100 R16LY KSEA K1 16L W16B
+99
+";
+        let (header, navaids) =
+            parse_file(data.as_bytes()).unwrap().collect_all().unwrap();
+        assert_eq!(navaids.len(), 10);
+        assert!(matches!(
+            navaids[2].type_data,
+            TypeSpecificData::Localizer { crs_mag, .. } if (crs_mag - 163.0).abs() < 1e-3
+        ));
+        assert!(matches!(
+            &navaids[1].type_data,
+            TypeSpecificData::Vor { freq, .. } if freq.to_string() == "116.80"
+        ));
+        assert_eq!(navaids[5].tacan_channel().unwrap().to_string(), "CH 115X");
+        assert_eq!(navaids[8].vhf_frequency().unwrap().to_string(), "108.025");
+
+        let mut written = Vec::new();
+        write_file(&header, &navaids, &mut written).unwrap();
+        let (reheader, renavaids) = parse_file(written.as_slice())
+            .unwrap()
+            .collect_all()
+            .unwrap();
+        assert_eq!(reheader, header);
+        assert_eq!(renavaids, navaids);
+    }
+}
@@ -0,0 +1,286 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Which navaids a receiver in the air can pick up, and what it indicates for them.
+//!
+//! A navaid is receivable within its nominal range: the range coded in the class of
+//! an NDB or VOR, or the range given for a localizer, glideslope, or DME. VHF and UHF
+//! navaids are further limited to radio line of sight, over a smooth earth with
+//! standard refraction. NDBs propagate by ground wave, so only their range applies.
+
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    geo::{wmm::true_to_magnetic, GeoPoint, METRES_PER_NM},
+    navdata::{
+        nav::{Navaid, TypeSpecificData},
+        NavEntry, NavEntryKind, NavGraph,
+    },
+};
+
+/// The kinds of entries that can be received.
+const RECEIVABLE_KINDS: [NavEntryKind; 5] = [
+    NavEntryKind::Ndb,
+    NavEntryKind::Vor,
+    NavEntryKind::Localizer,
+    NavEntryKind::Glideslope,
+    NavEntryKind::Dme,
+];
+
+/// The height of navaid antennas above the elevation of the navaid, in feet.
+const ANTENNA_HEIGHT_FT: f64 = 15f64;
+
+/// The distance to the radio horizon is this many nautical miles, times the square
+/// root of the height in feet. This includes the usual 4/3 earth radius refraction.
+const RADIO_HORIZON_FACTOR: f64 = 1.23;
+
+const NM_PER_FT: f64 = 0.3048 / METRES_PER_NM;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A receiver in an aircraft.
+pub struct Receiver {
+    pub position: GeoPoint,
+    /// The altitude, in feet above mean sea level.
+    pub alt_ft: f64,
+    /// The true heading of the aircraft, for the relative bearings of NDBs.
+    pub heading_true: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What a receiver indicates for a navaid.
+pub enum Indication {
+    /// The radial of a VOR the receiver is on, referenced to the variation the VOR is
+    /// slaved to.
+    Vor { radial: f64 },
+    /// The distance a DME shows: the slant range, less the bias of the DME. The bias
+    /// makes the DME of an ILS or localizer read zero at the runway threshold.
+    Dme { distance_nm: f64 },
+    /// The bearing to an NDB, relative to the nose of the aircraft, as shown on an
+    /// ADF.
+    Ndb { relative_bearing: f64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A navaid in reception.
+pub struct Reception<'a> {
+    pub idx: NodeIndex,
+    pub navaid: &'a Navaid,
+    /// The great-circle distance to the navaid, in nautical miles.
+    pub ground_range_nm: f64,
+    /// The straight-line distance to the navaid, in nautical miles.
+    pub slant_range_nm: f64,
+    /// [`None`] for localizers and glideslopes.
+    pub indication: Option<Indication>,
+}
+
+impl Receiver {
+    #[must_use]
+    /// The farthest a VHF or UHF navaid can be received from, in nautical miles, over
+    /// a smooth earth.
+    pub fn line_of_sight_nm(&self, navaid: &Navaid) -> f64 {
+        let height_ft = (self.alt_ft - f64::from(navaid.elevation)).max(0f64);
+        RADIO_HORIZON_FACTOR * (height_ft.sqrt() + ANTENNA_HEIGHT_FT.sqrt())
+    }
+
+    #[must_use]
+    /// The farthest `navaid` can be received from, in nautical miles, or [`None`] if
+    /// it isn't a kind of navaid that is received.
+    pub fn range_nm(&self, navaid: &Navaid) -> Option<f64> {
+        let (nominal_nm, is_line_of_sight) = match &navaid.type_data {
+            TypeSpecificData::Ndb { class, .. } => (class.range_nm().into(), false),
+            TypeSpecificData::Vor { class, .. } => (class.range_nm().into(), true),
+            TypeSpecificData::Localizer { max_range, .. }
+            | TypeSpecificData::Glideslope { max_range, .. } => {
+                (f64::from(*max_range), true)
+            },
+            TypeSpecificData::Dme { service_volume, .. } => {
+                (f64::from(*service_volume), true)
+            },
+            _ => return None,
+        };
+        Some(if is_line_of_sight {
+            nominal_nm.min(self.line_of_sight_nm(navaid))
+        } else {
+            nominal_nm
+        })
+    }
+
+    #[must_use]
+    /// The reception of `navaid`, the node `idx`, or [`None`] if it can't be
+    /// received.
+    pub fn receive<'a>(
+        &self,
+        idx: NodeIndex,
+        navaid: &'a Navaid,
+    ) -> Option<Reception<'a>> {
+        let station = GeoPoint::new(navaid.lat, navaid.lon);
+        let ground_range_nm = self.position.great_circle_distance_nm(station);
+        if ground_range_nm > self.range_nm(navaid)? {
+            return None;
+        }
+        let height_nm = (self.alt_ft - f64::from(navaid.elevation)) * NM_PER_FT;
+        let slant_range_nm = ground_range_nm.hypot(height_nm);
+        let indication = match &navaid.type_data {
+            TypeSpecificData::Vor {
+                slaved_variation, ..
+            } => Some(Indication::Vor {
+                radial: true_to_magnetic(
+                    station.great_circle_bearing(self.position),
+                    f64::from(*slaved_variation),
+                ),
+            }),
+            TypeSpecificData::Dme { bias, .. } => Some(Indication::Dme {
+                distance_nm: (slant_range_nm - f64::from(*bias)).max(0f64),
+            }),
+            TypeSpecificData::Ndb { .. } => Some(Indication::Ndb {
+                relative_bearing: (self.position.great_circle_bearing(station)
+                    - self.heading_true)
+                    .rem_euclid(360f64),
+            }),
+            _ => None,
+        };
+        Some(Reception {
+            idx,
+            navaid,
+            ground_range_nm,
+            slant_range_nm,
+            indication,
+        })
+    }
+}
+
+impl NavGraph {
+    #[must_use]
+    /// Every navaid `receiver` can receive, nearest first.
+    pub fn receivable(&self, receiver: &Receiver) -> Vec<Reception<'_>> {
+        // Ranges are coded in a `u8` for NDBs, and no VHF or UHF navaid can be
+        // received past the line of sight to one at sea level.
+        let search_radius_nm = f64::from(u8::MAX).max(
+            RADIO_HORIZON_FACTOR
+                * (receiver.alt_ft.max(0f64).sqrt() + ANTENNA_HEIGHT_FT.sqrt()),
+        );
+        self.within_radius(
+            receiver.position.lat,
+            receiver.position.lon,
+            search_radius_nm,
+            Some(&RECEIVABLE_KINDS),
+        )
+        .into_iter()
+        .filter_map(|(idx, entry, _)| match entry {
+            NavEntry::Navaid(navaid) => receiver.receive(idx, navaid),
+            NavEntry::Fix(_) => None,
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        geo::GeoPoint,
+        navdata::{
+            builder::NavGraphBuilder,
+            nav::{Navaid, NdbClass, TypeSpecificData, VorClass},
+            reception::{Indication, Receiver},
+        },
+        radio::{LfFrequency, VhfFrequency},
+    };
+
+    fn navaid(
+        ident: &str,
+        lon: f64,
+        elevation: i32,
+        type_data: TypeSpecificData,
+    ) -> Navaid {
+        Navaid {
+            lat: 0.0,
+            lon,
+            elevation,
+            icao_region: "ZZ".try_into().unwrap(),
+            ident: ident.try_into().unwrap(),
+            type_data,
+        }
+    }
+
+    #[test]
+    fn receive_in_range() {
+        let vor = TypeSpecificData::Vor {
+            freq: VhfFrequency::from_khz(113_900),
+            class: VorClass::LowAlt,
+            slaved_variation: 10.0,
+            name: "VOR".to_owned(),
+        };
+        let ndb = |class| TypeSpecificData::Ndb {
+            freq: LfFrequency::from_khz(350),
+            class,
+            flags: 0.0,
+            terminal_region: "ENRT".try_into().unwrap(),
+            name: "NDB".to_owned(),
+        };
+        let dme = |bias| TypeSpecificData::Dme {
+            display_freq: true,
+            paired_freq: VhfFrequency::from_khz(113_900),
+            service_volume: 130,
+            bias,
+            terminal_region: "ENRT".try_into().unwrap(),
+            name: "DME".to_owned(),
+        };
+        let nav_graph = NavGraphBuilder::new()
+            .navaid(navaid("VVV", 0.0, 0, vor))
+            .navaid(navaid("DDD", 0.0, 0, dme(0.0)))
+            .navaid(navaid("IDD", -0.1, 0, dme(1.5)))
+            .navaid(navaid("NNN", 0.6, 0, ndb(NdbClass::Locator)))
+            .navaid(navaid("FAR", 2.0, 0, ndb(NdbClass::Locator)))
+            .build()
+            .unwrap();
+
+        // About 30 NM east of the VOR, heading north.
+        let mut receiver = Receiver {
+            position: GeoPoint::new(0.0, 0.5),
+            alt_ft: 10_000.0,
+            heading_true: 0.0,
+        };
+        let in_range = nav_graph.receivable(&receiver);
+        let idents: Vec<_> =
+            in_range.iter().map(|r| r.navaid.ident.as_str()).collect();
+        assert_eq!(idents, ["NNN", "VVV", "DDD", "IDD"]);
+        assert!(matches!(
+            in_range[0].indication,
+            Some(Indication::Ndb { relative_bearing }) if (relative_bearing - 90.0).abs() < 0.1
+        ));
+        assert!(matches!(
+            in_range[1].indication,
+            Some(Indication::Vor { radial }) if (radial - 80.0).abs() < 0.1
+        ));
+        let dme = &in_range[2];
+        assert!(dme.slant_range_nm > dme.ground_range_nm);
+        assert!(matches!(
+            dme.indication,
+            Some(Indication::Dme { distance_nm })
+                if (distance_nm - dme.slant_range_nm).abs() < 1e-9
+        ));
+        let biased = &in_range[3];
+        assert!(matches!(
+            biased.indication,
+            Some(Indication::Dme { distance_nm })
+                if (distance_nm - (biased.slant_range_nm - 1.5)).abs() < 1e-9
+        ));
+
+        // Down low, the VOR and DMEs are below the horizon, but the NDB is not.
+        receiver.alt_ft = 100.0;
+        let in_range = nav_graph.receivable(&receiver);
+        assert_eq!(in_range.len(), 1);
+        assert_eq!(in_range[0].navaid.ident, "NNN");
+
+        // Nearer than its bias, a DME reads zero.
+        receiver.position = GeoPoint::new(0.0, -0.1);
+        receiver.alt_ft = 1000.0;
+        let in_range = nav_graph.receivable(&receiver);
+        assert_eq!(in_range[0].navaid.ident, "IDD");
+        assert_eq!(
+            in_range[0].indication,
+            Some(Indication::Dme { distance_nm: 0.0 })
+        );
+    }
+}
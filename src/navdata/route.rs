@@ -0,0 +1,639 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Parsing ICAO-style route strings, like `KSFO SSTIK4 SSTIK J5 LKV J1 OAL KLAX`, and
+//! resolving them against a [`NavGraph`].
+
+use either::Either::Left;
+use heapless::String as HString;
+use petgraph::{
+    algo::astar,
+    graph::NodeIndex,
+    visit::{EdgeFiltered, EdgeRef},
+};
+use snafu::prelude::*;
+
+use crate::{
+    geo::great_circle_distance_nm,
+    navdata::{
+        airways::AwyEdge, cifp::ProcedureKind, AirwayWithoutExitSnafu,
+        BadAirwayExitSnafu, BadLatLonSnafu, EmptyRouteSnafu, NavEdge, NavEntryKind,
+        NavGraph, NoPathSnafu, RouteError, UnknownProcedureSnafu,
+        UnknownWaypointSnafu,
+    },
+};
+
+#[derive(Debug, Clone, Default)]
+/// A route, resolved against a [`NavGraph`].
+pub struct ResolvedRoute {
+    /// The departure airport, if the route starts with one.
+    pub departure: Option<HString<4>>,
+    pub sid: Option<ProcedureUse>,
+    /// The enroute part of the route, from the end of the SID (if any) to the start
+    /// of the STAR (if any).
+    pub legs: Vec<RouteLeg>,
+    pub star: Option<ProcedureUse>,
+    /// The arrival airport, if the route ends with one.
+    pub arrival: Option<HString<4>>,
+}
+
+#[derive(Debug, Clone)]
+/// A SID or STAR flown as part of a route.
+pub struct ProcedureUse {
+    pub ident: HString<6>,
+    /// The enroute transition flown, if one was given or could be inferred from the
+    /// adjacent waypoint.
+    pub transition: Option<HString<5>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteLeg {
+    /// How `to` is reached from the previous point.
+    pub via: Via,
+    pub to: RoutePoint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Via {
+    Direct,
+    Airway(HString<5>),
+    /// The point is reached at the end of the route's SID.
+    Sid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoutePoint {
+    Node(NodeIndex),
+    /// A latitude/longitude waypoint, given in the route as e.g. `46N078W`.
+    LatLon {
+        lat: f64,
+        lon: f64,
+    },
+    /// An airport along the route, other than the departure and arrival. Airports
+    /// are not in the graph, so this is flown to as its position.
+    Airport {
+        ident: HString<4>,
+        lat: f64,
+        lon: f64,
+    },
+}
+
+impl RoutePoint {
+    #[must_use]
+    /// The latitude and longitude of this point.
+    pub fn position(&self, nav_graph: &NavGraph) -> (f64, f64) {
+        match self {
+            RoutePoint::Node(idx) => {
+                let entry = &nav_graph.graph[*idx];
+                (entry.lat(), entry.lon())
+            },
+            RoutePoint::LatLon { lat, lon }
+            | RoutePoint::Airport { lat, lon, .. } => (*lat, *lon),
+        }
+    }
+}
+
+struct Token {
+    /// The index of the token in the route string, counting from 0.
+    index: usize,
+    text: String,
+}
+
+/// Split a route into tokens, dropping speed/level groups and placeholder `SID` and
+/// `STAR` tokens.
+fn tokenize(route: &str) -> Vec<Token> {
+    route
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, text)| {
+            // Drop speed/level changes, like `LKV/N0450F370`.
+            let text = text.split_once('/').map_or(text, |(text, _)| text);
+            Token {
+                index,
+                text: text.to_ascii_uppercase(),
+            }
+        })
+        .filter(|t| {
+            !(t.text.is_empty()
+                || t.text == "SID"
+                || t.text == "STAR"
+                || is_speed_level(&t.text))
+        })
+        .collect()
+}
+
+/// Whether the token is a speed/level group, like `N0450F350` or `M082F370`.
+fn is_speed_level(token: &str) -> bool {
+    let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let Some(rest) = token.strip_prefix(['N', 'K', 'M']) else {
+        return false;
+    };
+    let Some(split) = rest.find(['F', 'A', 'S', 'M']) else {
+        return false;
+    };
+    let (speed, level) = rest.split_at(split);
+    matches!(speed.len(), 3 | 4)
+        && all_digits(speed)
+        && matches!(level.len(), 4 | 5)
+        && all_digits(&level[1..])
+}
+
+/// Parse a latitude/longitude waypoint, like `46N078W` or `4620N07805W`.
+/// Returns [`None`] if the token isn't shaped like one, and `Some(Err(()))` if it is,
+/// but is out of range.
+fn parse_lat_lon(token: &str) -> Option<Result<(f64, f64), ()>> {
+    if !token.is_ascii() {
+        return None;
+    }
+    let (lat, rest) = token.split_at(token.find(['N', 'S'])?);
+    let (ns, rest) = rest.split_at(1);
+    let (lon, ew) = rest.split_at(rest.len().checked_sub(1)?);
+    if !(matches!((lat.len(), lon.len()), (2, 3) | (4, 5))
+        && matches!(ew, "E" | "W")
+        && lat.bytes().chain(lon.bytes()).all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    // Everything is ASCII digits of a known length at this point.
+    let degrees = |s: &str, deg_len: usize| -> Option<f64> {
+        let (deg, min) = s.split_at(deg_len);
+        let deg: u16 = deg.parse().ok()?;
+        let min: u16 = if min.is_empty() { 0 } else { min.parse().ok()? };
+        (min < 60).then(|| f64::from(deg) + f64::from(min) / 60f64)
+    };
+    let lat = degrees(lat, 2).filter(|lat| *lat <= 90f64);
+    let lon = degrees(lon, 3).filter(|lon| *lon <= 180f64);
+    Some(lat.zip(lon).ok_or(()).map(|(lat, lon)| {
+        (
+            if ns == "S" { -lat } else { lat },
+            if ew == "W" { -lon } else { lon },
+        )
+    }))
+}
+
+impl NavGraph {
+    /// Parse an ICAO-style route string, and resolve it against this graph.
+    ///
+    /// The route may start with a departure airport, optionally followed by a SID, and
+    /// may end with an arrival airport, optionally preceded by a STAR. Procedures may
+    /// be given with a transition, as `SSTIK4.SSTIK` or `OAL.ANJLL4`. Between them may
+    /// be waypoints, `DCT`, airways, and latitude/longitude waypoints like `46N078W`
+    /// or `4620N07805W`. Speed/level groups are ignored.
+    ///
+    /// A token is taken as an airport if airport or CIFP data was loaded for it. If no
+    /// airports have been added, a four character ident that matches no waypoint is
+    /// also taken as one. Waypoint idents matching several
+    /// entries are resolved to the one nearest to the previous point; for the first
+    /// point, to the one nearest to the next point that is unambiguous.
+    ///
+    /// # Errors
+    /// An error naming the offending token will be returned if the route cannot be
+    /// resolved.
+    pub fn resolve_route(&self, route: &str) -> Result<ResolvedRoute, RouteError> {
+        let mut tokens = tokenize(route);
+        ensure!(!tokens.is_empty(), EmptyRouteSnafu);
+
+        let departure = tokens.first().and_then(|t| self.route_airport(t));
+        if departure.is_some() {
+            tokens.remove(0);
+        }
+        let arrival = tokens.last().and_then(|t| self.route_airport(t));
+        if arrival.is_some() {
+            tokens.pop();
+        }
+
+        let mut sid = match (&departure, tokens.first()) {
+            (Some(airport), Some(token)) => {
+                self.route_procedure(airport, ProcedureKind::Sid, token)?
+            },
+            _ => None,
+        };
+        if sid.is_some() {
+            tokens.remove(0);
+        }
+        let mut star = match (&arrival, tokens.last()) {
+            (Some(airport), Some(token)) => {
+                self.route_procedure(airport, ProcedureKind::Star, token)?
+            },
+            _ => None,
+        };
+        if star.is_some() {
+            tokens.pop();
+        }
+
+        let legs = self.resolve_enroute(&tokens, sid.is_some())?;
+
+        // Infer the transitions from the waypoints next to the procedures.
+        let node_ident = |leg: Option<&RouteLeg>| match leg?.to {
+            RoutePoint::Node(idx) => Some(self.graph[idx].ident()),
+            RoutePoint::LatLon { .. } | RoutePoint::Airport { .. } => None,
+        };
+        for (airport, kind, proc_use, ident) in [
+            (
+                &departure,
+                ProcedureKind::Sid,
+                &mut sid,
+                node_ident(legs.first()),
+            ),
+            (
+                &arrival,
+                ProcedureKind::Star,
+                &mut star,
+                node_ident(legs.last()),
+            ),
+        ] {
+            if let (Some(airport), Some(proc_use), Some(ident)) =
+                (airport, proc_use, ident)
+            {
+                if proc_use.transition.is_none() {
+                    proc_use.transition = self
+                        .find_procedure(airport, kind, &proc_use.ident)
+                        .and_then(|p| p.enroute_transition(ident))
+                        .map(|t| t.ident.clone());
+                }
+            }
+        }
+
+        Ok(ResolvedRoute {
+            departure,
+            sid,
+            legs,
+            star,
+            arrival,
+        })
+    }
+
+    fn resolve_enroute(
+        &self,
+        tokens: &[Token],
+        has_sid: bool,
+    ) -> Result<Vec<RouteLeg>, RouteError> {
+        let mut legs: Vec<RouteLeg> = Vec::new();
+        let mut pending_airway: Option<(&Token, HString<5>)> = None;
+        for (i, token) in tokens.iter().enumerate() {
+            let via = if legs.is_empty() && has_sid {
+                Via::Sid
+            } else {
+                Via::Direct
+            };
+            let prev = legs.last().map(|leg| &leg.to);
+
+            if let Some((airway, name)) = pending_airway.take() {
+                let Some(&RoutePoint::Node(entry)) = prev else {
+                    unreachable!("airways are only entered from nodes")
+                };
+                ensure!(
+                    token.text != "DCT" && parse_lat_lon(&token.text).is_none(),
+                    AirwayWithoutExitSnafu {
+                        token: &airway.text,
+                        index: airway.index,
+                    }
+                );
+                legs.extend(self.route_airway_legs(entry, &name, token)?);
+                continue;
+            }
+
+            if token.text == "DCT" {
+                continue;
+            }
+
+            if let Some(lat_lon) = parse_lat_lon(&token.text) {
+                let (lat, lon) = lat_lon.ok().context(BadLatLonSnafu {
+                    token: &token.text,
+                    index: token.index,
+                })?;
+                legs.push(RouteLeg {
+                    via,
+                    to: RoutePoint::LatLon { lat, lon },
+                });
+                continue;
+            }
+
+            if let Some(&RoutePoint::Node(idx)) = prev {
+                let airway =
+                    self.graph.edges(idx).find_map(|er| match er.weight() {
+                        NavEdge::Airway(AwyEdge { name, .. })
+                            if name == token.text.as_str() =>
+                        {
+                            Some(name.clone())
+                        },
+                        _ => None,
+                    });
+                if let Some(airway) = airway {
+                    pending_airway = Some((token, airway));
+                    continue;
+                }
+            }
+
+            let reference = match prev {
+                Some(point) => Some(point.position(self)),
+                None => self.route_anchor(&tokens[i + 1..]),
+            };
+            let idx = self
+                .nearest_of(self.route_waypoint_candidates(&token.text), reference)
+                .context(UnknownWaypointSnafu {
+                    token: &token.text,
+                    index: token.index,
+                })?;
+            legs.push(RouteLeg {
+                via,
+                to: RoutePoint::Node(idx),
+            });
+        }
+
+        if let Some((airway, _)) = pending_airway {
+            return AirwayWithoutExitSnafu {
+                token: &airway.text,
+                index: airway.index,
+            }
+            .fail();
+        }
+        Ok(legs)
+    }
+
+    /// The legs from `entry` along the airway `name`, to the waypoint `exit`.
+    fn route_airway_legs(
+        &self,
+        entry: NodeIndex,
+        name: &HString<5>,
+        exit: &Token,
+    ) -> Result<Vec<RouteLeg>, RouteError> {
+        let not_on_airway = BadAirwayExitSnafu {
+            token: &exit.text,
+            index: exit.index,
+            airway: name.as_str(),
+        };
+        let exits = self
+            .airway_find(entry, name, &exit.text)
+            .context(not_on_airway)?;
+        let entry_pos = RoutePoint::Node(entry).position(self);
+        let exit_idx = self
+            .nearest_of(
+                exits.into_iter().map(|(idx, _)| idx).collect(),
+                Some(entry_pos),
+            )
+            .context(NoPathSnafu { idx: Left(entry) })
+            .context(not_on_airway)?;
+
+        let ef = EdgeFiltered::from_fn(
+            &self.graph,
+            |er| matches!(er.weight(), NavEdge::Airway(AwyEdge { name: awy, .. }) if awy == name),
+        );
+        let (_, path) = astar(
+            &ef,
+            entry,
+            |idx| idx == exit_idx,
+            |er| {
+                let (a, b) = (&self.graph[er.source()], &self.graph[er.target()]);
+                great_circle_distance_nm(a.lat(), a.lon(), b.lat(), b.lon())
+            },
+            |_| 0f64,
+        )
+        .context(NoPathSnafu {
+            idx: Left(exit_idx),
+        })
+        .context(not_on_airway)?;
+
+        Ok(path
+            .into_iter()
+            .skip(1)
+            .map(|idx| RouteLeg {
+                via: Via::Airway(name.clone()),
+                to: RoutePoint::Node(idx),
+            })
+            .collect())
+    }
+
+    /// The airport `token` refers to, if it is one.
+    fn route_airport(&self, token: &Token) -> Option<HString<4>> {
+        // Without airport data, an ident can only be guessed to be an airport.
+        let is_airport = self.procedures(&token.text).is_some()
+            || self.airport(&token.text).is_some()
+            || (self.airports.is_empty()
+                && token.text.len() == 4
+                && token.text.bytes().all(|b| b.is_ascii_alphanumeric())
+                && self.route_waypoint_candidates(&token.text).is_empty());
+        is_airport
+            .then(|| HString::try_from(token.text.as_str()).ok())
+            .flatten()
+    }
+
+    /// The procedure of `kind` at `airport` that `token` refers to, if it is one.
+    fn route_procedure(
+        &self,
+        airport: &str,
+        kind: ProcedureKind,
+        token: &Token,
+    ) -> Result<Option<ProcedureUse>, RouteError> {
+        let Some((first, second)) = token.text.split_once('.') else {
+            return Ok(self.find_procedure(airport, kind, &token.text).map(|p| {
+                ProcedureUse {
+                    ident: p.ident.clone(),
+                    transition: None,
+                }
+            }));
+        };
+        [(first, second), (second, first)]
+            .into_iter()
+            .find_map(|(ident, transition)| {
+                let procedure = self.find_procedure(airport, kind, ident)?;
+                Some(ProcedureUse {
+                    ident: procedure.ident.clone(),
+                    transition: Some(
+                        procedure.enroute_transition(transition)?.ident.clone(),
+                    ),
+                })
+            })
+            .map(Some)
+            .context(UnknownProcedureSnafu {
+                token: &token.text,
+                index: token.index,
+                kind,
+                airport,
+            })
+    }
+
+    /// Nodes that a route waypoint `ident` could refer to. DMEs are only considered
+    /// if there is no VOR with the same ident, as they are usually co-located.
+    fn route_waypoint_candidates(&self, ident: &str) -> Vec<NodeIndex> {
+        let found = self.find_nav_entry(ident);
+        let has_vor = found
+            .iter()
+            .any(|(_, entry)| entry.kind() == NavEntryKind::Vor);
+        found
+            .into_iter()
+            .filter(|(_, entry)| match entry.kind() {
+                NavEntryKind::Fix | NavEntryKind::Ndb | NavEntryKind::Vor => true,
+                NavEntryKind::Dme => !has_vor,
+                _ => false,
+            })
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// The position of the first point in `tokens` that can be resolved without
+    /// ambiguity.
+    fn route_anchor(&self, tokens: &[Token]) -> Option<(f64, f64)> {
+        tokens.iter().find_map(|token| {
+            if let Some(lat_lon) = parse_lat_lon(&token.text) {
+                return lat_lon.ok();
+            }
+            match self.route_waypoint_candidates(&token.text).as_slice() {
+                [idx] => Some(RoutePoint::Node(*idx).position(self)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Of `candidates`, the one nearest to `reference`. Without a reference, the first.
+    pub(super) fn nearest_of(
+        &self,
+        candidates: Vec<NodeIndex>,
+        reference: Option<(f64, f64)>,
+    ) -> Option<NodeIndex> {
+        let Some((lat, lon)) = reference else {
+            return candidates.into_iter().next();
+        };
+        candidates.into_iter().min_by(|a, b| {
+            let dist = |idx: &NodeIndex| {
+                let entry = &self.graph[*idx];
+                great_circle_distance_nm(lat, lon, entry.lat(), entry.lon())
+            };
+            dist(a).total_cmp(&dist(b))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Cursor};
+
+    use petgraph::graph::DiGraph;
+
+    use crate::navdata::{
+        apt,
+        cifp::{Procedure, ProcedureKind, Transition},
+        route::{parse_lat_lon, RoutePoint, Via},
+        tests::{awy, fix, nav_graph},
+        NavEdge, NavEntry, RouteError,
+    };
+
+    #[test]
+    fn lat_lon_waypoints() {
+        assert_eq!(parse_lat_lon("46N078W"), Some(Ok((46.0, -78.0))));
+        assert_eq!(parse_lat_lon("4630S17015E"), Some(Ok((-46.5, 170.25))));
+        assert_eq!(parse_lat_lon("95N078W"), Some(Err(())));
+        assert_eq!(parse_lat_lon("4675N07800W"), Some(Err(())));
+        assert_eq!(parse_lat_lon("SSTIK"), None);
+        assert_eq!(parse_lat_lon("46N78W"), None);
+    }
+
+    #[test]
+    fn resolve_routes() {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::new();
+        let sstik = graph.add_node(fix("SSTIK", 38.0, -123.0));
+        let mid = graph.add_node(fix("MIDDL", 38.0, -122.0));
+        let lkv = graph.add_node(fix("LKV", 38.0, -121.0));
+        // Two fixes with the same ident; the one near the route should be chosen.
+        let oal = graph.add_node(fix("OAL", 37.0, -120.0));
+        graph.add_node(fix("OAL", -37.0, 120.0));
+        for (a, b) in [(sstik, mid), (mid, lkv)] {
+            graph.add_edge(a, b, awy("J5", 180, 450, true));
+            graph.add_edge(b, a, awy("J5", 180, 450, true));
+        }
+        let procedures = HashMap::from([(
+            "KSFO".try_into().unwrap(),
+            vec![Procedure {
+                kind: ProcedureKind::Sid,
+                ident: "SSTIK4".try_into().unwrap(),
+                trans_alt_ft_msl: None,
+                runway_transitions: Vec::new(),
+                enroute_transitions: vec![Transition {
+                    ident: "SSTIK".try_into().unwrap(),
+                    legs: Vec::new(),
+                }],
+                legs: Vec::new(),
+            }],
+        )]);
+        let nav_graph = nav_graph(graph, procedures);
+
+        let route = nav_graph
+            .resolve_route(
+                "KSFO SSTIK4 SSTIK J5 LKV/N0450F350 DCT 3730N12030W OAL KLAX",
+            )
+            .unwrap();
+        assert_eq!(route.departure.as_deref(), Some("KSFO"));
+        assert_eq!(route.arrival.as_deref(), Some("KLAX"));
+        let sid = route.sid.unwrap();
+        assert_eq!(sid.ident, "SSTIK4");
+        assert_eq!(sid.transition.as_deref(), Some("SSTIK"));
+        assert!(route.star.is_none());
+        let legs: Vec<_> = route
+            .legs
+            .iter()
+            .map(|leg| (&leg.via, leg.to.clone()))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                (&Via::Sid, RoutePoint::Node(sstik)),
+                (
+                    &Via::Airway("J5".try_into().unwrap()),
+                    RoutePoint::Node(mid)
+                ),
+                (
+                    &Via::Airway("J5".try_into().unwrap()),
+                    RoutePoint::Node(lkv)
+                ),
+                (
+                    &Via::Direct,
+                    RoutePoint::LatLon {
+                        lat: 37.5,
+                        lon: -120.5
+                    }
+                ),
+                (&Via::Direct, RoutePoint::Node(oal)),
+            ]
+        );
+
+        assert!(matches!(
+            nav_graph.resolve_route("KSFO SSTIK J5 NOPE KLAX"),
+            Err(RouteError::BadAirwayExit { token, index: 3, .. }) if token == "NOPE"
+        ));
+        assert!(matches!(
+            nav_graph.resolve_route("SSTIK J5"),
+            Err(RouteError::AirwayWithoutExit { token, index: 1, .. }) if token == "J5"
+        ));
+        assert!(matches!(
+            nav_graph.resolve_route("KSFO SSTIK4.NOPE SSTIK"),
+            Err(RouteError::UnknownProcedure { token, .. }) if token == "SSTIK4.NOPE"
+        ));
+        assert!(matches!(
+            nav_graph.resolve_route("SSTIK ZZZZZ LKV"),
+            Err(RouteError::UnknownWaypoint { token, index: 1, .. }) if token == "ZZZZZ"
+        ));
+    }
+
+    #[test]
+    fn resolve_airports_when_loaded() {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::new();
+        let sstik = graph.add_node(fix("SSTIK", 38.0, -123.0));
+        let mut nav_graph = nav_graph(graph, HashMap::new());
+        assert!(nav_graph.resolve_route("KOAK SSTIK KXYZ").is_ok());
+
+        nav_graph.add_airports(
+            apt::parse_file_buffered(Cursor::new(
+                "I\n1300 Test\n\n1 9 0 0 KOAK Oakland\n99\n",
+            ))
+            .unwrap(),
+        );
+        let route = nav_graph.resolve_route("KOAK SSTIK").unwrap();
+        assert_eq!(route.departure.as_deref(), Some("KOAK"));
+        assert_eq!(route.legs[0].to, RoutePoint::Node(sstik));
+        assert!(matches!(
+            nav_graph.resolve_route("KOAK SSTIK KXYZ"),
+            Err(RouteError::UnknownWaypoint { token, index: 2, .. }) if token == "KXYZ"
+        ));
+    }
+}
@@ -0,0 +1,353 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Sources that navdata files are read from.
+//!
+//! A [`NavSource`] is a tree of files, addressed by paths relative to its root, with
+//! components separated by `/`, like `earth_fix.dat` or `CIFP/KSFO.dat`. A source can
+//! be a folder on disk ([`DirSource`]), or files held in memory ([`MemorySource`]),
+//! which can be unpacked from a 7z archive with the `navdata_7z` feature, or from a
+//! zip archive with the `navdata_zip` feature.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fmt::Debug,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader, Error as IoError, ErrorKind},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// A tree of navdata files.
+pub trait NavSource: Debug {
+    /// Open the file at `path`. Returns [`None`] if there is no such file.
+    /// # Errors
+    /// Returns an [`Err`] if the file is there, but cannot be opened.
+    fn open(&self, path: &str) -> Result<Option<Box<dyn BufRead + '_>>, IoError>;
+
+    /// The names of the files directly in the folder at `path`. Returns [`None`] if
+    /// there is no such folder.
+    /// # Errors
+    /// Returns an [`Err`] if the folder is there, but cannot be read.
+    fn list(&self, path: &str) -> Result<Option<Vec<String>>, IoError>;
+
+    /// A path that identifies the file at `path` to a user, in diagnostics and
+    /// [`Provenance`](super::layers::Provenance)s. It need not exist on disk.
+    fn file_path(&self, path: &str) -> PathBuf;
+
+    /// A fingerprint of the file at `path`, which changes whenever the file does, so
+    /// that caches of the data can tell they are stale. Returns [`None`] if there is no
+    /// such file. By default, this is a hash of the contents of the file.
+    /// # Errors
+    /// Returns an [`Err`] if the file is there, but cannot be read.
+    fn fingerprint(&self, path: &str) -> Result<Option<u64>, IoError> {
+        let Some(mut reader) = self.open(path)? else {
+            return Ok(None);
+        };
+        let mut hasher = DefaultHasher::new();
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.write(buf);
+            let len = buf.len();
+            reader.consume(len);
+        }
+        Ok(Some(hasher.finish()))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A folder on disk.
+pub struct DirSource {
+    root: PathBuf,
+}
+
+impl DirSource {
+    #[must_use]
+    /// A source reading from the folder `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    #[must_use]
+    /// The folder this source reads from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl NavSource for DirSource {
+    fn open(&self, path: &str) -> Result<Option<Box<dyn BufRead + '_>>, IoError> {
+        match File::open(self.file_path(path)) {
+            Ok(file) => Ok(Some(Box::new(BufReader::new(file)))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list(&self, path: &str) -> Result<Option<Vec<String>>, IoError> {
+        let folder = self.file_path(path);
+        if !folder.is_dir() {
+            return Ok(None);
+        }
+        let mut names = Vec::new();
+        for dir_entry in folder.read_dir()? {
+            let dir_entry = dir_entry?;
+            if dir_entry.file_type()?.is_file() {
+                names.push(dir_entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(Some(names))
+    }
+
+    fn file_path(&self, path: &str) -> PathBuf {
+        path.split('/')
+            .fold(self.root.clone(), |full_path, component| {
+                full_path.join(component)
+            })
+    }
+
+    /// Hashes the size and modification time of the file, rather than reading it.
+    fn fingerprint(&self, path: &str) -> Result<Option<u64>, IoError> {
+        let metadata = match fs::metadata(self.file_path(path)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut hasher = DefaultHasher::new();
+        (metadata.len(), modified).hash(&mut hasher);
+        Ok(Some(hasher.finish()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Files held in memory, like test fixtures built into a binary, or the contents of
+/// an archive.
+pub struct MemorySource {
+    name: PathBuf,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    #[must_use]
+    /// An empty source. `name` is shown in place of a folder in the paths of its
+    /// files.
+    pub fn new(name: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Add the file at `path`, replacing any already there.
+    pub fn insert(&mut self, path: impl Into<String>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), contents.into());
+    }
+
+    #[must_use]
+    /// Add the file at `path`, like [`insert`](MemorySource::insert).
+    pub fn with_file(
+        mut self,
+        path: impl Into<String>,
+        contents: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.insert(path, contents);
+        self
+    }
+}
+
+impl NavSource for MemorySource {
+    fn open(&self, path: &str) -> Result<Option<Box<dyn BufRead + '_>>, IoError> {
+        Ok(self
+            .files
+            .get(path)
+            .map(|contents| Box::new(contents.as_slice()) as Box<dyn BufRead>))
+    }
+
+    fn list(&self, path: &str) -> Result<Option<Vec<String>>, IoError> {
+        let prefix = format!("{path}/");
+        let mut found = false;
+        let names = self
+            .files
+            .keys()
+            .filter_map(|file| file.strip_prefix(&prefix))
+            .inspect(|_| found = true)
+            .filter(|name| !name.contains('/'))
+            .map(str::to_owned)
+            .collect();
+        Ok(found.then_some(names))
+    }
+
+    fn file_path(&self, path: &str) -> PathBuf {
+        self.name.join(path)
+    }
+}
+
+#[cfg(feature = "navdata_7z")]
+impl MemorySource {
+    /// Unpack the 7z archive at `path` into memory. Paths in the source are relative
+    /// to the root of the archive.
+    /// # Errors
+    /// Returns an [`Err`] if the archive cannot be read.
+    pub fn from_7z(path: &Path) -> Result<Self, IoError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::from_7z_reader(file, len, path)
+    }
+
+    /// Unpack a 7z archive of `len` bytes from `reader` into memory, like
+    /// [`from_7z`](MemorySource::from_7z). `name` is used like in
+    /// [`new`](MemorySource::new).
+    /// # Errors
+    /// Returns an [`Err`] if the archive cannot be read.
+    pub fn from_7z_reader<R: std::io::Read + std::io::Seek>(
+        reader: R,
+        len: u64,
+        name: impl Into<PathBuf>,
+    ) -> Result<Self, IoError> {
+        let to_io = |e: sevenz_rust::Error| IoError::new(ErrorKind::InvalidData, e);
+        let mut source = Self::new(name);
+        sevenz_rust::SevenZReader::new(reader, len, sevenz_rust::Password::empty())
+            .map_err(to_io)?
+            .for_each_entries(|entry, contents| {
+                if !entry.is_directory() {
+                    let mut buf = Vec::new();
+                    contents.read_to_end(&mut buf)?;
+                    // Archives made on Windows may use either separator.
+                    source.insert(entry.name().replace('\\', "/"), buf);
+                }
+                Ok(true)
+            })
+            .map_err(to_io)?;
+        Ok(source)
+    }
+}
+
+#[cfg(feature = "navdata_zip")]
+impl MemorySource {
+    /// Unpack the zip archive at `path` into memory. Paths in the source are relative
+    /// to the root of the archive.
+    /// # Errors
+    /// Returns an [`Err`] if the archive cannot be read.
+    pub fn from_zip(path: &Path) -> Result<Self, IoError> {
+        Self::from_zip_reader(BufReader::new(File::open(path)?), path)
+    }
+
+    /// Unpack a zip archive from `reader` into memory, like
+    /// [`from_zip`](MemorySource::from_zip). `name` is used like in
+    /// [`new`](MemorySource::new).
+    /// # Errors
+    /// Returns an [`Err`] if the archive cannot be read.
+    pub fn from_zip_reader<R: std::io::Read + std::io::Seek>(
+        reader: R,
+        name: impl Into<PathBuf>,
+    ) -> Result<Self, IoError> {
+        use std::io::Read;
+
+        let to_io =
+            |e: zip::result::ZipError| IoError::new(ErrorKind::InvalidData, e);
+        let mut archive = zip::ZipArchive::new(reader).map_err(to_io)?;
+        let mut source = Self::new(name);
+        for idx in 0..archive.len() {
+            let mut entry = archive.by_index(idx).map_err(to_io)?;
+            if !entry.is_dir() {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf)?;
+                // Archives made on Windows may use either separator.
+                source.insert(entry.name().replace('\\', "/"), buf);
+            }
+        }
+        Ok(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::navdata::{
+        layers::{DataFolders, Layer},
+        source::{MemorySource, NavSource},
+        tests::test_data_files,
+        NavGraph,
+    };
+
+    #[test]
+    fn build_from_memory() {
+        let source = test_data_files()
+            .into_iter()
+            .fold(MemorySource::new("fixtures"), |source, (name, contents)| {
+                source.with_file(name, contents)
+            })
+            .with_file("CIFP/KSFO.dat", "")
+            .with_file("CIFP/old/KSFO.dat", "");
+        assert_eq!(source.list("CIFP").unwrap().unwrap(), ["KSFO.dat"]);
+        assert!(source.list("earth_fix.dat").unwrap().is_none());
+
+        let nav_graph =
+            NavGraph::build_data_from_folders(&DataFolders::from_source(source))
+                .unwrap();
+        assert_eq!(nav_graph.fix_header.cycle, 2401);
+        assert!(nav_graph.procedures("KSFO").unwrap().is_empty());
+        let sfo = nav_graph.find_nav_entry("SFO")[0].0;
+        assert!(nav_graph.airway_find(sfo, "J3", "ALPHA").is_ok());
+        let provenance = nav_graph.provenance(sfo).unwrap();
+        assert_eq!(provenance.layer, Layer::CustomData);
+        assert_eq!(
+            provenance.file.unwrap(),
+            std::path::Path::new("fixtures/earth_nav.dat")
+        );
+    }
+
+    #[cfg(feature = "navdata_7z")]
+    #[test]
+    fn build_from_7z() {
+        use std::{fs, io::Cursor};
+
+        use crate::navdata::tests::write_test_data;
+
+        let folder = write_test_data("7z");
+        let mut archive =
+            sevenz_rust::compress(&folder, Cursor::new(Vec::new())).unwrap();
+        archive.set_position(0);
+        let len = archive.get_ref().len() as u64;
+        let source =
+            MemorySource::from_7z_reader(archive, len, "navdata.7z").unwrap();
+        let nav_graph =
+            NavGraph::build_data_from_folders(&DataFolders::from_source(source))
+                .unwrap();
+        assert_eq!(nav_graph.find_nav_entry("ALPHA").len(), 1);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[cfg(feature = "navdata_zip")]
+    #[test]
+    fn build_from_zip() {
+        use std::io::{Cursor, Write};
+
+        use zip::{write::FileOptions, ZipWriter};
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_directory("CIFP", FileOptions::default())
+            .unwrap();
+        for (name, contents) in test_data_files() {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        let archive = writer.finish().unwrap();
+        let source = MemorySource::from_zip_reader(archive, "navdata.zip").unwrap();
+        assert_eq!(source.list("CIFP").unwrap(), None);
+        let nav_graph =
+            NavGraph::build_data_from_folders(&DataFolders::from_source(source))
+                .unwrap();
+        assert_eq!(nav_graph.find_nav_entry("ALPHA").len(), 1);
+    }
+}
@@ -0,0 +1,228 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! A spatial index over the positions of [`NavEntry`]s, bucketed into 1°×1° cells.
+
+use std::collections::HashMap;
+
+use petgraph::graph::{DiGraph, NodeIndex};
+
+use crate::{
+    geo::{great_circle_distance_nm, EARTH_RADIUS_NM},
+    navdata::{NavEdge, NavEntry, NavEntryKind},
+};
+
+#[derive(Debug, Default)]
+pub(super) struct SpatialIndex {
+    cells: HashMap<(i16, i16), Vec<NodeIndex>>,
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn cell_lat(lat: f64) -> i16 {
+    (lat.floor() as i16).clamp(-90, 89)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn cell_lon(lon: f64) -> i16 {
+    ((lon + 180f64).rem_euclid(360f64) - 180f64).floor() as i16
+}
+
+fn matches_kind(entry: &NavEntry, kinds: Option<&[NavEntryKind]>) -> bool {
+    kinds.map_or(true, |kinds| kinds.contains(&entry.kind()))
+}
+
+impl SpatialIndex {
+    pub(super) fn build(graph: &DiGraph<NavEntry, NavEdge>) -> Self {
+        let mut index = Self::default();
+        for idx in graph.node_indices() {
+            index.insert(idx, &graph[idx]);
+        }
+        index
+    }
+
+    pub(super) fn insert(&mut self, idx: NodeIndex, entry: &NavEntry) {
+        self.cells
+            .entry((cell_lat(entry.lat()), cell_lon(entry.lon())))
+            .or_default()
+            .push(idx);
+    }
+
+    pub(super) fn remove(&mut self, idx: NodeIndex, entry: &NavEntry) {
+        if let Some(cell) = self
+            .cells
+            .get_mut(&(cell_lat(entry.lat()), cell_lon(entry.lon())))
+        {
+            cell.retain(|other| *other != idx);
+        }
+    }
+
+    /// Every node in the cells overlapping the given latitude range and longitude
+    /// cell range. `west` may be greater than `east` if the range crosses the
+    /// antimeridian.
+    fn candidates(
+        &self,
+        south: i16,
+        north: i16,
+        west: i16,
+        east: i16,
+    ) -> impl Iterator<Item = NodeIndex> + '_ {
+        let lons: Vec<i16> = if west <= east {
+            (west..=east).collect()
+        } else {
+            (west..180).chain(-180..=east).collect()
+        };
+        (south..=north)
+            .flat_map(move |lat| lons.clone().into_iter().map(move |lon| (lat, lon)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+
+    /// All nodes within `radius_nm` of the position, sorted by distance.
+    pub(super) fn within_radius(
+        &self,
+        graph: &DiGraph<NavEntry, NavEdge>,
+        lat: f64,
+        lon: f64,
+        radius_nm: f64,
+        kinds: Option<&[NavEntryKind]>,
+    ) -> Vec<(NodeIndex, f64)> {
+        let radius_rad = radius_nm / EARTH_RADIUS_NM;
+        let lat_rad = lat.to_radians();
+        let south = (lat_rad - radius_rad).to_degrees();
+        let north = (lat_rad + radius_rad).to_degrees();
+        let (west, east) = if south <= -90f64
+            || north >= 90f64
+            || radius_rad.sin() >= lat_rad.cos()
+        {
+            (-180, 179)
+        } else {
+            let dlon = (radius_rad.sin() / lat_rad.cos()).asin().to_degrees();
+            // Past this, the range could wrap back around into its starting cell.
+            if dlon >= 179f64 {
+                (-180, 179)
+            } else {
+                (cell_lon(lon - dlon), cell_lon(lon + dlon))
+            }
+        };
+        let mut found: Vec<_> = self
+            .candidates(
+                cell_lat(south.max(-90f64)),
+                cell_lat(north.min(90f64)),
+                west,
+                east,
+            )
+            .filter(|idx| matches_kind(&graph[*idx], kinds))
+            .map(|idx| {
+                let entry = &graph[idx];
+                (
+                    idx,
+                    great_circle_distance_nm(lat, lon, entry.lat(), entry.lon()),
+                )
+            })
+            .filter(|(_, dist)| *dist <= radius_nm)
+            .collect();
+        found.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        found
+    }
+
+    /// The `count` nodes nearest to the position, sorted by distance.
+    pub(super) fn nearest(
+        &self,
+        graph: &DiGraph<NavEntry, NavEdge>,
+        lat: f64,
+        lon: f64,
+        count: usize,
+        kinds: Option<&[NavEntryKind]>,
+    ) -> Vec<(NodeIndex, f64)> {
+        if count == 0 {
+            return Vec::new();
+        }
+        // Widen the search until enough nodes are found. Anything within the searched
+        // radius is guaranteed to be nearer than anything outside of it.
+        let half_circumference_nm = std::f64::consts::PI * EARTH_RADIUS_NM;
+        let mut radius_nm = 16f64;
+        loop {
+            let mut found = self.within_radius(graph, lat, lon, radius_nm, kinds);
+            if found.len() >= count || radius_nm >= half_circumference_nm {
+                found.truncate(count);
+                return found;
+            }
+            radius_nm *= 4f64;
+        }
+    }
+
+    /// All nodes inside the bounding box. If `west` is greater than `east`, the box
+    /// crosses the antimeridian.
+    pub(super) fn within_bounds(
+        &self,
+        graph: &DiGraph<NavEntry, NavEdge>,
+        south: f64,
+        west: f64,
+        north: f64,
+        east: f64,
+        kinds: Option<&[NavEntryKind]>,
+    ) -> Vec<NodeIndex> {
+        let crosses_antimeridian = west > east;
+        let span = if crosses_antimeridian {
+            east + 360f64 - west
+        } else {
+            east - west
+        };
+        let (west_cell, east_cell) = if span >= 359f64 {
+            (-180, 179)
+        } else {
+            (cell_lon(west), cell_lon(east))
+        };
+        self.candidates(cell_lat(south), cell_lat(north), west_cell, east_cell)
+            .filter(|idx| {
+                let entry = &graph[*idx];
+                let lon_inside = if crosses_antimeridian {
+                    entry.lon() >= west || entry.lon() <= east
+                } else {
+                    (west..=east).contains(&entry.lon())
+                };
+                lon_inside
+                    && (south..=north).contains(&entry.lat())
+                    && matches_kind(entry, kinds)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+
+    use crate::navdata::{spatial::SpatialIndex, tests::fix, NavEdge, NavEntry};
+
+    #[test]
+    fn spatial_queries() {
+        let mut graph = DiGraph::<NavEntry, NavEdge>::new();
+        let near = graph.add_node(fix("NEAR", 10.1, 179.9));
+        let across = graph.add_node(fix("ACROS", 10.0, -179.9));
+        let far = graph.add_node(fix("FAR", 12.0, 179.0));
+        graph.add_node(fix("OTHER", -40.0, 20.0));
+        let index = SpatialIndex::build(&graph);
+
+        let nearest: Vec<_> = index
+            .nearest(&graph, 10.0, 179.95, 3, None)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(nearest, [near, across, far]);
+
+        let within: Vec<_> = index
+            .within_radius(&graph, 10.0, 179.95, 30.0, None)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(within, [near, across]);
+
+        let mut bounded =
+            index.within_bounds(&graph, 9.0, 179.5, 11.0, -179.5, None);
+        bounded.sort();
+        assert_eq!(bounded, [near, across]);
+    }
+}
@@ -0,0 +1,369 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! Radio frequencies and channels, as tuned on navigation and communication radios.
+//!
+//! Each type keeps its own unit, so that frequencies in kHz and in 10s of kHz can't
+//! be mixed up. They display and parse in the form they are charted in, like `113.90`,
+//! `350`, or `CH 58X`.
+
+pub mod pairing;
+
+use std::{fmt, str::FromStr};
+
+use snafu::{prelude::*, Backtrace};
+
+#[derive(Snafu, Debug)]
+pub enum RadioError {
+    #[snafu(display("`{input}` is not a frequency."))]
+    InvalidFrequency { input: String, backtrace: Backtrace },
+
+    #[snafu(display("`{input}` is outside of the band."))]
+    OutOfBand { input: String, backtrace: Backtrace },
+
+    #[snafu(display("`{input}` is not on a 25 kHz or 8.33 kHz channel."))]
+    OffChannel { input: String, backtrace: Backtrace },
+
+    #[snafu(display("`{input}` is not a channel."))]
+    InvalidChannel { input: String, backtrace: Backtrace },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The spacing of channels in the VHF band.
+pub enum ChannelSpacing {
+    /// 25 kHz, which all VHF navaids, and most of the world's communications, use.
+    /// Navaids are further spaced 50 kHz apart.
+    Khz25,
+    /// 8.33 kHz, as used for communications in much of Europe. Channels are named
+    /// in 5 kHz steps, so `118.005` names the 8.33 kHz channel at 118 MHz, and
+    /// `118.010` the one 8.33 kHz above it.
+    Khz8_33,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// A frequency or channel name in the VHF band, like that of a VOR, localizer, or
+/// ATC facility.
+pub struct VhfFrequency(u32);
+
+impl VhfFrequency {
+    /// The lowest frequency in the band, in kHz.
+    pub const MIN_KHZ: u32 = 108_000;
+    /// The highest frequency in the band, in kHz.
+    pub const MAX_KHZ: u32 = 136_990;
+
+    #[must_use]
+    /// A frequency of `khz` kHz. This is not checked to be on a channel.
+    pub const fn from_khz(khz: u32) -> Self {
+        Self(khz)
+    }
+
+    #[must_use]
+    /// A frequency of `freq_10khz` 10s of kHz, as in `earth_nav.dat`. This is not
+    /// checked to be on a channel.
+    pub const fn from_10khz(freq_10khz: u32) -> Self {
+        Self(freq_10khz * 10)
+    }
+
+    #[must_use]
+    /// The frequency, in kHz.
+    pub const fn khz(self) -> u32 {
+        self.0
+    }
+
+    #[must_use]
+    /// The frequency, in 10s of kHz, rounded down, as in `earth_nav.dat`.
+    pub const fn to_10khz(self) -> u32 {
+        self.0 / 10
+    }
+
+    #[must_use]
+    /// The frequency, in MHz.
+    pub fn mhz(self) -> f64 {
+        f64::from(self.0) / 1000f64
+    }
+
+    #[must_use]
+    /// Whether this is in the band of VHF navaids, from 108.00 to 117.95 MHz.
+    pub const fn is_nav(self) -> bool {
+        self.0 >= Self::MIN_KHZ && self.0 < 118_000
+    }
+
+    #[must_use]
+    /// The narrowest spacing with a channel named by this frequency, or [`None`] if it
+    /// names no channel.
+    pub const fn spacing(self) -> Option<ChannelSpacing> {
+        if self.0 % 25 == 0 {
+            Some(ChannelSpacing::Khz25)
+        } else if self.is_on_channel(ChannelSpacing::Khz8_33) {
+            Some(ChannelSpacing::Khz8_33)
+        } else {
+            None
+        }
+    }
+
+    #[must_use]
+    /// Whether a radio with `spacing` can tune this frequency. Radios with 8.33 kHz
+    /// spacing can also tune 25 kHz channels.
+    pub const fn is_on_channel(self, spacing: ChannelSpacing) -> bool {
+        match spacing {
+            ChannelSpacing::Khz25 => self.0 % 25 == 0,
+            ChannelSpacing::Khz8_33 => self.0 % 5 == 0 && self.0 % 25 != 20,
+        }
+    }
+}
+
+impl fmt::Display for VhfFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mhz, khz) = (self.0 / 1000, self.0 % 1000);
+        if khz % 10 == 0 {
+            write!(f, "{mhz}.{:02}", khz / 10)
+        } else {
+            write!(f, "{mhz}.{khz:03}")
+        }
+    }
+}
+
+impl FromStr for VhfFrequency {
+    type Err = RadioError;
+
+    /// Parse a frequency in MHz, like `113.90` or `118.005`. It must be in the band,
+    /// and on a 25 kHz or 8.33 kHz channel.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        let (mhz, frac) = input.split_once('.').unwrap_or((input, ""));
+        ensure!(
+            !mhz.is_empty()
+                && frac.len() <= 3
+                && mhz.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()),
+            InvalidFrequencySnafu { input }
+        );
+        let mhz: u32 = mhz.parse().ok().context(InvalidFrequencySnafu { input })?;
+        let khz = frac
+            .bytes()
+            .chain(std::iter::repeat(b'0'))
+            .take(3)
+            .fold(0, |khz, digit| khz * 10 + u32::from(digit - b'0'));
+        let freq = Self(
+            mhz.checked_mul(1000)
+                .and_then(|mhz| mhz.checked_add(khz))
+                .context(OutOfBandSnafu { input })?,
+        );
+        ensure!(
+            (Self::MIN_KHZ..=Self::MAX_KHZ).contains(&freq.0),
+            OutOfBandSnafu { input }
+        );
+        ensure!(
+            freq.is_on_channel(ChannelSpacing::Khz8_33),
+            OffChannelSnafu { input }
+        );
+        Ok(freq)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// A frequency in the LF and MF bands, like that of an NDB, in whole kHz.
+pub struct LfFrequency(u16);
+
+impl LfFrequency {
+    /// The lowest frequency NDBs use, in kHz.
+    pub const MIN_KHZ: u16 = 190;
+    /// The highest frequency NDBs use, in kHz.
+    pub const MAX_KHZ: u16 = 1750;
+
+    #[must_use]
+    /// A frequency of `khz` kHz. This is not checked to be in the band.
+    pub const fn from_khz(khz: u16) -> Self {
+        Self(khz)
+    }
+
+    #[must_use]
+    /// The frequency, in kHz.
+    pub const fn khz(self) -> u16 {
+        self.0
+    }
+}
+
+impl fmt::Display for LfFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for LfFrequency {
+    type Err = RadioError;
+
+    /// Parse a frequency in kHz, like `350`. It must be in the band NDBs use.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        ensure!(
+            !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()),
+            InvalidFrequencySnafu { input }
+        );
+        let khz: u16 = input.parse().ok().context(OutOfBandSnafu { input })?;
+        ensure!(
+            (Self::MIN_KHZ..=Self::MAX_KHZ).contains(&khz),
+            OutOfBandSnafu { input }
+        );
+        Ok(Self(khz))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// The band of a DME or TACAN channel.
+pub enum TacanBand {
+    X,
+    Y,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "navdata_cache",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+/// A channel, tuned by number rather than by frequency.
+pub enum Channel {
+    /// A DME or TACAN channel, from 1 to 126 in either band, like `CH 58X`.
+    Tacan { number: u8, band: TacanBand },
+    /// A five-digit approach channel, like those of GLS and SBAS approaches. GBAS
+    /// channels are from 20000 to 39999, and SBAS channels from 40000 to 99999.
+    Approach(u32),
+}
+
+impl Channel {
+    /// The highest DME or TACAN channel number.
+    pub const MAX_TACAN: u8 = 126;
+
+    #[must_use]
+    /// The DME or TACAN channel `number` in `band`, or [`None`] if there is no such
+    /// channel.
+    pub const fn tacan(number: u8, band: TacanBand) -> Option<Self> {
+        if number >= 1 && number <= Self::MAX_TACAN {
+            Some(Self::Tacan { number, band })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tacan { number, band } => write!(f, "CH {number}{band:?}"),
+            Self::Approach(number) => write!(f, "{number}"),
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = RadioError;
+
+    /// Parse a DME or TACAN channel, like `CH 58X` or `58X`, or a five-digit approach
+    /// channel, like `21234`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        let upper = input.to_ascii_uppercase();
+        let number = upper.strip_prefix("CH").unwrap_or(&upper).trim_start();
+        let (number, band) = if let Some(number) = number.strip_suffix('X') {
+            (number, Some(TacanBand::X))
+        } else if let Some(number) = number.strip_suffix('Y') {
+            (number, Some(TacanBand::Y))
+        } else {
+            (number, None)
+        };
+        ensure!(
+            !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()),
+            InvalidChannelSnafu { input }
+        );
+        if let Some(band) = band {
+            number
+                .parse()
+                .ok()
+                .and_then(|number| Self::tacan(number, band))
+                .context(InvalidChannelSnafu { input })
+        } else {
+            let number: u32 =
+                number.parse().ok().context(InvalidChannelSnafu { input })?;
+            ensure!(
+                (20_000..=99_999).contains(&number),
+                InvalidChannelSnafu { input }
+            );
+            Ok(Self::Approach(number))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::radio::{
+        Channel, ChannelSpacing, LfFrequency, TacanBand, VhfFrequency,
+    };
+
+    #[test]
+    fn parse_and_display() {
+        for (input, khz, shown) in [
+            ("113.90", 113_900, "113.90"),
+            ("113.9", 113_900, "113.90"),
+            ("118.005", 118_005, "118.005"),
+            ("121.5", 121_500, "121.50"),
+            ("132.025", 132_025, "132.025"),
+        ] {
+            let freq: VhfFrequency = input.parse().unwrap();
+            assert_eq!(freq.khz(), khz);
+            assert_eq!(freq.to_string(), shown);
+        }
+        assert_eq!(VhfFrequency::from_10khz(11030).to_string(), "110.30");
+        assert!(VhfFrequency::from_10khz(11030).is_nav());
+        assert!("118.020".parse::<VhfFrequency>().is_err());
+        assert!("99.5".parse::<VhfFrequency>().is_err());
+        assert!("118.0.0".parse::<VhfFrequency>().is_err());
+        assert!("118.0001".parse::<VhfFrequency>().is_err());
+
+        let ndb: LfFrequency = "350".parse().unwrap();
+        assert_eq!(ndb, LfFrequency::from_khz(350));
+        assert_eq!(ndb.to_string(), "350");
+        assert!("35".parse::<LfFrequency>().is_err());
+
+        let tacan: Channel = "CH 58X".parse().unwrap();
+        assert_eq!(
+            tacan,
+            Channel::Tacan {
+                number: 58,
+                band: TacanBand::X
+            }
+        );
+        assert_eq!(tacan.to_string(), "CH 58X");
+        assert_eq!("17y".parse::<Channel>().unwrap().to_string(), "CH 17Y");
+        assert_eq!(
+            "21234".parse::<Channel>().unwrap(),
+            Channel::Approach(21234)
+        );
+        assert!("CH 127X".parse::<Channel>().is_err());
+        assert!("CH 0Y".parse::<Channel>().is_err());
+        assert!("123".parse::<Channel>().is_err());
+    }
+
+    #[test]
+    fn spacing() {
+        let freq = |khz| VhfFrequency::from_khz(khz);
+        assert_eq!(freq(118_025).spacing(), Some(ChannelSpacing::Khz25));
+        assert_eq!(freq(118_010).spacing(), Some(ChannelSpacing::Khz8_33));
+        assert_eq!(freq(118_020).spacing(), None);
+        assert_eq!(freq(118_001).spacing(), None);
+        assert!(freq(118_000).is_on_channel(ChannelSpacing::Khz8_33));
+        assert!(!freq(118_015).is_on_channel(ChannelSpacing::Khz25));
+        assert!(freq(113_900) < freq(118_005));
+    }
+}
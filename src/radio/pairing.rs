@@ -0,0 +1,147 @@
+// SPDX-FileCopyrightText: 2024 Julia DeMille <me@jdemille.com>
+//
+// SPDX-License-Identifier: Parity-7.0.0
+
+//! The pairing of DME and TACAN channels with VHF frequencies, per ICAO Annex 10,
+//! Volume I, Table A, and of GBAS channels with VDB frequencies.
+//!
+//! Every channel from 1X to 126Y is paired with a frequency, in 100 kHz steps, with the
+//! Y band 50 kHz above the X band:
+//!
+//! | Channels  | X band            | Y band            |
+//! |-----------|-------------------|-------------------|
+//! | 1 to 16   | 134.40–135.90 MHz | 134.45–135.95 MHz |
+//! | 17 to 59  | 108.00–112.20 MHz | 108.05–112.25 MHz |
+//! | 60 to 69  | 133.30–134.20 MHz | 133.35–134.25 MHz |
+//! | 70 to 126 | 112.30–117.90 MHz | 112.35–117.95 MHz |
+//!
+//! Only channels 17 to 59 and 70 to 126 are paired with VOR and ILS frequencies. The
+//! frequencies of the others are in the VHF communication band, and are not used for
+//! navigation, so they only identify the channel.
+
+use crate::radio::{Channel, TacanBand, VhfFrequency};
+
+/// The blocks of paired channels, as (first channel, last channel, kHz of the first
+/// channel in the X band).
+const TACAN_BLOCKS: [(u8, u8, u32); 4] = [
+    (1, 16, 134_400),
+    (17, 59, 108_000),
+    (60, 69, 133_300),
+    (70, 126, 112_300),
+];
+
+/// The number of GBAS channels for each RPDS or RSDS.
+const GBAS_CHANNELS_PER_SELECTOR: u32 = 411;
+
+impl Channel {
+    #[must_use]
+    /// The VHF frequency paired with this channel: the VOR or localizer frequency of
+    /// a DME or TACAN channel, or the VDB frequency of a GBAS channel. [`None`] if
+    /// there is no paired frequency.
+    ///
+    /// Channels 1 to 16 and 60 to 69 are paired with frequencies that are not VOR or
+    /// ILS frequencies; see the [module documentation](self).
+    pub fn paired_frequency(self) -> Option<VhfFrequency> {
+        match self {
+            Self::Tacan { number, band } => {
+                let (first, _, first_khz) = TACAN_BLOCKS
+                    .into_iter()
+                    .find(|(first, last, _)| (*first..=*last).contains(&number))?;
+                let band_offset = match band {
+                    TacanBand::X => 0,
+                    TacanBand::Y => 50,
+                };
+                Some(VhfFrequency::from_khz(
+                    first_khz + u32::from(number - first) * 100 + band_offset,
+                ))
+            },
+            // The channel is 20000 + 40 × (F - 108.0) + 411 × S, with F in MHz and
+            // S the RPDS or RSDS.
+            Self::Approach(number @ 20_001..=39_999) => {
+                let steps = (number - 20_000) % GBAS_CHANNELS_PER_SELECTOR;
+                (1..=398)
+                    .contains(&steps)
+                    .then(|| VhfFrequency::from_khz(108_000 + steps * 25))
+            },
+            Self::Approach(_) => None,
+        }
+    }
+}
+
+impl VhfFrequency {
+    #[must_use]
+    /// The DME or TACAN channel paired with this frequency, or [`None`] if there is
+    /// none. This is usually a VOR or localizer frequency, but the channels 1 to 16
+    /// and 60 to 69 are paired with frequencies from 133.30 to 135.95 MHz.
+    pub fn paired_channel(self) -> Option<Channel> {
+        TACAN_BLOCKS
+            .into_iter()
+            .find_map(|(first, last, first_khz)| {
+                let offset = self.0.checked_sub(first_khz)?;
+                if offset % 50 != 0 {
+                    return None;
+                }
+                let band = if offset % 100 == 0 {
+                    TacanBand::X
+                } else {
+                    TacanBand::Y
+                };
+                let number = u8::try_from(offset / 100).ok()?.checked_add(first)?;
+                (number <= last).then_some(Channel::Tacan { number, band })
+            })
+    }
+}
+
+/// Every DME or TACAN channel, with its paired frequency, in order of channel.
+pub fn tacan_pairings() -> impl Iterator<Item = (Channel, VhfFrequency)> {
+    (1..=Channel::MAX_TACAN)
+        .flat_map(|number| {
+            [TacanBand::X, TacanBand::Y].map(|band| Channel::Tacan { number, band })
+        })
+        .filter_map(|channel| Some((channel, channel.paired_frequency()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::radio::{pairing::tacan_pairings, Channel, VhfFrequency};
+
+    #[test]
+    fn pair_channels_and_frequencies() {
+        for (channel, freq) in [
+            ("CH 1X", "134.40"),
+            ("CH 1Y", "134.45"),
+            ("CH 16X", "135.90"),
+            ("CH 16Y", "135.95"),
+            ("CH 17X", "108.00"),
+            ("CH 17Y", "108.05"),
+            ("CH 40X", "110.30"),
+            ("CH 59Y", "112.25"),
+            ("CH 60X", "133.30"),
+            ("CH 69Y", "134.25"),
+            ("CH 70X", "112.30"),
+            ("CH 115X", "116.80"),
+            ("CH 126Y", "117.95"),
+        ] {
+            let channel: Channel = channel.parse().unwrap();
+            let freq: VhfFrequency = freq.parse().unwrap();
+            assert_eq!(channel.paired_frequency(), Some(freq));
+            assert_eq!(freq.paired_channel(), Some(channel));
+        }
+        // Between the blocks of channels 60 to 69 and 1 to 16.
+        assert_eq!(VhfFrequency::from_khz(134_300).paired_channel(), None);
+        assert_eq!(VhfFrequency::from_khz(112_275).paired_channel(), None);
+        assert_eq!(VhfFrequency::from_khz(118_000).paired_channel(), None);
+
+        let pairings: Vec<_> = tacan_pairings().collect();
+        assert_eq!(pairings.len(), 252);
+        assert!(pairings
+            .iter()
+            .all(|(channel, freq)| freq.paired_channel() == Some(*channel)));
+
+        assert_eq!(
+            Channel::Approach(21234).paired_frequency(),
+            Some(VhfFrequency::from_khz(108_025))
+        );
+        assert_eq!(Channel::Approach(56789).paired_frequency(), None);
+    }
+}